@@ -0,0 +1,220 @@
+/*
+ * Minimal libretro frontend to test the core without RetroArch. It loads the core and
+ * a ROM, runs frames without a window while holding RetroPad buttons, and checks that
+ * a save state restores the exact same frames.
+ *
+ *   cc -o frontend libretro/frontend.c -ldl
+ *   ./frontend target/release/libchip8.so rom.ch8 [frames] [button]
+ */
+#include <dlfcn.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#define RETRO_ENVIRONMENT_SET_PIXEL_FORMAT 10
+#define RETRO_PIXEL_FORMAT_XRGB8888 1
+#define RETRO_DEVICE_JOYPAD 1
+
+struct retro_system_info {
+    const char *library_name;
+    const char *library_version;
+    const char *valid_extensions;
+    bool need_fullpath;
+    bool block_extract;
+};
+
+struct retro_system_av_info {
+    unsigned base_width, base_height, max_width, max_height;
+    float aspect_ratio;
+    double fps, sample_rate;
+};
+
+struct retro_game_info {
+    const char *path;
+    const void *data;
+    size_t size;
+    const char *meta;
+};
+
+static struct {
+    unsigned (*api_version)(void);
+    void (*get_system_info)(struct retro_system_info *);
+    void (*get_system_av_info)(struct retro_system_av_info *);
+    void (*set_environment)(bool (*)(unsigned, void *));
+    void (*set_video_refresh)(void (*)(const void *, unsigned, unsigned, size_t));
+    void (*set_audio_sample_batch)(size_t (*)(const int16_t *, size_t));
+    void (*set_input_poll)(void (*)(void));
+    void (*set_input_state)(int16_t (*)(unsigned, unsigned, unsigned, unsigned));
+    void (*init)(void);
+    void (*deinit)(void);
+    bool (*load_game)(const struct retro_game_info *);
+    void (*unload_game)(void);
+    void (*run)(void);
+    size_t (*serialize_size)(void);
+    bool (*serialize)(void *, size_t);
+    bool (*unserialize)(const void *, size_t);
+} core;
+
+static unsigned pixel_format;
+static unsigned width, height, lit_pixels;
+/* FNV-1a hash of all frames since the last reset, to compare runs */
+static uint64_t video_hash;
+static size_t audio_frames, loud_frames;
+static int held_button = -1;
+
+static bool environment(unsigned command, void *data) {
+    if (command == RETRO_ENVIRONMENT_SET_PIXEL_FORMAT) {
+        pixel_format = *(unsigned *)data;
+        return pixel_format == RETRO_PIXEL_FORMAT_XRGB8888;
+    }
+    return false;
+}
+
+static void video_refresh(const void *data, unsigned frame_width, unsigned frame_height,
+                          size_t pitch) {
+    width = frame_width;
+    height = frame_height;
+    lit_pixels = 0;
+    for (unsigned y = 0; y < height; y++) {
+        const uint32_t *row = (const uint32_t *)((const uint8_t *)data + y * pitch);
+        for (unsigned x = 0; x < width; x++) {
+            lit_pixels += (row[x] & 0xFFFFFF) != 0;
+            video_hash = (video_hash ^ row[x]) * 0x100000001B3ULL;
+        }
+    }
+}
+
+static size_t audio_sample_batch(const int16_t *data, size_t frames) {
+    bool loud = false;
+    for (size_t i = 0; i < frames * 2; i++) {
+        loud |= data[i] != 0;
+    }
+    audio_frames += frames;
+    loud_frames += loud;
+    return frames;
+}
+
+static void input_poll(void) {}
+
+static int16_t input_state(unsigned port, unsigned device, unsigned index, unsigned id) {
+    (void)index;
+    return port == 0 && device == RETRO_DEVICE_JOYPAD && (int)id == held_button;
+}
+
+static void *symbol(void *library, const char *name) {
+    void *function = dlsym(library, name);
+    if (!function) {
+        fprintf(stderr, "Core has no %s\n", name);
+        exit(1);
+    }
+    return function;
+}
+
+static void *read_file(const char *path, size_t *size) {
+    FILE *file = fopen(path, "rb");
+    if (!file) {
+        perror(path);
+        exit(1);
+    }
+    fseek(file, 0, SEEK_END);
+    *size = ftell(file);
+    rewind(file);
+    void *data = malloc(*size);
+    if (fread(data, 1, *size, file) != *size) {
+        perror(path);
+        exit(1);
+    }
+    fclose(file);
+    return data;
+}
+
+static uint64_t run_frames(int frames) {
+    video_hash = 0xCBF29CE484222325ULL;
+    for (int i = 0; i < frames; i++) {
+        core.run();
+    }
+    return video_hash;
+}
+
+int main(int argc, char **argv) {
+    if (argc < 3) {
+        fprintf(stderr, "Usage: %s <core> <rom> [frames] [button]\n", argv[0]);
+        return 2;
+    }
+    int frames = argc > 3 ? atoi(argv[3]) : 600;
+    held_button = argc > 4 ? atoi(argv[4]) : -1;
+
+    void *library = dlopen(argv[1], RTLD_NOW);
+    if (!library) {
+        fprintf(stderr, "%s\n", dlerror());
+        return 1;
+    }
+    *(void **)&core.api_version = symbol(library, "retro_api_version");
+    *(void **)&core.get_system_info = symbol(library, "retro_get_system_info");
+    *(void **)&core.get_system_av_info = symbol(library, "retro_get_system_av_info");
+    *(void **)&core.set_environment = symbol(library, "retro_set_environment");
+    *(void **)&core.set_video_refresh = symbol(library, "retro_set_video_refresh");
+    *(void **)&core.set_audio_sample_batch = symbol(library, "retro_set_audio_sample_batch");
+    *(void **)&core.set_input_poll = symbol(library, "retro_set_input_poll");
+    *(void **)&core.set_input_state = symbol(library, "retro_set_input_state");
+    *(void **)&core.init = symbol(library, "retro_init");
+    *(void **)&core.deinit = symbol(library, "retro_deinit");
+    *(void **)&core.load_game = symbol(library, "retro_load_game");
+    *(void **)&core.unload_game = symbol(library, "retro_unload_game");
+    *(void **)&core.run = symbol(library, "retro_run");
+    *(void **)&core.serialize_size = symbol(library, "retro_serialize_size");
+    *(void **)&core.serialize = symbol(library, "retro_serialize");
+    *(void **)&core.unserialize = symbol(library, "retro_unserialize");
+
+    struct retro_system_info info;
+    core.get_system_info(&info);
+    printf("%s %s (API %u), extensions %s\n", info.library_name, info.library_version,
+           core.api_version(), info.valid_extensions);
+
+    core.set_environment(environment);
+    core.set_video_refresh(video_refresh);
+    core.set_audio_sample_batch(audio_sample_batch);
+    core.set_input_poll(input_poll);
+    core.set_input_state(input_state);
+    core.init();
+
+    struct retro_game_info game = {argv[2], NULL, 0, NULL};
+    game.data = read_file(argv[2], &game.size);
+    if (!core.load_game(&game)) {
+        fprintf(stderr, "Core couldn't load %s\n", argv[2]);
+        return 1;
+    }
+    struct retro_system_av_info av;
+    core.get_system_av_info(&av);
+    printf("%ux%u up to %ux%u, %.0f fps, %.0f Hz\n", av.base_width, av.base_height,
+           av.max_width, av.max_height, av.fps, av.sample_rate);
+
+    run_frames(frames);
+    printf("%d frames: %ux%u display with %u lit pixels, %zu audio frames, %zu frames beeped\n",
+           frames, width, height, lit_pixels, audio_frames, loud_frames);
+
+    size_t size = core.serialize_size();
+    void *state = malloc(size);
+    if (!core.serialize(state, size)) {
+        fprintf(stderr, "serialize failed\n");
+        return 1;
+    }
+    uint64_t first = run_frames(120);
+    if (!core.unserialize(state, size)) {
+        fprintf(stderr, "unserialize failed\n");
+        return 1;
+    }
+    uint64_t second = run_frames(120);
+    if (first != second) {
+        fprintf(stderr, "Frames after loading the %zu byte save state differ\n", size);
+        return 1;
+    }
+    printf("Save state of %zu bytes restores the same frames\n", size);
+
+    core.unload_game();
+    core.deinit();
+    dlclose(library);
+    return 0;
+}
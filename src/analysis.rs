@@ -0,0 +1,442 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::File,
+    io::{self, BufWriter, Write},
+};
+
+use crate::{
+    disassembler::{self, Flow},
+    instruction::Instruction,
+    symbols::Symbols,
+};
+
+/// Address programs are loaded to, and where execution starts
+const PROGRAM_START: u16 = 0x200;
+
+/// How control gets from one basic block to another
+#[derive(Clone, Copy, PartialEq)]
+enum Edge {
+    /// The next instruction, also after a skip that isn't taken or a call that returned
+    Fallthrough,
+    /// A jump, or a skip that is taken
+    Branch,
+    Call,
+}
+
+/// Instructions that are always executed one after another
+struct Block {
+    start: u16,
+    /// Address and opcode of every instruction
+    instructions: Vec<(u16, u16)>,
+    successors: Vec<(u16, Edge)>,
+}
+
+/// Static control flow of a ROM, found by following all jumps, calls, skips and returns
+/// from the start address without running the program
+pub struct Analysis {
+    instructions: BTreeMap<u16, u16>,
+    blocks: BTreeMap<u16, Block>,
+    /// Subroutines called by every subroutine, the program start counts as one
+    calls: BTreeMap<u16, BTreeSet<u16>>,
+    /// Addresses loaded into I, which usually point to sprites or other data
+    data_references: BTreeSet<u16>,
+    /// BNNN instructions with their base address
+    computed_jumps: Vec<(u16, u16)>,
+    /// FX55 and FX33 instructions writing into reachable code, with the written range
+    code_writes: Vec<(u16, u16, u16)>,
+    /// Reachable opcodes the CPU doesn't know
+    invalid: Vec<u16>,
+    /// Control flow targets outside of the ROM
+    outside: BTreeSet<u16>,
+    /// Ranges of ROM bytes no reachable instruction covers
+    unreachable: Vec<(u16, u16)>,
+}
+
+impl Analysis {
+    pub fn of(program: &[u8]) -> Self {
+        let end = PROGRAM_START as usize + program.len();
+        let fetch = |address: u16| {
+            let offset = (address as usize).checked_sub(PROGRAM_START as usize)?;
+            let high = *program.get(offset)?;
+            let low = *program.get(offset + 1)?;
+            Some((high as u16) << 8 | low as u16)
+        };
+
+        let mut analysis = Analysis {
+            instructions: BTreeMap::new(),
+            blocks: BTreeMap::new(),
+            calls: BTreeMap::new(),
+            data_references: BTreeSet::new(),
+            computed_jumps: Vec::new(),
+            code_writes: Vec::new(),
+            invalid: Vec::new(),
+            outside: BTreeSet::new(),
+            unreachable: Vec::new(),
+        };
+        let mut leaders = BTreeSet::from([PROGRAM_START]);
+        let mut functions = BTreeSet::from([PROGRAM_START]);
+        let mut pending = vec![PROGRAM_START];
+        while let Some(address) = pending.pop() {
+            if analysis.instructions.contains_key(&address) {
+                continue;
+            }
+            let Some(instruction) = fetch(address) else {
+                analysis.outside.insert(address);
+                continue;
+            };
+            analysis.instructions.insert(address, instruction);
+            if let Instruction::LoadI(target) = Instruction::decode(instruction) {
+                analysis.data_references.insert(target);
+            }
+            let next = address.wrapping_add(2);
+            match disassembler::flow(instruction) {
+                Flow::Next => pending.push(next),
+                Flow::Skip => {
+                    let skipped = address.wrapping_add(4);
+                    leaders.extend([next, skipped]);
+                    pending.extend([next, skipped]);
+                }
+                Flow::Jump(target) => {
+                    leaders.insert(target);
+                    pending.push(target);
+                }
+                Flow::Call(target) => {
+                    functions.insert(target);
+                    leaders.extend([target, next]);
+                    pending.extend([target, next]);
+                }
+                Flow::ComputedJump(base) => analysis.computed_jumps.push((address, base)),
+                Flow::Invalid => analysis.invalid.push(address),
+                Flow::Return => (),
+            }
+        }
+
+        for &leader in &leaders {
+            if analysis.instructions.contains_key(&leader) {
+                let block = analysis.build_block(leader, &leaders);
+                analysis.blocks.insert(leader, block);
+            }
+        }
+        for &function in &functions {
+            if analysis.blocks.contains_key(&function) {
+                let callees = analysis.callees(function);
+                analysis.calls.insert(function, callees);
+            }
+        }
+        analysis.find_code_writes();
+        analysis.find_unreachable(end);
+        analysis
+    }
+
+    /// Collect the instructions from `start` up to the next branch or leader
+    fn build_block(&self, start: u16, leaders: &BTreeSet<u16>) -> Block {
+        let mut instructions = Vec::new();
+        let mut address = start;
+        let flow = loop {
+            let instruction = self.instructions[&address];
+            instructions.push((address, instruction));
+            let flow = disassembler::flow(instruction);
+            address = address.wrapping_add(2);
+            if flow != Flow::Next
+                || leaders.contains(&address)
+                || !self.instructions.contains_key(&address)
+            {
+                break flow;
+            }
+        };
+
+        let successors = match flow {
+            Flow::Next => vec![(address, Edge::Fallthrough)],
+            Flow::Skip => vec![
+                (address, Edge::Fallthrough),
+                (address.wrapping_add(2), Edge::Branch),
+            ],
+            Flow::Jump(target) => vec![(target, Edge::Branch)],
+            Flow::Call(target) => vec![(target, Edge::Call), (address, Edge::Fallthrough)],
+            Flow::Return | Flow::ComputedJump(_) | Flow::Invalid => Vec::new(),
+        };
+        Block {
+            start,
+            instructions,
+            successors: successors
+                .into_iter()
+                .filter(|(target, _)| self.instructions.contains_key(target))
+                .collect(),
+        }
+    }
+
+    /// Subroutines called from the blocks reachable from `function` without calls
+    fn callees(&self, function: u16) -> BTreeSet<u16> {
+        let mut callees = BTreeSet::new();
+        let mut visited = BTreeSet::new();
+        let mut pending = vec![function];
+        while let Some(start) = pending.pop() {
+            if !visited.insert(start) {
+                continue;
+            }
+            let Some(block) = self.blocks.get(&start) else {
+                continue;
+            };
+            for &(target, edge) in &block.successors {
+                match edge {
+                    Edge::Call => {
+                        callees.insert(target);
+                    }
+                    _ => pending.push(target),
+                }
+            }
+        }
+        callees
+    }
+
+    /// Follow I through every block to find FX55 and FX33 writes into reachable code.
+    /// I is only known after an ANNN in the same block.
+    fn find_code_writes(&mut self) {
+        let code: BTreeSet<u16> = self
+            .instructions
+            .keys()
+            .flat_map(|&address| [address, address.wrapping_add(1)])
+            .collect();
+        for block in self.blocks.values() {
+            let mut i_register = None;
+            for &(address, instruction) in &block.instructions {
+                match Instruction::decode(instruction) {
+                    Instruction::LoadI(target) => i_register = Some(target),
+                    Instruction::AddToI(_) => i_register = None,
+                    decoded @ (Instruction::StoreDecimal(x) | Instruction::StoreRegisters(x)) => {
+                        let stores_registers = matches!(decoded, Instruction::StoreRegisters(_));
+                        let length = if stores_registers { x as u16 + 1 } else { 3 };
+                        if let Some(start) = i_register {
+                            let end = start + length - 1;
+                            if (start..=end).any(|byte| code.contains(&byte)) {
+                                self.code_writes.push((address, start, end));
+                            }
+                        }
+                        if stores_registers {
+                            i_register = i_register.map(|i| i + length);
+                        }
+                    }
+                    Instruction::LoadRegisters(x) => {
+                        i_register = i_register.map(|i| i + x as u16 + 1)
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    fn find_unreachable(&mut self, end: usize) {
+        let mut range: Option<(u16, u16)> = None;
+        for address in PROGRAM_START as usize..end {
+            let address = address as u16;
+            let covered = self.instructions.contains_key(&address)
+                || self.instructions.contains_key(&address.wrapping_sub(1));
+            match (covered, &mut range) {
+                (false, Some((_, last))) => *last = address,
+                (false, None) => range = Some((address, address)),
+                (true, Some(_)) => self.unreachable.extend(range.take()),
+                (true, None) => (),
+            }
+        }
+        self.unreachable.extend(range);
+    }
+
+    /// Write the findings as plain text
+    pub fn write_report(&self, out: &mut impl Write, symbols: &Symbols) -> io::Result<()> {
+        writeln!(
+            out,
+            "{} reachable instructions in {} basic blocks",
+            self.instructions.len(),
+            self.blocks.len()
+        )?;
+
+        writeln!(out, "\nCall graph:")?;
+        for (function, callees) in &self.calls {
+            let callees: Vec<String> = callees.iter().map(|&c| name(symbols, c)).collect();
+            let callees = if callees.is_empty() {
+                String::from("-")
+            } else {
+                callees.join(", ")
+            };
+            writeln!(out, "  {} calls {}", name(symbols, *function), callees)?;
+        }
+
+        if !self.computed_jumps.is_empty() {
+            writeln!(out, "\nComputed jumps, targets depend on V0:")?;
+            for (address, base) in &self.computed_jumps {
+                writeln!(out, "  {:04X} JP V0, 0x{:03X}", address, base)?;
+            }
+        }
+        if !self.code_writes.is_empty() {
+            writeln!(out, "\nSelf-modifying writes into code:")?;
+            for (address, start, end) in &self.code_writes {
+                let instruction = self.instructions[address];
+                writeln!(
+                    out,
+                    "  {} {} writes {:04X}-{:04X}",
+                    symbols.describe(*address),
+                    disassembler::disassemble_with_symbols(instruction, symbols),
+                    start,
+                    end
+                )?;
+            }
+        }
+        if !self.invalid.is_empty() {
+            writeln!(out, "\nUnknown opcodes that can be reached:")?;
+            for address in &self.invalid {
+                writeln!(out, "  {:04X} {:04X}", address, self.instructions[address])?;
+            }
+        }
+        if !self.outside.is_empty() {
+            writeln!(out, "\nControl flow leaving the ROM:")?;
+            for address in &self.outside {
+                writeln!(out, "  {:04X}", address)?;
+            }
+        }
+        if !self.unreachable.is_empty() {
+            writeln!(out, "\nUnreachable bytes:")?;
+            for &(start, end) in &self.unreachable {
+                let referenced = self.data_references.range(start..=end).next().is_some();
+                writeln!(
+                    out,
+                    "  {:04X}-{:04X} {:>5} bytes{}",
+                    start,
+                    end,
+                    end - start + 1,
+                    if referenced {
+                        ", data loaded into I"
+                    } else {
+                        ""
+                    }
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the control flow graph in Graphviz DOT format. Blocks list their disassembly,
+    /// subroutine entries have a double border and calls are drawn dashed.
+    pub fn write_dot(&self, out: &mut impl Write, symbols: &Symbols) -> io::Result<()> {
+        writeln!(out, "digraph rom {{")?;
+        writeln!(out, "    node [shape=box, fontname=\"monospace\"];")?;
+        for block in self.blocks.values() {
+            let mut label = String::new();
+            if let Some(name) = symbols.name(block.start) {
+                label += &format!("{}:\\l", name);
+            }
+            for &(address, instruction) in &block.instructions {
+                label += &format!(
+                    "{:04X}  {}\\l",
+                    address,
+                    disassembler::disassemble_with_symbols(instruction, symbols)
+                );
+            }
+            let border = if self.calls.contains_key(&block.start) {
+                ", peripheries=2"
+            } else {
+                ""
+            };
+            writeln!(
+                out,
+                "    b{:04X} [label=\"{}\"{}];",
+                block.start, label, border
+            )?;
+        }
+        for block in self.blocks.values() {
+            for &(target, edge) in &block.successors {
+                let style = match edge {
+                    Edge::Fallthrough => " [color=gray]",
+                    Edge::Branch => "",
+                    Edge::Call => " [style=dashed, color=blue, label=\"call\"]",
+                };
+                writeln!(out, "    b{:04X} -> b{:04X}{};", block.start, target, style)?;
+            }
+        }
+        writeln!(out, "}}")
+    }
+}
+
+/// Symbol name at `address`, or the address in hex
+fn name(symbols: &Symbols, address: u16) -> String {
+    symbols
+        .name(address)
+        .map_or_else(|| format!("{:04X}", address), String::from)
+}
+
+/// Analyze `program`, print the report and write the graph to `dot_path`
+pub fn run(program: &[u8], symbols: &Symbols, dot_path: &str) -> io::Result<()> {
+    let analysis = Analysis::of(program);
+    analysis.write_report(&mut io::stdout(), symbols)?;
+    let mut out = BufWriter::new(File::create(dot_path)?);
+    analysis.write_dot(&mut out, symbols)?;
+    out.flush()?;
+    println!("\nSaved control flow graph to {}", dot_path);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Jumps over a sprite, calls a subroutine and loops forever
+    const PROGRAM: [u8; 12] = [
+        0x12, 0x04, // 200 JP 204
+        0xF0, 0x90, // 202 sprite data
+        0xA2, 0x02, // 204 LD I 202
+        0x22, 0x0A, // 206 CALL 20A
+        0x12, 0x08, // 208 JP 208
+        0x00, 0xEE, // 20A RET
+    ];
+
+    #[test]
+    fn data_between_code_is_unreachable() {
+        let analysis = Analysis::of(&PROGRAM);
+        let reached: Vec<u16> = analysis.instructions.keys().copied().collect();
+        assert_eq!(reached, [0x200, 0x204, 0x206, 0x208, 0x20A]);
+        assert_eq!(analysis.unreachable, [(0x202, 0x203)]);
+        assert!(analysis.data_references.contains(&0x202));
+        assert!(analysis.outside.is_empty());
+        assert!(analysis.invalid.is_empty());
+    }
+
+    #[test]
+    fn jump_and_call_targets_start_labelled_blocks() {
+        let analysis = Analysis::of(&PROGRAM);
+        let blocks: Vec<u16> = analysis.blocks.keys().copied().collect();
+        assert_eq!(blocks, [0x200, 0x204, 0x208, 0x20A]);
+        assert_eq!(analysis.calls[&0x200], BTreeSet::from([0x20A]));
+        assert!(analysis.calls[&0x20A].is_empty());
+
+        let mut symbols = Symbols::default();
+        symbols.insert(0x20A, "draw");
+        let mut dot = Vec::new();
+        analysis.write_dot(&mut dot, &symbols).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.contains("b020A [label=\"draw:\\l020A  RET\\l\", peripheries=2];"));
+        assert!(dot.contains("0206  CALL draw\\l"));
+        assert!(dot.contains("b0204 -> b020A [style=dashed"));
+        assert!(dot.contains("b0208 -> b0208;"));
+    }
+
+    #[test]
+    fn code_writes_are_only_found_with_i_set_in_the_same_block() {
+        let same_block = [
+            0xA2, 0x00, // 200 LD I 200
+            0xF1, 0x55, // 202 LD [I] V1
+            0x12, 0x04, // 204 JP 204
+        ];
+        assert_eq!(
+            Analysis::of(&same_block).code_writes,
+            [(0x202, 0x200, 0x201)]
+        );
+
+        // I is unknown after the jump, so the write is missed
+        let other_block = [
+            0xA2, 0x00, // 200 LD I 200
+            0x12, 0x04, // 202 JP 204
+            0xF1, 0x55, // 204 LD [I] V1
+            0x12, 0x06, // 206 JP 206
+        ];
+        assert!(Analysis::of(&other_block).code_writes.is_empty());
+    }
+}
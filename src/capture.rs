@@ -0,0 +1,148 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter},
+};
+
+use crate::{palette::Palette, screenshot};
+
+/// Hundredths of a second are the unit of GIF frame delays, one 60 Hz frame is 5/3 of them
+const GIF_DELAY_PER_FRAME_NUMERATOR: u32 = 5;
+const GIF_DELAY_PER_FRAME_DENOMINATOR: u32 = 3;
+
+enum Output {
+    /// Animated GIF, identical consecutive frames are merged into one with a longer delay
+    Gif(gif::Encoder<BufWriter<File>>),
+    /// One PNG per 60 Hz frame, numbered so it can be muxed into a video offline,
+    /// e.g. `ffmpeg -framerate 60 -i frame_%06d.png capture.mp4`
+    PngSequence(String),
+}
+
+/// Records the frames of a session. The size of the capture is fixed when it is created,
+/// frames of a different display resolution are scaled to fit.
+pub struct Recorder {
+    output: Output,
+    width: usize,
+    height: usize,
+    palette: Palette,
+    /// Last frame that has not been written yet, scaled to the capture size
+    pending: Option<Vec<bool>>,
+    pending_frames: u32,
+    delay_remainder: u32,
+    frames_written: u64,
+}
+
+impl Recorder {
+    /// Start a capture at `path`: a `.gif` file, or otherwise a directory for a PNG sequence.
+    /// `width` and `height` are the display size, `scale` the size of a CHIP-8 pixel.
+    pub fn create(
+        path: &str,
+        width: usize,
+        height: usize,
+        scale: usize,
+        palette: &Palette,
+    ) -> io::Result<Self> {
+        let scale = scale.max(1);
+        let (width, height) = (width * scale, height * scale);
+        let output = if path.to_ascii_lowercase().ends_with(".gif") {
+            let mut colors = Vec::new();
+            for color in [palette.background(), palette.foreground()] {
+                colors.extend_from_slice(&[(color >> 16) as u8, (color >> 8) as u8, color as u8]);
+            }
+            let file = BufWriter::new(File::create(path)?);
+            let mut encoder = gif::Encoder::new(file, width as u16, height as u16, &colors)
+                .map_err(io::Error::other)?;
+            encoder
+                .set_repeat(gif::Repeat::Infinite)
+                .map_err(io::Error::other)?;
+            Output::Gif(encoder)
+        } else {
+            fs::create_dir_all(path)?;
+            Output::PngSequence(String::from(path.trim_end_matches('/')))
+        };
+        Ok(Self {
+            output,
+            width,
+            height,
+            palette: *palette,
+            pending: None,
+            pending_frames: 0,
+            delay_remainder: 0,
+            frames_written: 0,
+        })
+    }
+
+    /// Add the frame shown during the next `frames` 60 Hz frames
+    pub fn add_frame(
+        &mut self,
+        frame_buffer: &[bool],
+        width: usize,
+        height: usize,
+        frames: u32,
+    ) -> io::Result<()> {
+        if frames == 0 {
+            return Ok(());
+        }
+        let frame = self.scale_to_capture(frame_buffer, width, height);
+        if let Output::PngSequence(directory) = &self.output {
+            for _ in 0..frames {
+                let path = format!("{}/frame_{:06}.png", directory, self.frames_written);
+                screenshot::save_png(&path, &frame, self.width, self.height, 1, &self.palette)?;
+                self.frames_written += 1;
+            }
+            return Ok(());
+        }
+
+        if self.pending.as_ref() == Some(&frame) {
+            self.pending_frames += frames;
+            return Ok(());
+        }
+        self.write_pending()?;
+        self.pending = Some(frame);
+        self.pending_frames = frames;
+        Ok(())
+    }
+
+    /// Write the remaining frame and close the capture
+    pub fn finish(mut self) -> io::Result<u64> {
+        self.write_pending()?;
+        if let Output::Gif(encoder) = self.output {
+            encoder.into_inner()?;
+        }
+        Ok(self.frames_written)
+    }
+
+    fn write_pending(&mut self) -> io::Result<()> {
+        let (Output::Gif(encoder), Some(frame)) = (&mut self.output, self.pending.take()) else {
+            return Ok(());
+        };
+        // Carry the rounding error over to the next frame so the total length stays exact
+        let total = self.pending_frames * GIF_DELAY_PER_FRAME_NUMERATOR + self.delay_remainder;
+        let delay = total / GIF_DELAY_PER_FRAME_DENOMINATOR;
+        self.delay_remainder = total % GIF_DELAY_PER_FRAME_DENOMINATOR;
+
+        let indices: Vec<u8> = frame.iter().map(|&lit| lit as u8).collect();
+        let gif_frame = gif::Frame {
+            width: self.width as u16,
+            height: self.height as u16,
+            delay: delay.min(u16::MAX as u32) as u16,
+            buffer: indices.into(),
+            ..Default::default()
+        };
+        encoder.write_frame(&gif_frame).map_err(io::Error::other)?;
+        self.frames_written += 1;
+        Ok(())
+    }
+
+    /// Nearest neighbour scaling of a frame buffer to the size of the capture
+    fn scale_to_capture(&self, frame_buffer: &[bool], width: usize, height: usize) -> Vec<bool> {
+        let mut frame = Vec::with_capacity(self.width * self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let source_x = x * width / self.width;
+                let source_y = y * height / self.height;
+                frame.push(frame_buffer[source_y * width + source_x]);
+            }
+        }
+        frame
+    }
+}
@@ -0,0 +1,259 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+use crate::cpu::CPU;
+
+/// How the candidates of a search are narrowed down, comparing the current memory with
+/// the snapshot of the last search step
+#[derive(Clone, Copy)]
+pub enum Comparison {
+    Equal(u8),
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+}
+
+impl Comparison {
+    fn matches(&self, old: u8, new: u8) -> bool {
+        match *self {
+            Comparison::Equal(value) => new == value,
+            Comparison::Changed => new != old,
+            Comparison::Unchanged => new == old,
+            Comparison::Increased => new > old,
+            Comparison::Decreased => new < old,
+        }
+    }
+}
+
+/// Classic cheat finder: start with every address of the memory as candidate, then
+/// keep only those whose value behaves like the one looked for, e.g. decreased after
+/// losing a life
+pub struct CheatSearch {
+    snapshot: Vec<u8>,
+    candidates: Vec<u16>,
+}
+
+impl CheatSearch {
+    pub fn new(memory: &[u8]) -> Self {
+        Self {
+            snapshot: memory.to_vec(),
+            candidates: (0..memory.len() as u16).collect(),
+        }
+    }
+
+    /// Drop the candidates that don't match, then take a new snapshot
+    pub fn filter(&mut self, memory: &[u8], comparison: Comparison) {
+        let snapshot = &self.snapshot;
+        self.candidates.retain(|&address| {
+            let address = address as usize;
+            comparison.matches(snapshot[address], memory[address])
+        });
+        self.snapshot = memory.to_vec();
+    }
+
+    pub fn candidates(&self) -> &[u16] {
+        &self.candidates
+    }
+}
+
+/// A memory location set to a fixed value
+pub struct Cheat {
+    pub address: u16,
+    pub value: u8,
+    /// Frozen cheats are written every frame, the others only once
+    pub frozen: bool,
+    pub name: String,
+}
+
+/// The cheats of one ROM, stored in a file named after the hash of the ROM in the
+/// cheat directory. Lines have the hex address and value, `freeze` or `poke` and an
+/// optional name, e.g. `03F0 09 freeze lives`. Lines starting with `#` are comments.
+pub struct Cheats {
+    path: String,
+    cheats: Vec<Cheat>,
+    /// Whether the pokes still have to be written
+    poke_pending: bool,
+}
+
+impl Cheats {
+    /// Load the cheats of `program` from `directory`, there are none if the ROM has no
+    /// cheat file yet
+    pub fn load(directory: &str, program: &[u8]) -> io::Result<Self> {
+        let path = Path::new(directory).join(format!("{:016x}.txt", fnv1a(program)));
+        let path = path.to_string_lossy().into_owned();
+        let mut cheats = Cheats {
+            path,
+            cheats: Vec::new(),
+            poke_pending: true,
+        };
+        if !Path::new(&cheats.path).exists() {
+            return Ok(cheats);
+        }
+        let contents = fs::read_to_string(&cheats.path)?;
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match Cheats::parse_line(line) {
+                Some(cheat) => cheats.cheats.push(cheat),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "{}:{}: expected `<address> <value> freeze|poke [name]`",
+                            cheats.path,
+                            number + 1
+                        ),
+                    ))
+                }
+            }
+        }
+        Ok(cheats)
+    }
+
+    fn parse_line(line: &str) -> Option<Cheat> {
+        let mut parts = line.splitn(4, char::is_whitespace);
+        let address = u16::from_str_radix(parts.next()?, 16).ok()?;
+        let value = u8::from_str_radix(parts.next()?, 16).ok()?;
+        let frozen = match parts.next()? {
+            "freeze" => true,
+            "poke" => false,
+            _ => return None,
+        };
+        let name = String::from(parts.next().unwrap_or("").trim());
+        Some(Cheat {
+            address,
+            value,
+            frozen,
+            name,
+        })
+    }
+
+    /// The cheats of another version of the ROM from the same directory, e.g. after the
+    /// ROM was reloaded
+    pub fn for_program(&self, program: &[u8]) -> io::Result<Self> {
+        let directory = Path::new(&self.path).parent().unwrap_or(Path::new(""));
+        Cheats::load(&directory.to_string_lossy(), program)
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    /// Add a cheat, replacing one at the same address, and save the file
+    pub fn add(&mut self, cheat: Cheat) -> io::Result<()> {
+        self.cheats.retain(|other| other.address != cheat.address);
+        self.cheats.push(cheat);
+        self.cheats.sort_by_key(|cheat| cheat.address);
+        self.poke_pending = true;
+        self.save()
+    }
+
+    /// Remove the cheat at `address` and save the file, returns false if there is none
+    pub fn remove(&mut self, address: u16) -> io::Result<bool> {
+        let count = self.cheats.len();
+        self.cheats.retain(|cheat| cheat.address != address);
+        if self.cheats.len() == count {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    fn save(&self) -> io::Result<()> {
+        if let Some(directory) = Path::new(&self.path).parent() {
+            fs::create_dir_all(directory)?;
+        }
+        let mut file = fs::File::create(&self.path)?;
+        writeln!(file, "# address value freeze|poke name")?;
+        for cheat in &self.cheats {
+            let line = format!(
+                "{:04X} {:02X} {} {}",
+                cheat.address,
+                cheat.value,
+                if cheat.frozen { "freeze" } else { "poke" },
+                cheat.name
+            );
+            writeln!(file, "{}", line.trim_end())?;
+        }
+        Ok(())
+    }
+
+    /// Write the frozen values, and the pokes if they weren't written yet.
+    /// Called at the start of every frame.
+    pub fn apply(&mut self, cpu: &mut CPU) {
+        for cheat in &self.cheats {
+            if cheat.frozen || self.poke_pending {
+                cpu.write_memory(cheat.address as usize, &[cheat.value]);
+            }
+        }
+        self.poke_pending = false;
+    }
+
+    /// Write the pokes again on the next frame, e.g. after a reset reloaded the program
+    pub fn reset(&mut self) {
+        self.poke_pending = true;
+    }
+}
+
+/// 64-bit FNV-1a hash, identifies a ROM independent of its file name
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn search_narrows_down_to_the_changing_byte() {
+        let mut memory = vec![0u8; 16];
+        memory[3] = 5;
+        memory[9] = 5;
+        let mut search = CheatSearch::new(&memory);
+        assert_eq!(search.candidates().len(), 16);
+
+        search.filter(&memory, Comparison::Equal(5));
+        assert_eq!(search.candidates(), [3, 9]);
+
+        // A life lost: the counter at 9 decreases, the other byte stays
+        memory[9] = 4;
+        memory[0] = 1;
+        search.filter(&memory, Comparison::Decreased);
+        assert_eq!(search.candidates(), [9]);
+
+        // The filter compares with the memory of the last step, not the first
+        search.filter(&memory, Comparison::Unchanged);
+        assert_eq!(search.candidates(), [9]);
+        memory[9] = 6;
+        search.filter(&memory, Comparison::Increased);
+        assert_eq!(search.candidates(), [9]);
+        search.filter(&memory, Comparison::Changed);
+        assert!(search.candidates().is_empty());
+    }
+
+    #[test]
+    fn cheat_lines_have_address_value_mode_and_name() {
+        let cheat = Cheats::parse_line("03F0 09 freeze infinite lives").unwrap();
+        assert_eq!((cheat.address, cheat.value, cheat.frozen), (0x3F0, 9, true));
+        assert_eq!(cheat.name, "infinite lives");
+        let cheat = Cheats::parse_line("0200 FF poke").unwrap();
+        assert_eq!(
+            (cheat.address, cheat.value, cheat.frozen),
+            (0x200, 0xFF, false)
+        );
+        assert!(Cheats::parse_line("0200 FF keep").is_none());
+        assert!(Cheats::parse_line("0200 100 poke").is_none());
+    }
+}
@@ -0,0 +1,87 @@
+use crate::{cpu::CPU, keypad::Keypad, FRAMES_PER_SECOND};
+
+/// Splits the cycles of the CPU into 60 Hz frames. Every frontend runs the machine
+/// through it, so they agree on where a frame starts and when the timers count down.
+///
+/// With `emulate_cycle` and `run_frame` the timers count down once at the start of
+/// every frame after the first, so runs only depend on the input. Frontends with a
+/// clock can pass the time to the CPU instead and only count the cycles with `advance`.
+#[derive(Clone)]
+pub struct FrameClock {
+    instructions_per_second: u32,
+    cycles_per_frame: u64,
+    /// Cycles executed since the last reset
+    cycle: u64,
+}
+
+impl FrameClock {
+    pub fn new(instructions_per_second: u32) -> Self {
+        Self {
+            instructions_per_second,
+            cycles_per_frame: (instructions_per_second / FRAMES_PER_SECOND).max(1) as u64,
+            cycle: 0,
+        }
+    }
+
+    pub fn instructions_per_second(&self) -> u32 {
+        self.instructions_per_second
+    }
+
+    pub fn cycles_per_frame(&self) -> u32 {
+        self.cycles_per_frame as u32
+    }
+
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    /// Continue counting at `cycle`, e.g. from a save state
+    pub fn set_cycle(&mut self, cycle: u64) {
+        self.cycle = cycle;
+    }
+
+    /// Start counting from frame 0 again
+    pub fn reset(&mut self) {
+        self.cycle = 0;
+    }
+
+    /// Number of frames completed since the last reset
+    pub fn frame(&self) -> u64 {
+        self.cycle / self.cycles_per_frame
+    }
+
+    /// Whether the next cycle is the first of a frame
+    pub fn starts_frame(&self) -> bool {
+        self.cycle.is_multiple_of(self.cycles_per_frame)
+    }
+
+    /// Count the timers down if the next cycle starts a frame other than the first.
+    /// Called before the instruction of the cycle.
+    pub fn update_timers(&self, cpu: &mut CPU) {
+        if self.cycle > 0 && self.starts_frame() {
+            cpu.decrease_timers();
+        }
+    }
+
+    /// Count a cycle that was executed
+    pub fn advance(&mut self) {
+        self.cycle += 1;
+    }
+
+    /// Execute one instruction with timers that count frames
+    pub fn emulate_cycle(&mut self, cpu: &mut CPU, keypad: &Keypad) {
+        self.update_timers(cpu);
+        cpu.emulate_cycle(0, keypad);
+        self.advance();
+    }
+
+    /// Execute all instructions up to the end of the current frame
+    pub fn run_frame(&mut self, cpu: &mut CPU, keypad: &Keypad) {
+        loop {
+            self.emulate_cycle(cpu, keypad);
+            if self.starts_frame() {
+                return;
+            }
+        }
+    }
+}
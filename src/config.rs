@@ -0,0 +1,418 @@
+use std::{fs, path::Path, process};
+
+use crate::{
+    palette::{Palette, PRESETS},
+    phosphor::PersistenceMode,
+    trace::TraceFilter,
+};
+
+/// Config file that is read from the working directory if no other one is given
+const DEFAULT_CONFIG_FILE: &str = "chip8.cfg";
+/// Extension of the per-ROM metadata file, which lives next to the ROM: `game.ch8.cfg`
+const ROM_METADATA_EXTENSION: &str = "cfg";
+
+const USAGE: &str = "Usage: chip8 [OPTIONS] [ROM]
+
+Options:
+    --config <FILE>        Read settings from FILE instead of chip8.cfg
+    --palette <NAME>       Color palette preset
+    --background <COLOR>   Background color as RRGGBB
+    --foreground <COLOR>   Foreground color as RRGGBB
+    --pixel-gap <PIXELS>   Empty space between neighbouring pixels
+    --rounded-pixels       Draw pixels with rounded corners
+    --integer-scaling      Only scale the display by whole numbers
+    --fullscreen           Start in fullscreen mode
+    --screenshot-dir <DIR> Directory screenshots are saved to with F12
+    --screenshot-scale <N> Size of a CHIP-8 pixel in screenshots, 1 is the native
+                           resolution and 0 the current window scale
+    --headless             Run without a window
+    --tui                  Run in the terminal instead of a window
+    --tui-charset <SET>    Characters the terminal display is drawn with:
+                           half (half blocks) or braille
+    --gdb <PORT>           Wait for a GDB remote debugger on localhost:PORT and run
+                           under its control, without a window
+    --rpc <PORT>           Accept JSON-RPC requests on localhost:PORT to control the
+                           machine, without a window together with --headless
+    --env <FILE>           Play random episodes in the reinforcement learning
+                           environment described in FILE, without a window
+    --env-batch <N>        Number of environments run in parallel with --env
+    --env-episodes <N>     Number of episodes to play with --env
+    --seed <N>             Seed of the random numbers (CXNN), for reproducible runs
+    --frame-timers         Count the timers down once per frame instead of by the
+                           time the instructions take, for reproducible runs
+    --watch                Reload the ROM whenever its file changes, for ROM development
+    --watch-keep-ram       Keep the memory outside of the program when reloading
+    --assembler <COMMAND>  Command that assembles .8o Octo sources, called with the
+                           source and the output file
+    --analyze <FILE>       Analyze the control flow of the ROM without running it,
+                           print a report and write the graph as Graphviz DOT to FILE
+    --frames <N>           Number of frames to run in headless mode
+    --screenshot <FILE>    Save the last frame as PNG in headless mode
+    --record <PATH>        Capture all frames in headless mode, to a GIF if PATH
+                           ends with .gif, otherwise as PNG sequence into PATH
+    --capture-dir <DIR>    Directory captures are saved to with F10
+    --cheat-dir <DIR>      Directory the cheats of every ROM are stored in,
+                           in a file named after the hash of the ROM
+    --movie <FILE>         Play back the keypad input recorded in FILE
+    --record-movie <FILE>  Record the keypad input to FILE
+    --script <FILE>        Run the Rhai script in FILE, which can hook frames,
+                           instructions and memory writes to automate the machine
+    --symbols <FILE>       Show the label names in FILE instead of addresses, one
+                           `<address> <name>` per line or Octo `:const` lines
+    --trace <FILE>         Log the executed instructions to FILE
+    --trace-range <RANGE>  Only trace instructions at the hex addresses in RANGE,
+                           e.g. 200-2FF
+    --trace-opcodes <LIST> Only trace the opcode families starting with these hex
+                           digits, e.g. D,F
+    --trace-frames <RANGE> Only trace during these frames, e.g. 120-180
+    --trace-ring <N>       Keep the last N instructions in memory and only write
+                           them to the trace file when the CPU panics
+    --profile <FILE>       Count the executed instructions and write a report to FILE
+                           when the run ends, or with F3 in the window
+    --coverage <FILE>      Track executed instructions and data read through I, and
+                           write an annotated listing of the ROM to FILE when the
+                           run ends
+    --coverage-min <PERCENT>
+                           Exit with an error in headless mode if less of the ROM
+                           was covered, tracks coverage without --coverage too
+    --persistence <MODE>   Anti-flicker mode: off, decay or blend
+    --decay <FACTOR>       Share of intensity a pixel keeps per frame in decay mode
+    --blend-frames <N>     Number of frames that are combined in blend mode
+    --help                 Print this text
+
+Every option can also be written as `key = value` in the config file, using the
+option name with underscores. A `<ROM>.cfg` file next to the ROM can set how the
+display looks: the palette, colors, pixel style, scaling and persistence options.
+Settings on the command line take precedence over the ROM file, which takes
+precedence over the config file.";
+
+/// All settings of a run, collected from the defaults, the config file,
+/// the per-ROM metadata file and the command line (in that order)
+pub struct Config {
+    pub rom: String,
+    pub palette: Palette,
+    pub pixel_gap: f32,
+    pub rounded_pixels: bool,
+    pub integer_scaling: bool,
+    pub fullscreen: bool,
+    pub screenshot_dir: String,
+    pub screenshot_scale: usize,
+    pub headless: bool,
+    pub tui: bool,
+    pub tui_charset: String,
+    pub gdb: Option<u16>,
+    pub rpc: Option<u16>,
+    pub env: Option<String>,
+    pub env_batch: usize,
+    pub env_episodes: usize,
+    pub seed: Option<u64>,
+    pub frame_timers: bool,
+    pub watch: bool,
+    pub watch_keep_ram: bool,
+    pub assembler: String,
+    pub analyze: Option<String>,
+    pub frames: Option<u64>,
+    pub screenshot: Option<String>,
+    pub record: Option<String>,
+    pub capture_dir: String,
+    pub cheat_dir: String,
+    pub movie: Option<String>,
+    pub record_movie: Option<String>,
+    pub script: Option<String>,
+    pub symbols: Option<String>,
+    pub trace: Option<String>,
+    pub trace_filter: TraceFilter,
+    pub trace_ring: Option<usize>,
+    pub profile: Option<String>,
+    pub coverage: Option<String>,
+    pub coverage_min: Option<f64>,
+    pub persistence: String,
+    pub decay: f32,
+    pub blend_frames: usize,
+}
+
+impl Config {
+    fn new(rom: &str) -> Self {
+        Self {
+            rom: String::from(rom),
+            palette: Palette::default(),
+            pixel_gap: 0.0,
+            rounded_pixels: false,
+            integer_scaling: false,
+            fullscreen: false,
+            screenshot_dir: String::from("."),
+            screenshot_scale: 0,
+            headless: false,
+            tui: false,
+            tui_charset: String::from("half"),
+            gdb: None,
+            rpc: None,
+            env: None,
+            env_batch: 8,
+            env_episodes: 16,
+            seed: None,
+            frame_timers: false,
+            watch: false,
+            watch_keep_ram: false,
+            assembler: String::from("octo"),
+            analyze: None,
+            frames: None,
+            screenshot: None,
+            record: None,
+            capture_dir: String::from("."),
+            cheat_dir: String::from("cheats"),
+            movie: None,
+            record_movie: None,
+            script: None,
+            symbols: None,
+            trace: None,
+            trace_filter: TraceFilter::default(),
+            trace_ring: None,
+            profile: None,
+            coverage: None,
+            coverage_min: None,
+            persistence: String::from("off"),
+            decay: 0.6,
+            blend_frames: 3,
+        }
+    }
+
+    /// Build the config from the process arguments, exiting with the usage text on bad input
+    pub fn from_args(default_rom: &str) -> Self {
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        let mut options = Vec::new();
+        let mut rom = None;
+        let mut config_file = None;
+
+        let mut iter = args.into_iter();
+        while let Some(arg) = iter.next() {
+            if arg == "--help" || arg == "-h" {
+                println!("{}", USAGE);
+                println!("\nPalettes: {}", PRESETS.join(", "));
+                process::exit(0);
+            }
+            match arg.strip_prefix("--") {
+                Some(name) => {
+                    let key = name.replace('-', "_");
+                    if Config::is_flag(&key) {
+                        options.push((key, String::from("true")));
+                    } else if let Some(value) = iter.next() {
+                        if key == "config" {
+                            config_file = Some(value);
+                        } else {
+                            options.push((key, value));
+                        }
+                    } else {
+                        Config::exit_with_usage(&format!("Missing value for {}", arg));
+                    }
+                }
+                None => rom = Some(arg),
+            }
+        }
+
+        let mut config = Config::new(rom.as_deref().unwrap_or(default_rom));
+        match config_file {
+            Some(path) => config.load_file(&path, true, false),
+            None => config.load_file(DEFAULT_CONFIG_FILE, false, false),
+        }
+        let rom_metadata = format!("{}.{}", config.rom, ROM_METADATA_EXTENSION);
+        config.load_file(&rom_metadata, false, true);
+        for (key, value) in options {
+            if let Err(message) = config.set(&key, &value) {
+                Config::exit_with_usage(&message);
+            }
+        }
+        config
+    }
+
+    /// Apply all `key = value` lines of a file. Lines starting with `#` are comments.
+    /// `rom_metadata` files come with downloaded ROMs, they may only set how the display
+    /// looks, not run commands, open ports or write files.
+    fn load_file(&mut self, path: &str, required: bool, rom_metadata: bool) {
+        if !required && !Path::new(path).exists() {
+            return;
+        }
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(error) => {
+                println!("Couldn't read config file {}: {}", path, error);
+                return;
+            }
+        };
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let result = match line.split_once('=') {
+                Some((key, _)) if rom_metadata && !Config::is_display_option(key.trim()) => Err(
+                    format!("{} can't be set for a ROM, ignoring it", key.trim()),
+                ),
+                Some((key, value)) => self.set(key.trim(), value.trim()),
+                None => Err(String::from("Expected `key = value`")),
+            };
+            if let Err(message) = result {
+                println!("{}:{}: {}", path, number + 1, message);
+            }
+        }
+    }
+
+    /// All persistence modes with the configured parameters, starting with the selected one.
+    /// The window cycles through them at runtime.
+    pub fn persistence_modes(&self) -> Vec<PersistenceMode> {
+        let mut modes = vec![
+            PersistenceMode::Off,
+            PersistenceMode::Decay(self.decay),
+            PersistenceMode::Blend(self.blend_frames),
+        ];
+        let selected = modes
+            .iter()
+            .position(|mode| mode.name().eq_ignore_ascii_case(&self.persistence))
+            .unwrap_or(0);
+        modes.rotate_left(selected);
+        modes
+    }
+
+    fn is_flag(key: &str) -> bool {
+        matches!(
+            key,
+            "rounded_pixels"
+                | "integer_scaling"
+                | "fullscreen"
+                | "headless"
+                | "tui"
+                | "frame_timers"
+                | "watch"
+                | "watch_keep_ram"
+        )
+    }
+
+    /// Options that only change how the display looks, which ROM metadata may set
+    fn is_display_option(key: &str) -> bool {
+        matches!(
+            key,
+            "palette"
+                | "background"
+                | "foreground"
+                | "pixel_gap"
+                | "rounded_pixels"
+                | "integer_scaling"
+                | "persistence"
+                | "decay"
+                | "blend_frames"
+        )
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "palette" => {
+                self.palette =
+                    Palette::preset(value).ok_or_else(|| format!("Unknown palette {}", value))?;
+            }
+            "background" => self.palette.colors[0] = Config::parse_color(value)?,
+            "foreground" => self.palette.colors[1] = Config::parse_color(value)?,
+            "pixel_gap" => {
+                self.pixel_gap = value
+                    .parse()
+                    .map_err(|_| format!("Invalid pixel gap {}", value))?;
+            }
+            "rounded_pixels" => self.rounded_pixels = Config::parse_bool(value)?,
+            "integer_scaling" => self.integer_scaling = Config::parse_bool(value)?,
+            "fullscreen" => self.fullscreen = Config::parse_bool(value)?,
+            "screenshot_dir" => self.screenshot_dir = String::from(value),
+            "screenshot_scale" => self.screenshot_scale = Config::parse_number(value)?,
+            "headless" => self.headless = Config::parse_bool(value)?,
+            "tui" => self.tui = Config::parse_bool(value)?,
+            "tui_charset" => match value {
+                "half" | "braille" => self.tui_charset = String::from(value),
+                _ => return Err(format!("Unknown terminal charset {}", value)),
+            },
+            "gdb" => self.gdb = Some(Config::parse_number(value)?),
+            "rpc" => self.rpc = Some(Config::parse_number(value)?),
+            "env" => self.env = Some(String::from(value)),
+            "env_batch" => self.env_batch = Config::parse_number(value)?,
+            "env_episodes" => self.env_episodes = Config::parse_number(value)?,
+            "seed" => self.seed = Some(Config::parse_number(value)?),
+            "frame_timers" => self.frame_timers = Config::parse_bool(value)?,
+            "watch" => self.watch = Config::parse_bool(value)?,
+            "watch_keep_ram" => self.watch_keep_ram = Config::parse_bool(value)?,
+            "assembler" => self.assembler = String::from(value),
+            "analyze" => self.analyze = Some(String::from(value)),
+            "frames" => self.frames = Some(Config::parse_number(value)?),
+            "screenshot" => self.screenshot = Some(String::from(value)),
+            "record" => self.record = Some(String::from(value)),
+            "capture_dir" => self.capture_dir = String::from(value),
+            "cheat_dir" => self.cheat_dir = String::from(value),
+            "movie" => self.movie = Some(String::from(value)),
+            "record_movie" => self.record_movie = Some(String::from(value)),
+            "script" => self.script = Some(String::from(value)),
+            "symbols" => self.symbols = Some(String::from(value)),
+            "trace" => self.trace = Some(String::from(value)),
+            "trace_range" => {
+                self.trace_filter.addresses = Some(
+                    TraceFilter::parse_addresses(value)
+                        .ok_or_else(|| format!("Invalid address range {}", value))?,
+                );
+            }
+            "trace_opcodes" => {
+                self.trace_filter.families = Some(
+                    TraceFilter::parse_families(value)
+                        .ok_or_else(|| format!("Invalid opcode families {}", value))?,
+                );
+            }
+            "trace_frames" => {
+                self.trace_filter.frames = Some(
+                    TraceFilter::parse_frames(value)
+                        .ok_or_else(|| format!("Invalid frame range {}", value))?,
+                );
+            }
+            "trace_ring" => self.trace_ring = Some(Config::parse_number(value)?),
+            "profile" => self.profile = Some(String::from(value)),
+            "coverage" => self.coverage = Some(String::from(value)),
+            "coverage_min" => self.coverage_min = Some(Config::parse_number(value)?),
+            "persistence" => match value {
+                "off" | "decay" | "blend" => self.persistence = String::from(value),
+                _ => return Err(format!("Unknown persistence mode {}", value)),
+            },
+            "decay" => {
+                self.decay = value
+                    .parse()
+                    .ok()
+                    .filter(|decay| (0.0..1.0).contains(decay))
+                    .ok_or_else(|| format!("Decay must be between 0 and 1, got {}", value))?;
+            }
+            "blend_frames" => {
+                self.blend_frames = value
+                    .parse()
+                    .ok()
+                    .filter(|&frames| frames > 0)
+                    .ok_or_else(|| format!("Invalid number of blend frames {}", value))?;
+            }
+            _ => return Err(format!("Unknown option {}", key)),
+        }
+        Ok(())
+    }
+
+    fn parse_color(value: &str) -> Result<u32, String> {
+        Palette::parse_color(value).ok_or_else(|| format!("Invalid color {}", value))
+    }
+
+    fn parse_number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+        value
+            .parse()
+            .map_err(|_| format!("Invalid number {}", value))
+    }
+
+    fn parse_bool(value: &str) -> Result<bool, String> {
+        match value {
+            "true" | "yes" | "on" | "1" => Ok(true),
+            "false" | "no" | "off" | "0" => Ok(false),
+            _ => Err(format!("Invalid boolean {}", value)),
+        }
+    }
+
+    fn exit_with_usage(message: &str) -> ! {
+        println!("{}\n\n{}", message, USAGE);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,165 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+};
+
+use crate::{cpu::CPU, disassembler, symbols::Symbols};
+
+/// Address programs are loaded to
+const PROGRAM_START: usize = 0x200;
+/// Bytes per line of data in the listing
+const DATA_LINE_BYTES: usize = 8;
+
+/// Remembers which addresses were executed as instructions and which bytes were read
+/// as data through I, by sprites (DXYN) and register loads (FX65)
+pub struct Coverage {
+    executed: Vec<bool>,
+    read: Vec<bool>,
+}
+
+/// Share of the program bytes that were executed or read
+pub struct Summary {
+    pub program_bytes: usize,
+    pub code_bytes: usize,
+    pub data_bytes: usize,
+}
+
+impl Summary {
+    pub fn percent(&self) -> f64 {
+        (self.code_bytes + self.data_bytes) as f64 * 100.0 / self.program_bytes.max(1) as f64
+    }
+}
+
+impl Coverage {
+    pub fn new(memory_size: usize) -> Self {
+        Self {
+            executed: vec![false; memory_size],
+            read: vec![false; memory_size],
+        }
+    }
+
+    pub fn record_execution(&mut self, address: u16) {
+        if let Some(executed) = self.executed.get_mut(address as usize) {
+            *executed = true;
+        }
+    }
+
+    pub fn record_read(&mut self, address: u16, length: usize) {
+        let start = (address as usize).min(self.read.len());
+        let end = (start + length).min(self.read.len());
+        self.read[start..end].fill(true);
+    }
+
+    /// Whether the byte at `address` belongs to an executed instruction
+    fn is_code(&self, address: usize) -> bool {
+        self.executed[address] || address > 0 && self.executed[address - 1]
+    }
+
+    pub fn summary(&self, program_length: usize) -> Summary {
+        let end = (PROGRAM_START + program_length).min(self.executed.len());
+        let mut summary = Summary {
+            program_bytes: end - PROGRAM_START,
+            code_bytes: 0,
+            data_bytes: 0,
+        };
+        for address in PROGRAM_START..end {
+            if self.is_code(address) {
+                summary.code_bytes += 1;
+            } else if self.read[address] {
+                summary.data_bytes += 1;
+            }
+        }
+        summary
+    }
+
+    /// Write the program as annotated listing: executed instructions marked `+`, bytes read
+    /// as data marked `d`, and bytes that were never reached marked `-`, disassembled as if
+    /// they were code so unreached branches can be recognized. Symbols start a `name:` line.
+    pub fn write_report(
+        &self,
+        path: &str,
+        cpu: &CPU,
+        symbols: &Symbols,
+        program_length: usize,
+    ) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        let summary = self.summary(program_length);
+        writeln!(
+            out,
+            "Coverage: {:.1}% of {} bytes, {} bytes code, {} bytes data, {} bytes never reached",
+            summary.percent(),
+            summary.program_bytes,
+            summary.code_bytes,
+            summary.data_bytes,
+            summary.program_bytes - summary.code_bytes - summary.data_bytes
+        )?;
+        writeln!(out)?;
+
+        let memory = cpu.memory();
+        let end = PROGRAM_START + summary.program_bytes;
+        let mut address = PROGRAM_START;
+        while address < end {
+            if let Some(name) = symbols.name(address as u16) {
+                writeln!(out, "{}:", name)?;
+            }
+            if self.executed[address] {
+                let instruction = cpu.instruction_at(address).unwrap_or(0);
+                writeln!(
+                    out,
+                    "+ {:04X}  {:04X}  {}",
+                    address,
+                    instruction,
+                    disassembler::disassemble_with_symbols(instruction, symbols)
+                )?;
+                address += 2;
+            } else if self.read[address] {
+                let length = (address..end.min(address + DATA_LINE_BYTES))
+                    .take_while(|&next| {
+                        self.read[next]
+                            && !self.executed[next]
+                            && (next == address || symbols.name(next as u16).is_none())
+                    })
+                    .count();
+                let bytes: Vec<String> = memory[address..address + length]
+                    .iter()
+                    .map(|byte| format!("{:02X}", byte))
+                    .collect();
+                writeln!(out, "d {:04X}  {}", address, bytes.join(" "))?;
+                address += length;
+            } else if address + 1 < end && !self.is_code(address + 1) && !self.read[address + 1] {
+                let instruction = cpu.instruction_at(address).unwrap_or(0);
+                writeln!(
+                    out,
+                    "- {:04X}  {:04X}  {}",
+                    address,
+                    instruction,
+                    disassembler::disassemble_with_symbols(instruction, symbols)
+                )?;
+                address += 2;
+            } else {
+                writeln!(out, "- {:04X}  {:02X}", address, memory[address])?;
+                address += 1;
+            }
+        }
+        out.flush()
+    }
+}
+
+/// Write the coverage report of `cpu` to `path`, if it tracks coverage, print the outcome
+/// and return the summary. Used by the frontends when they finish.
+pub fn save_report(
+    cpu: &CPU,
+    symbols: &Symbols,
+    program_length: usize,
+    path: &str,
+) -> Option<Summary> {
+    let coverage = cpu.coverage.as_ref()?;
+    if let Err(error) = coverage.write_report(path, cpu, symbols, program_length) {
+        println!("Couldn't save coverage to {}: {}", path, error);
+        return None;
+    }
+    println!("Saved coverage to {}", path);
+    let summary = coverage.summary(program_length);
+    println!("Coverage: {:.1}%", summary.percent());
+    Some(summary)
+}
@@ -1,5 +1,42 @@
-use crate::keypad::{self, Keypad};
-use std::time::Duration;
+#[cfg(feature = "std")]
+use crate::{coverage::Coverage, profiler::Profiler};
+use crate::{
+    instruction::Instruction,
+    keypad::{self, Keypad},
+    state::CpuState,
+};
+use alloc::{boxed::Box, format, string::String, vec, vec::Vec};
+use core::fmt::Write;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+
+/// The timers count down at 60 Hz
+const TIMER_PERIOD_NANOS: u64 = 17_000_000;
+
+/// Where the CPU writes its messages: why it panicked, memory dumps and the detailed
+/// instruction log. With the `std` feature they go to stdout, targets without one like
+/// microcontrollers can show them elsewhere with `CPU::set_console`.
+pub trait Console: Send + Sync {
+    fn write_line(&mut self, line: &str);
+}
+
+/// Prints the messages of the CPU to stdout
+#[cfg(feature = "std")]
+pub struct Stdout;
+
+#[cfg(feature = "std")]
+impl Console for Stdout {
+    fn write_line(&mut self, line: &str) {
+        println!("{}", line);
+    }
+}
+
+/// Drops the messages of the CPU, the default without the `std` feature
+pub struct NoConsole;
+
+impl Console for NoConsole {
+    fn write_line(&mut self, _line: &str) {}
+}
 
 #[derive(PartialEq)]
 enum CPUState {
@@ -15,7 +52,7 @@ pub struct CPU {
     delay_timer: u8,
     sound_timer: u8,
     memory: Vec<u8>,
-    pub frame_buffer: Vec<bool>,
+    frame_buffer: Vec<bool>,
     stack: Vec<u16>,
     keypad: Keypad,
     keypad_interrupt: Option<fn(&mut CPU, u8)>,
@@ -24,11 +61,35 @@ pub struct CPU {
     memory_size: usize,
     frame_buffer_size: usize,
     max_stack_size: usize,
-    pub redraw: bool,
+    display_width: usize,
+    display_height: usize,
+    /// Whether the frame buffer changed since `take_redraw` was last called
+    redraw: bool,
     cpu_state: CPUState,
+    panic_message: Option<String>,
+    /// Address and length of the sprite drawn last
+    last_sprite: Option<(u16, u16)>,
     pub detailed_logging: bool,
-
-    time_since_last_decrease: Duration,
+    /// Counts the executed instructions when profiling
+    #[cfg(feature = "std")]
+    pub profiler: Option<Profiler>,
+    /// Tracks executed instructions and data reads when measuring coverage
+    #[cfg(feature = "std")]
+    pub coverage: Option<Coverage>,
+    /// Collects the address and value of every byte instructions write to memory,
+    /// when a script watches memory writes
+    pub memory_writes: Option<Vec<(u16, u8)>>,
+    font: Vec<u8>,
+    /// Source of CXNN. The core has no access to OS entropy, frontends seed it with
+    /// `seed_rng`, otherwise the numbers are the same on every run. The generator of
+    /// `StdRng`, which can jump to a position in its stream to restore save states.
+    rng: ChaCha12Rng,
+    /// Seed of `rng`, saved with the position in its stream
+    rng_seed: u64,
+    console: Box<dyn Console>,
+
+    /// Nanoseconds passed since the timers were last counted down
+    time_since_last_decrease: u64,
 }
 
 impl CPU {
@@ -38,6 +99,7 @@ impl CPU {
         frame_buffer_size: usize,
         max_stack_size: usize,
     ) -> Self {
+        let display_width = CPU::display_width_for(frame_buffer_size);
         let mut cpu = Self {
             pc: 0x200,
             i_register: 0,
@@ -46,29 +108,48 @@ impl CPU {
             sound_timer: 0,
             memory: vec![0; memory_size],
             frame_buffer: vec![false; frame_buffer_size],
-            stack: vec![0; max_stack_size],
+            stack: Vec::with_capacity(max_stack_size),
             keypad: Keypad::new(),
             keypad_interrupt: None,
             interrupt_register: 0,
             memory_size,
             frame_buffer_size,
             max_stack_size,
+            display_width,
+            display_height: display_width / 2,
             redraw: true,
             cpu_state: CPUState::Running,
+            panic_message: None,
+            last_sprite: None,
             detailed_logging: false,
-            time_since_last_decrease: Duration::new(0, 0),
+            #[cfg(feature = "std")]
+            profiler: None,
+            #[cfg(feature = "std")]
+            coverage: None,
+            memory_writes: None,
+            font: font.to_vec(),
+            rng: ChaCha12Rng::seed_from_u64(0),
+            rng_seed: 0,
+            #[cfg(feature = "std")]
+            console: Box::new(Stdout),
+            #[cfg(not(feature = "std"))]
+            console: Box::new(NoConsole),
+            time_since_last_decrease: 0,
         };
         cpu.memory[20..100].copy_from_slice(font);
 
         return cpu;
     }
 
-    pub fn emulate_cycle(&mut self, delta: Duration, keypad: &Keypad) {
+    /// Execute one instruction. `elapsed_nanos` is the time the cycle takes, the timers
+    /// count down for every 1/60 s of it. Frontends that count the timers down
+    /// themselves with `decrease_timers` pass 0.
+    pub fn emulate_cycle(&mut self, elapsed_nanos: u64, keypad: &Keypad) {
         if self.cpu_state == CPUState::Panic {
             return;
         }
 
-        self.update_timers(delta);
+        self.update_timers(elapsed_nanos);
 
         match self.cpu_state {
             CPUState::Running => self.execute_instruction(),
@@ -79,6 +160,8 @@ impl CPU {
     }
 
     fn execute_instruction(&mut self) {
+        #[cfg(feature = "std")]
+        let address = self.pc;
         let instruction: u16 = (self.memory[self.pc as usize] as u16) << 8
             | (self.memory[(self.pc + 1) as usize] as u16);
         self.pc += 2;
@@ -88,34 +171,59 @@ impl CPU {
         let nd = instruction & 0x000F;
 
         if self.detailed_logging {
-            println!("Instruction: {:x} {:x} {:x} {:x}", na, nb, nc, nd);
+            let line = format!("Instruction: {:x} {:x} {:x} {:x}", na, nb, nc, nd);
+            self.console.write_line(&line);
         }
 
-        match na {
-            0x0 => {
-                match instruction {
-                    0x00E0 => self.clear_screen(),
-                    0x00EE => self.return_from_subroutine(),
-                    _ => self.panic_unknown_instruction(instruction),
-                };
+        match Instruction::decode(instruction) {
+            Instruction::ClearScreen => self.clear_screen(),
+            Instruction::Return => self.return_from_subroutine(),
+            Instruction::Jump(address) => self.jump_to_address(address),
+            Instruction::Call(address) => self.jump_to_subroutine(address),
+            Instruction::SkipIfEqual(x, value) => self.skip_if_equal(x.into(), value),
+            Instruction::SkipIfNotEqual(x, value) => self.skip_if_not_equal(x.into(), value),
+            Instruction::SkipIfRegistersEqual(x, y) => self.skip_if_x_equals_y(x.into(), y.into()),
+            Instruction::Load(x, value) => self.set_register_vx(x.into(), value),
+            Instruction::Add(x, value) => self.add_to_register_vx(x.into(), value),
+            Instruction::LoadRegister(x, y) => self.store_vy_in_vx(x.into(), y.into()),
+            Instruction::Or(x, y) => self.set_vx_to_vx_or_vy(x.into(), y.into()),
+            Instruction::And(x, y) => self.set_vx_to_vx_and_vy(x.into(), y.into()),
+            Instruction::Xor(x, y) => self.set_vx_to_vx_xor_vy(x.into(), y.into()),
+            Instruction::AddRegister(x, y) => self.add_vy_to_vx_carry(x.into(), y.into()),
+            Instruction::Subtract(x, y) => self.subtract_vy_from_vx_borrow(x.into(), y.into()),
+            Instruction::ShiftRight(x, y) => {
+                self.shift_vy_one_right_store_in_vx(x.into(), y.into())
+            }
+            Instruction::SubtractFrom(x, y) => self.subtract_vx_from_vy_borrow(x.into(), y.into()),
+            Instruction::ShiftLeft(x, y) => self.shift_vy_one_left_store_in_vx(x.into(), y.into()),
+            Instruction::SkipIfRegistersNotEqual(x, y) => {
+                self.skip_if_x_not_equals_y(x.into(), y.into())
             }
-            0x1 => self.jump_to_address(nb << 8 | nc << 4 | nd),
-            0x2 => self.jump_to_subroutine(nb << 8 | nc << 4 | nd),
-            0x3 => self.skip_if_equal(nb, (nc << 4 | nd) as u8),
-            0x4 => self.skip_if_not_equal(nb, (nc << 4 | nd) as u8),
-            0x5 => self.skip_if_x_equals_y(nb, nc),
-            0x6 => self.set_register_vx(nb, (nc << 4 | nd) as u8),
-            0x7 => self.add_to_register_vx(nb, (nc << 4 | nd) as u8),
-            0x8 => self.arithmetic_instructions(nb, nc, nd),
-            0x9 => self.skip_if_x_not_equals_y(nb, nc),
-            0xA => self.set_index_register(nb << 8 | nc << 4 | nd),
-            0xB => self.jump_with_offset(nb << 8 | nc << 4 | nd),
-            0xC => self.set_masked_random(nb, (nc << 4 | nd) as u8),
-            0xD => self.draw_sprite(nb, nc, nd),
-            0xE => self.e_instructions(nb, nc, nd),
-            0xF => self.f_instructions(nb, nc, nd),
-            _ => self.panic_unknown_instruction(instruction),
+            Instruction::LoadI(address) => self.set_index_register(address),
+            Instruction::JumpWithOffset(address) => self.jump_with_offset(address),
+            Instruction::Random(x, mask) => self.set_masked_random(x.into(), mask),
+            Instruction::Draw(x, y, n) => self.draw_sprite(x.into(), y.into(), n.into()),
+            Instruction::SkipIfPressed(x) => self.skip_if_pressed(x.into()),
+            Instruction::SkipIfNotPressed(x) => self.skip_if_not_pressed(x.into()),
+            Instruction::LoadDelayTimer(x) => self.store_delay_timer_in_vx(x.into()),
+            Instruction::WaitForKey(x) => self.store_next_keypress_in_vx(x.into()),
+            Instruction::SetDelayTimer(x) => self.set_timer_delay(x.into()),
+            Instruction::SetSoundTimer(x) => self.set_sound_delay(x.into()),
+            Instruction::AddToI(x) => self.add_vx_to_i(x.into()),
+            Instruction::StoreDecimal(x) => self.store_decimal_at_i(x.into()),
+            Instruction::StoreRegisters(x) => self.store_register_values_in_memory(x.into()),
+            Instruction::LoadRegisters(x) => self.load_register_values_from_memory(x.into()),
+            Instruction::Unknown(instruction) => self.panic_unknown_instruction(instruction),
         };
+
+        #[cfg(feature = "std")]
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record(address, instruction, self.pc);
+        }
+        #[cfg(feature = "std")]
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record_execution(address);
+        }
     }
 
     fn handle_interrupt(&mut self, keypad: &Keypad) {
@@ -132,17 +240,20 @@ impl CPU {
         }
     }
 
-    /// Update timers with the duration that has elapsed since the last cycle
-    fn update_timers(&mut self, delta: Duration) {
-        self.time_since_last_decrease += delta;
-        let frequency_duration = Duration::from_millis(17);
-        if self.time_since_last_decrease >= frequency_duration {
+    /// Update timers with the time that has elapsed since the last cycle. Called by
+    /// `emulate_cycle`, or before it by tools that tell timer ticks and instructions apart.
+    pub fn update_timers(&mut self, elapsed_nanos: u64) {
+        self.time_since_last_decrease += elapsed_nanos;
+        if self.time_since_last_decrease >= TIMER_PERIOD_NANOS {
             self.decrease_timers();
-            self.time_since_last_decrease -= frequency_duration;
+            self.time_since_last_decrease -= TIMER_PERIOD_NANOS;
         }
     }
 
-    fn decrease_timers(&mut self) {
+    /// Count the timers down by one 60 Hz tick. Called by `emulate_cycle` as time passes,
+    /// or once per frame by `FrameClock` for frontends that pass no time to keep runs
+    /// reproducible.
+    pub fn decrease_timers(&mut self) {
         if self.sound_timer > 0 {
             self.sound_timer -= 1;
         }
@@ -164,13 +275,18 @@ impl CPU {
         let address = self.stack.pop();
         match address {
             Some(address) => self.pc = address,
-            None => {
-                println!("Return from subroutine failed because stack was empty");
-                self.cpu_state = CPUState::Panic;
-            }
+            None => self.enter_panic(String::from(
+                "Return from subroutine failed because stack was empty",
+            )),
         }
     }
 
+    /// Width of a display with the given number of pixels.
+    /// CHIP-8 displays are always twice as wide as they are high.
+    fn display_width_for(frame_buffer_size: usize) -> usize {
+        (frame_buffer_size * 2).isqrt()
+    }
+
     /// 0x1NNN
     /// Jump to address NNN
     fn jump_to_address(&mut self, address: u16) {
@@ -181,6 +297,12 @@ impl CPU {
     /// 0x2NNN
     /// Execute subroutine starting at address NNN
     fn jump_to_subroutine(&mut self, address: u16) {
+        if self.stack.len() >= self.max_stack_size {
+            self.enter_panic(String::from(
+                "Call of subroutine failed because stack was full",
+            ));
+            return;
+        }
         self.stack.push(self.pc);
         self.pc = address;
     }
@@ -228,23 +350,6 @@ impl CPU {
         (self.registers[register as usize], _) = value.overflowing_add(number);
     }
 
-    /// 0x8...
-    /// Handler for the 0x8 instruction family
-    fn arithmetic_instructions(&mut self, nb: u16, nc: u16, nd: u16) {
-        match nd {
-            0x0 => self.store_vy_in_vx(nb, nc),
-            0x1 => self.set_vx_to_vx_or_vy(nb, nc),
-            0x2 => self.set_vx_to_vx_and_vy(nb, nc),
-            0x3 => self.set_vx_to_vx_xor_vy(nb, nc),
-            0x4 => self.add_vy_to_vx_carry(nb, nc),
-            0x5 => self.subtract_vy_from_vx_borrow(nb, nc),
-            0x6 => self.shift_vy_one_right_store_in_vx(nb, nc),
-            0x7 => self.subtract_vx_from_vy_borrow(nb, nc),
-            0xE => self.shift_vy_one_left_store_in_vx(nb, nc),
-            _ => self.panic_unknown_instruction(0x8 << 12 | nb << 8 | nc << 4 | nd),
-        }
-    }
-
     /// 0x8XY0
     /// Store the value of register VY in register VX
     fn store_vy_in_vx(&mut self, x: u16, y: u16) {
@@ -359,7 +464,7 @@ impl CPU {
     /// 0xCXNN
     /// Set VX to a random number with mask NN
     fn set_masked_random(&mut self, x: u16, mask: u8) {
-        let number: u8 = rand::random();
+        let number: u8 = self.rng.gen();
         let number = number & mask;
         self.set_value_of_register(x, number);
     }
@@ -368,27 +473,34 @@ impl CPU {
     /// Draw a sprite to the screen
     fn draw_sprite(&mut self, register_x: u16, register_y: u16, n: u16) {
         //println!("Draw sprite {:x}, {:x}, {:x}, {:x}", register_x, register_y, n, self.i_register);
+        let width = self.display_width as u16;
+        let height = self.display_height as u16;
         let mut x_coordinate = self.registers[register_x as usize] as u16;
         let mut y_coordinate = self.registers[register_y as usize] as u16;
-        if x_coordinate > 63 {
-            x_coordinate = x_coordinate % 64;
+        if x_coordinate >= width {
+            x_coordinate = x_coordinate % width;
         }
-        if y_coordinate > 31 {
-            y_coordinate = y_coordinate % 32;
+        if y_coordinate >= height {
+            y_coordinate = y_coordinate % height;
         }
 
         self.registers[0xF] = 0;
+        self.last_sprite = Some((self.i_register, n));
+        #[cfg(feature = "std")]
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record_read(self.i_register, n as usize);
+        }
         for i in 0..n {
             let row = self.memory[(self.i_register + i) as usize];
-            let y = ((y_coordinate + i) as usize) * 64;
-            if y_coordinate + i > 31 {
+            let y = ((y_coordinate + i) as usize) * self.display_width;
+            if y_coordinate + i >= height {
                 break;
             }
             for j in 0..8 {
                 let pixel = (row & (0x1 << (7 - j))) >> (7 - j);
                 let x = (x_coordinate + j) as usize;
 
-                if x > 63 {
+                if x >= self.display_width {
                     break;
                 }
 
@@ -407,22 +519,12 @@ impl CPU {
         self.redraw = true;
     }
 
-    fn e_instructions(&mut self, nb: u16, nc: u16, nd: u16) {
-        let encoded = nc << 4 | nd;
-        match encoded {
-            0x9E => self.skip_if_pressed(nb),
-            0xA1 => self.skip_if_not_pressed(nb),
-            _ => self.panic_unknown_instruction(0xE << 12 | nb << 8 | encoded),
-        }
-    }
-
     /// 0xEX9E
     /// Skip next instruction if key in VX is pressed
     fn skip_if_pressed(&mut self, x: u16) {
         let value = self.get_value_of_register(x);
         if value > 16 {
-            println!("Keycode passed to 0xEX9E was > 16: {}", value);
-            self.cpu_state = CPUState::Panic;
+            self.enter_panic(format!("Keycode passed to 0xEX9E was > 16: {}", value));
             return;
         }
         if self.keypad.is_key_pressed(value as usize) {
@@ -435,8 +537,7 @@ impl CPU {
     fn skip_if_not_pressed(&mut self, x: u16) {
         let value = self.get_value_of_register(x);
         if value > 16 {
-            println!("Keycode passed to 0xEX9E was > 16: {}", value);
-            self.cpu_state = CPUState::Panic;
+            self.enter_panic(format!("Keycode passed to 0xEXA1 was > 16: {}", value));
             return;
         }
         if !self.keypad.is_key_pressed(value as usize) {
@@ -444,22 +545,6 @@ impl CPU {
         }
     }
 
-    /// F instruction family
-    fn f_instructions(&mut self, nb: u16, nc: u16, nd: u16) {
-        let encoded = nc << 4 | nd;
-        match encoded {
-            0x07 => self.store_delay_timer_in_vx(nb),
-            0x0A => self.store_next_keypress_in_vx(nb),
-            0x15 => self.set_timer_delay(nb),
-            0x18 => self.set_sound_delay(nb),
-            0x1E => self.add_vx_to_i(nb),
-            0x33 => self.store_decimal_at_i(nb),
-            0x55 => self.store_register_values_in_memory(nb),
-            0x65 => self.load_register_values_from_memory(nb),
-            _ => self.panic_unknown_instruction(0xF << 12 | nb << 8 | encoded),
-        };
-    }
-
     /// 0xFX07
     /// Store the current value of the delay timer in register VX
     fn store_delay_timer_in_vx(&mut self, x: u16) {
@@ -508,7 +593,11 @@ impl CPU {
     fn store_decimal_at_i(&mut self, x: u16) {
         let mut value = self.get_value_of_register(x);
         for i in (0..3).rev() {
-            self.memory[(self.i_register as usize) + i] = value % 10;
+            let address = self.i_register as usize + i;
+            self.memory[address] = value % 10;
+            if let Some(writes) = &mut self.memory_writes {
+                writes.push((address as u16, value % 10));
+            }
             value /= 10;
         }
     }
@@ -518,7 +607,11 @@ impl CPU {
     /// I is set to I + X + 1 after operation²
     fn store_register_values_in_memory(&mut self, x: u16) {
         for i in 0..(x + 1) {
-            self.memory[self.i_register as usize] = self.get_value_of_register(i as u16) as u8;
+            let value = self.get_value_of_register(i as u16);
+            self.memory[self.i_register as usize] = value;
+            if let Some(writes) = &mut self.memory_writes {
+                writes.push((self.i_register, value));
+            }
             self.i_register += 1;
         }
     }
@@ -527,6 +620,10 @@ impl CPU {
     /// Fill registers V0 to VX inclusive with the values stored in memory starting at address I
     /// I is set to I + X + 1 after operation²
     fn load_register_values_from_memory(&mut self, x: u16) {
+        #[cfg(feature = "std")]
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record_read(self.i_register, x as usize + 1);
+        }
         for i in 0..(x + 1) {
             let value = self.memory[self.i_register as usize];
             self.set_value_of_register(i as u16, value);
@@ -551,18 +648,154 @@ impl CPU {
         let nb = (instruction & 0x0F00) >> 8;
         let nc = (instruction & 0x00F0) >> 4;
         let nd = instruction & 0x000F;
-        println!("Unknown instruction {:x} {:x} {:x} {:x}", na, nb, nc, nd);
         self.print_memory();
+        self.enter_panic(format!(
+            "Unknown instruction {:x} {:x} {:x} {:x}",
+            na, nb, nc, nd
+        ));
+    }
+
+    /// Stop the CPU and remember why, so frontends can show the reason
+    fn enter_panic(&mut self, message: String) {
+        self.console.write_line(&message);
+        self.panic_message = Some(message);
         self.cpu_state = CPUState::Panic;
     }
 
-    pub fn print_memory(&self) {
+    pub fn panic_message(&self) -> Option<&str> {
+        self.panic_message.as_deref()
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn i_register(&self) -> u16 {
+        self.i_register
+    }
+
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.registers
+    }
+
+    /// Seed the numbers of CXNN, they are the same on every run with the same seed
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = ChaCha12Rng::seed_from_u64(seed);
+        self.rng_seed = seed;
+    }
+
+    /// Overwrite register VX, for tools that change the machine state
+    pub fn set_register(&mut self, x: usize, value: u8) {
+        self.registers[x] = value;
+    }
+
+    pub fn set_i_register(&mut self, value: u16) {
+        self.i_register = value;
+    }
+
+    pub fn set_pc(&mut self, value: u16) {
+        self.pc = value;
+    }
+
+    /// Return addresses of the active subroutine calls, the innermost last. Its length
+    /// is the stack pointer.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// The memory for frontends that hand it out to be changed in place, like libretro
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        &mut self.memory
+    }
+
+    /// Exchange the memory with `memory`, which has the same size, without copying it.
+    /// For tools that lend it out for a moment, like the hooks of scripts.
+    pub fn swap_memory(&mut self, memory: &mut Vec<u8>) {
+        assert_eq!(memory.len(), self.memory_size, "Memory size differs");
+        core::mem::swap(&mut self.memory, memory);
+    }
+
+    /// Overwrite memory starting at `address`, returns false if it doesn't fit
+    pub fn write_memory(&mut self, address: usize, data: &[u8]) -> bool {
+        let Some(end) = address.checked_add(data.len()) else {
+            return false;
+        };
+        match self.memory.get_mut(address..end) {
+            Some(target) => {
+                target.copy_from_slice(data);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The two bytes at `address` as instruction, if they are inside the memory
+    pub fn instruction_at(&self, address: usize) -> Option<u16> {
+        let high = *self.memory.get(address)?;
+        let low = *self.memory.get(address + 1)?;
+        Some((high as u16) << 8 | low as u16)
+    }
+
+    /// Whether the next cycle executes an instruction, instead of waiting for a key or
+    /// being stopped by a panic
+    pub fn is_running(&self) -> bool {
+        self.cpu_state == CPUState::Running
+    }
+
+    /// Address and number of bytes of the sprite drawn last, if any
+    pub fn last_sprite(&self) -> Option<(u16, u16)> {
+        self.last_sprite
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    /// Whether the CPU waits for a key press (0xFX0A)
+    pub fn is_waiting_for_key(&self) -> bool {
+        self.cpu_state == CPUState::Sleeping
+    }
+
+    /// Current display size in pixels as (width, height)
+    pub fn display_size(&self) -> (usize, usize) {
+        (self.display_width, self.display_height)
+    }
+
+    /// Pixels of the display row by row, the first `width * height` are in use
+    pub fn frame_buffer(&self) -> &[bool] {
+        &self.frame_buffer
+    }
+
+    /// Whether the frame buffer changed since the last call
+    pub fn take_redraw(&mut self) -> bool {
+        core::mem::take(&mut self.redraw)
+    }
+
+    /// Send the messages of the CPU somewhere else than to stdout
+    pub fn set_console(&mut self, console: Box<dyn Console>) {
+        self.console = console;
+    }
+
+    /// Write a message of the frontend to the console, next to the messages of the CPU
+    pub fn log(&mut self, line: &str) {
+        self.console.write_line(line);
+    }
+
+    pub fn print_memory(&mut self) {
         for i in 0..self.memory_size / 32 {
-            print!("{:>8x}  ", i * 32);
+            let mut line = format!("{:>8x}  ", i * 32);
             for j in 0..32 {
-                print!("{:>3} ", self.memory[i * 32 + j]);
+                let _ = write!(line, "{:>3} ", self.memory[i * 32 + j]);
             }
-            println!();
+            self.console.write_line(&line);
             // Temporarily we don't need to print out more than that of the memory
             if i * 32 > 1500 {
                 break;
@@ -570,34 +803,254 @@ impl CPU {
         }
     }
 
-    pub fn print_frame_buffer(&self) {
-        for y in 0..32 {
-            for x in 0..64 {
-                print!("{}", self.frame_buffer[y * 64 + x] as i32);
+    pub fn print_frame_buffer(&mut self) {
+        for y in 0..self.display_height {
+            let mut line = String::new();
+            for x in 0..self.display_width {
+                let _ = write!(
+                    line,
+                    "{}",
+                    self.frame_buffer[y * self.display_width + x] as i32
+                );
             }
-            println!();
+            self.console.write_line(&line);
         }
     }
 
-    pub fn print_registers(&self) {
+    pub fn print_registers(&mut self) {
+        let mut line = String::new();
         for i in 0..16 {
-            print!("{:>3} ", i);
+            let _ = write!(line, "{:>3} ", i);
         }
-        println!();
+        self.console.write_line(&line);
+        line.clear();
         for i in 0..16 {
-            print!("{:>3} ", self.registers[i]);
+            let _ = write!(line, "{:>3} ", self.registers[i]);
         }
-        println!();
-        println!("Index: {:>3x}", self.i_register);
-        println!("PC:    {:>3x}", self.pc);
+        self.console.write_line(&line);
+        self.console
+            .write_line(&format!("Index: {:>3x}", self.i_register));
+        self.console.write_line(&format!("PC:    {:>3x}", self.pc));
     }
 
-    pub fn print_value_at_i(&self) {
+    pub fn print_value_at_i(&mut self) {
         let value = self.memory[self.i_register as usize];
-        println!("I: {:x}", value);
+        self.console.write_line(&format!("I: {:x}", value));
     }
 
     pub fn set_program(&mut self, data: &[u8]) {
         self.memory[512..512 + data.len()].copy_from_slice(data);
     }
+
+    /// Soft reset: reinitialize registers, timers, stack and screen.
+    /// Memory is left untouched, so the program has to be set again afterwards.
+    pub fn reset(&mut self) {
+        self.pc = 0x200;
+        self.i_register = 0;
+        self.registers = [0; 16];
+        self.delay_timer = 0;
+        self.sound_timer = 0;
+        self.frame_buffer.fill(false);
+        self.stack.clear();
+        self.keypad = Keypad::new();
+        self.keypad_interrupt = None;
+        self.interrupt_register = 0;
+        self.redraw = true;
+        self.cpu_state = CPUState::Running;
+        self.panic_message = None;
+        self.last_sprite = None;
+        self.time_since_last_decrease = 0;
+    }
+
+    /// Copy of everything needed to continue from this point later, see `load_state`
+    pub fn save_state(&self) -> CpuState {
+        CpuState {
+            pc: self.pc,
+            i_register: self.i_register,
+            registers: self.registers,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            memory: self.memory.clone(),
+            frame_buffer: self.frame_buffer.clone(),
+            stack: self.stack.clone(),
+            keys: self.keypad.state(),
+            display_width: self.display_width,
+            display_height: self.display_height,
+            waiting_register: match self.cpu_state {
+                CPUState::Sleeping => Some(self.interrupt_register as u8),
+                _ => None,
+            },
+            panic_message: self.panic_message.clone(),
+            time_since_last_decrease: self.time_since_last_decrease,
+            rng_seed: self.rng_seed,
+            rng_position: self.rng.get_word_pos() as u64,
+        }
+    }
+
+    /// Continue from a state saved with `save_state`. Fails without changing anything if
+    /// the state belongs to a CPU with a different memory or display size, or points
+    /// outside the memory.
+    pub fn load_state(&mut self, state: &CpuState) -> Result<(), String> {
+        if state.memory.len() != self.memory_size
+            || state.stack.len() > self.max_stack_size
+            || state.frame_buffer.len() != self.frame_buffer_size
+            || (state.display_width, state.display_height) != self.display_size()
+        {
+            return Err(String::from("State doesn't fit the memory or display size"));
+        }
+        // Both bytes of an instruction have to be inside the memory
+        let outside = |address: u16| address as usize + 1 >= self.memory_size;
+        if outside(state.pc)
+            || state.i_register as usize >= self.memory_size
+            || state.stack.iter().any(|&address| outside(address))
+        {
+            return Err(String::from("State points outside the memory"));
+        }
+        self.pc = state.pc;
+        self.i_register = state.i_register;
+        self.registers = state.registers;
+        self.delay_timer = state.delay_timer;
+        self.sound_timer = state.sound_timer;
+        self.memory.copy_from_slice(&state.memory);
+        self.frame_buffer = state.frame_buffer.clone();
+        self.stack = state.stack.clone();
+        self.keypad.set_state(state.keys);
+        self.keypad_interrupt = None;
+        self.interrupt_register = 0;
+        self.cpu_state = CPUState::Running;
+        if let Some(register) = state.waiting_register {
+            self.store_next_keypress_in_vx(register as u16);
+        }
+        self.panic_message = state.panic_message.clone();
+        if self.panic_message.is_some() {
+            self.cpu_state = CPUState::Panic;
+        }
+        self.last_sprite = None;
+        self.time_since_last_decrease = state.time_since_last_decrease;
+        self.seed_rng(state.rng_seed);
+        self.rng.set_word_pos(state.rng_position as u128);
+        self.redraw = true;
+        Ok(())
+    }
+
+    /// Hard reset: like `reset`, but also clears the whole memory and reloads the font
+    pub fn hard_reset(&mut self) {
+        self.reset();
+        self.memory = vec![0; self.memory_size];
+        self.memory[20..100].copy_from_slice(&self.font);
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{
+        state::SaveState, DEFAULT_FRAME_BUFFER_SIZE, DEFAULT_MAX_STACK_SIZE, DEFAULT_MEMORY_SIZE,
+        FONT,
+    };
+
+    /// CALL 204, JP 200 after returning, and a subroutine that loops forever
+    const CALL_PROGRAM: [u8; 6] = [0x22, 0x04, 0x12, 0x00, 0x12, 0x04];
+
+    fn cpu_with_program(program: &[u8]) -> CPU {
+        let mut cpu = CPU::new(
+            &FONT,
+            DEFAULT_MEMORY_SIZE,
+            DEFAULT_FRAME_BUFFER_SIZE,
+            DEFAULT_MAX_STACK_SIZE,
+        );
+        cpu.set_console(Box::new(NoConsole));
+        cpu.set_program(program);
+        cpu
+    }
+
+    #[test]
+    fn stack_is_empty_after_reset() {
+        let cpu = cpu_with_program(&CALL_PROGRAM);
+        assert!(cpu.stack().is_empty());
+    }
+
+    #[test]
+    fn save_state_inside_call_round_trips() {
+        let mut cpu = cpu_with_program(&CALL_PROGRAM);
+        let keypad = Keypad::new();
+        for _ in 0..3 {
+            cpu.emulate_cycle(0, &keypad);
+        }
+        assert_eq!(cpu.stack(), [0x202]);
+
+        let state = SaveState {
+            cpu: cpu.save_state(),
+            keys: 0,
+            cycle: 3,
+        };
+        let loaded = SaveState::from_bytes(&state.to_bytes()).expect("State doesn't decode");
+        let mut restored = cpu_with_program(&[]);
+        restored
+            .load_state(&loaded.cpu)
+            .expect("State inside a call doesn't load");
+        assert_eq!(restored.stack(), [0x202]);
+        assert_eq!(restored.pc(), 0x204);
+    }
+
+    #[test]
+    fn save_state_restores_random_numbers() {
+        // RND V0 FF, JP 200
+        let program = [0xC0, 0xFF, 0x12, 0x00];
+        let mut cpu = cpu_with_program(&program);
+        cpu.seed_rng(7);
+        let keypad = Keypad::new();
+        for _ in 0..5 {
+            cpu.emulate_cycle(0, &keypad);
+        }
+        let state = SaveState::from_bytes(
+            &SaveState {
+                cpu: cpu.save_state(),
+                keys: 0,
+                cycle: 5,
+            }
+            .to_bytes(),
+        )
+        .expect("State doesn't decode");
+        let mut restored = cpu_with_program(&program);
+        restored.load_state(&state.cpu).expect("State doesn't load");
+
+        for _ in 0..6 {
+            cpu.emulate_cycle(0, &keypad);
+            restored.emulate_cycle(0, &keypad);
+            assert_eq!(restored.registers()[0], cpu.registers()[0]);
+        }
+    }
+
+    #[test]
+    fn load_state_rejects_addresses_outside_memory() {
+        let mut cpu = cpu_with_program(&CALL_PROGRAM);
+        let mut state = cpu.save_state();
+        state.pc = DEFAULT_MEMORY_SIZE as u16 - 1;
+        assert!(cpu.load_state(&state).is_err());
+        let mut state = cpu.save_state();
+        state.i_register = 0xFFFF;
+        assert!(cpu.load_state(&state).is_err());
+        let mut state = cpu.save_state();
+        state.stack = vec![DEFAULT_MEMORY_SIZE as u16];
+        assert!(cpu.load_state(&state).is_err());
+        assert_eq!(cpu.pc(), 0x200);
+
+        // The random numbers jump to any position instead of replaying it
+        let mut state = cpu.save_state();
+        state.rng_position = u64::MAX;
+        assert!(cpu.load_state(&state).is_ok());
+    }
+
+    #[test]
+    fn call_beyond_max_stack_size_panics() {
+        // CALL 200 calls itself forever
+        let mut cpu = cpu_with_program(&[0x22, 0x00]);
+        let keypad = Keypad::new();
+        for _ in 0..=DEFAULT_MAX_STACK_SIZE {
+            cpu.emulate_cycle(0, &keypad);
+        }
+        assert_eq!(cpu.stack().len(), DEFAULT_MAX_STACK_SIZE);
+        assert!(cpu.panic_message().is_some());
+    }
 }
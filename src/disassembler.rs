@@ -0,0 +1,88 @@
+use crate::{instruction::Instruction, symbols::Symbols};
+
+/// How an instruction passes on control
+#[derive(Clone, Copy, PartialEq)]
+pub enum Flow {
+    /// Continues with the next instruction
+    Next,
+    /// Continues with the next instruction or skips it, depending on a condition
+    Skip,
+    Jump(u16),
+    Call(u16),
+    Return,
+    /// BNNN, jumps to NNN plus V0, so the target is only known at runtime
+    ComputedJump(u16),
+    /// An opcode the CPU doesn't know, it panics there
+    Invalid,
+}
+
+/// Mnemonic of a single instruction in the syntax of the CHIP-8 technical reference,
+/// e.g. `LD V0, 0x0C`. Opcodes the CPU doesn't know are shown as data word `DW 0x1234`.
+pub fn disassemble(instruction: u16) -> String {
+    match Instruction::decode(instruction) {
+        Instruction::ClearScreen => String::from("CLS"),
+        Instruction::Return => String::from("RET"),
+        Instruction::Jump(address) => format!("JP 0x{:03X}", address),
+        Instruction::Call(address) => format!("CALL 0x{:03X}", address),
+        Instruction::SkipIfEqual(x, value) => format!("SE V{:X}, 0x{:02X}", x, value),
+        Instruction::SkipIfNotEqual(x, value) => format!("SNE V{:X}, 0x{:02X}", x, value),
+        Instruction::SkipIfRegistersEqual(x, y) => format!("SE V{:X}, V{:X}", x, y),
+        Instruction::Load(x, value) => format!("LD V{:X}, 0x{:02X}", x, value),
+        Instruction::Add(x, value) => format!("ADD V{:X}, 0x{:02X}", x, value),
+        Instruction::LoadRegister(x, y) => format!("LD V{:X}, V{:X}", x, y),
+        Instruction::Or(x, y) => format!("OR V{:X}, V{:X}", x, y),
+        Instruction::And(x, y) => format!("AND V{:X}, V{:X}", x, y),
+        Instruction::Xor(x, y) => format!("XOR V{:X}, V{:X}", x, y),
+        Instruction::AddRegister(x, y) => format!("ADD V{:X}, V{:X}", x, y),
+        Instruction::Subtract(x, y) => format!("SUB V{:X}, V{:X}", x, y),
+        Instruction::ShiftRight(x, y) => format!("SHR V{:X}, V{:X}", x, y),
+        Instruction::SubtractFrom(x, y) => format!("SUBN V{:X}, V{:X}", x, y),
+        Instruction::ShiftLeft(x, y) => format!("SHL V{:X}, V{:X}", x, y),
+        Instruction::SkipIfRegistersNotEqual(x, y) => format!("SNE V{:X}, V{:X}", x, y),
+        Instruction::LoadI(address) => format!("LD I, 0x{:03X}", address),
+        Instruction::JumpWithOffset(address) => format!("JP V0, 0x{:03X}", address),
+        Instruction::Random(x, mask) => format!("RND V{:X}, 0x{:02X}", x, mask),
+        Instruction::Draw(x, y, n) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        Instruction::SkipIfPressed(x) => format!("SKP V{:X}", x),
+        Instruction::SkipIfNotPressed(x) => format!("SKNP V{:X}", x),
+        Instruction::LoadDelayTimer(x) => format!("LD V{:X}, DT", x),
+        Instruction::WaitForKey(x) => format!("LD V{:X}, K", x),
+        Instruction::SetDelayTimer(x) => format!("LD DT, V{:X}", x),
+        Instruction::SetSoundTimer(x) => format!("LD ST, V{:X}", x),
+        Instruction::AddToI(x) => format!("ADD I, V{:X}", x),
+        Instruction::StoreDecimal(x) => format!("LD B, V{:X}", x),
+        Instruction::StoreRegisters(x) => format!("LD [I], V{:X}", x),
+        Instruction::LoadRegisters(x) => format!("LD V{:X}, [I]", x),
+        Instruction::Unknown(opcode) => format!("DW 0x{:04X}", opcode),
+    }
+}
+
+/// Like `disassemble`, with the target address of jumps, calls and `LD I` shown as
+/// symbol name if there is a symbol at it
+pub fn disassemble_with_symbols(instruction: u16, symbols: &Symbols) -> String {
+    let text = disassemble(instruction);
+    let target = Instruction::decode(instruction).address();
+    match target.and_then(|target| Some((target, symbols.name(target)?))) {
+        Some((target, name)) => text.replace(&format!("0x{:03X}", target), name),
+        None => text,
+    }
+}
+
+/// Decode how `instruction` continues the control flow, for tools that follow the program
+/// without running it
+pub fn flow(instruction: u16) -> Flow {
+    match Instruction::decode(instruction) {
+        Instruction::Return => Flow::Return,
+        Instruction::Jump(address) => Flow::Jump(address),
+        Instruction::Call(address) => Flow::Call(address),
+        Instruction::JumpWithOffset(address) => Flow::ComputedJump(address),
+        Instruction::SkipIfEqual(..)
+        | Instruction::SkipIfNotEqual(..)
+        | Instruction::SkipIfRegistersEqual(..)
+        | Instruction::SkipIfRegistersNotEqual(..)
+        | Instruction::SkipIfPressed(_)
+        | Instruction::SkipIfNotPressed(_) => Flow::Skip,
+        Instruction::Unknown(_) => Flow::Invalid,
+        _ => Flow::Next,
+    }
+}
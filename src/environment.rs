@@ -0,0 +1,404 @@
+//! Gym-style environments for reinforcement learning. A spec file describes the actions
+//! and rewards of a ROM, `Environment` runs one episode at a time without rendering and
+//! `Batch` steps many environments in parallel.
+
+use std::{fs, io, thread};
+
+use crate::{
+    clock::FrameClock, cpu::CPU, keypad::Keypad, DEFAULT_FRAME_BUFFER_SIZE,
+    DEFAULT_INSTRUCTIONS_PER_SECOND, DEFAULT_MAX_STACK_SIZE, DEFAULT_MEMORY_SIZE, FONT,
+    FRAMES_PER_SECOND,
+};
+
+/// How a watched byte is compared to end an episode
+#[derive(Clone, Copy)]
+enum Condition {
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+}
+
+/// Rules of the environment of one ROM: the actions an agent can take, and memory
+/// addresses whose changes are rewarded or that end an episode.
+///
+/// Spec files have one rule per line, lines starting with `#` are comments:
+/// `frame_skip 4` frames per step, `max_frames 18000` per episode,
+/// `action 4 6` an action pressing keys 4 and 6 (`action none` presses nothing),
+/// `reward 03F0 1.0` adds the change of the byte at 03F0 times 1.0 to the reward and
+/// `done 03F1 == 0` ends the episode when the byte at 03F1 is 0 (`==`, `!=`, `<`, `>`).
+#[derive(Clone)]
+pub struct EnvSpec {
+    frame_skip: u32,
+    max_frames: u64,
+    /// Key state of every action
+    actions: Vec<u16>,
+    rewards: Vec<(u16, f64)>,
+    done: Vec<(u16, Condition, u8)>,
+}
+
+impl EnvSpec {
+    pub fn load(path: &str) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        Self::parse(&contents).map_err(|error| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("{}:{}", path, error))
+        })
+    }
+
+    /// Parse the rules of a spec file, errors start with the line number
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        let mut spec = EnvSpec {
+            frame_skip: 4,
+            max_frames: 60 * 60 * FRAMES_PER_SECOND as u64,
+            actions: Vec::new(),
+            rewards: Vec::new(),
+            done: Vec::new(),
+        };
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if spec.parse_line(line).is_none() {
+                return Err(format!("{}: invalid rule `{}`", number + 1, line));
+            }
+        }
+        if spec.actions.is_empty() {
+            // No key and every single key
+            spec.actions = [0].into_iter().chain((0..16).map(|key| 1 << key)).collect();
+        }
+        Ok(spec)
+    }
+
+    fn parse_line(&mut self, line: &str) -> Option<()> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let address = |word: &str| {
+            u16::from_str_radix(word, 16)
+                .ok()
+                .filter(|&address| (address as usize) < DEFAULT_MEMORY_SIZE)
+        };
+        match words[..] {
+            ["frame_skip", frames] => self.frame_skip = frames.parse().ok().filter(|&n| n > 0)?,
+            ["max_frames", frames] => self.max_frames = frames.parse().ok()?,
+            ["action", "none"] => self.actions.push(0),
+            ["action", ref keys @ ..] if !keys.is_empty() => {
+                let mut state = 0;
+                for key in keys {
+                    state |= 1 << u8::from_str_radix(key, 16).ok().filter(|&key| key < 16)?;
+                }
+                self.actions.push(state);
+            }
+            ["reward", at, factor] => self.rewards.push((address(at)?, factor.parse().ok()?)),
+            ["done", at, condition, value] => {
+                let condition = match condition {
+                    "==" => Condition::Equal,
+                    "!=" => Condition::NotEqual,
+                    "<" => Condition::Less,
+                    ">" => Condition::Greater,
+                    _ => return None,
+                };
+                self.done
+                    .push((address(at)?, condition, value.parse().ok()?));
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+
+    pub fn action_count(&self) -> usize {
+        self.actions.len()
+    }
+
+    pub fn frame_skip(&self) -> u32 {
+        self.frame_skip
+    }
+}
+
+/// Result of one step of an environment
+pub struct Step {
+    pub observation: Vec<u8>,
+    pub reward: f64,
+    pub done: bool,
+}
+
+/// Gym-style environment running a ROM without any rendering. Timers count frames and
+/// the random numbers are seeded per episode, so the same actions always give the
+/// same episode.
+pub struct Environment {
+    cpu: CPU,
+    keypad: Keypad,
+    program: Vec<u8>,
+    spec: EnvSpec,
+    seed: u64,
+    episodes: u64,
+    clock: FrameClock,
+    /// Values of the rewarded bytes after the last step
+    watched: Vec<u8>,
+}
+
+impl Environment {
+    pub fn new(program: &[u8], spec: EnvSpec, seed: u64) -> Self {
+        let cpu = CPU::new(
+            &FONT,
+            DEFAULT_MEMORY_SIZE,
+            DEFAULT_FRAME_BUFFER_SIZE,
+            DEFAULT_MAX_STACK_SIZE,
+        );
+        let mut environment = Self {
+            cpu,
+            keypad: Keypad::new(),
+            program: program.to_vec(),
+            spec,
+            seed,
+            episodes: 0,
+            clock: FrameClock::new(DEFAULT_INSTRUCTIONS_PER_SECOND),
+            watched: Vec::new(),
+        };
+        environment.reset();
+        environment
+    }
+
+    /// Start a new episode and return the first observation
+    pub fn reset(&mut self) -> Vec<u8> {
+        self.cpu.hard_reset();
+        self.cpu.set_program(&self.program);
+        self.cpu.seed_rng(self.seed.wrapping_add(self.episodes));
+        self.episodes += 1;
+        self.keypad = Keypad::new();
+        self.clock.reset();
+        self.watched = self.read_watched();
+        self.observation()
+    }
+
+    /// Hold the keys of `action` for the configured number of frames
+    pub fn step(&mut self, action: usize) -> Result<Step, String> {
+        let keys = self.spec.actions.get(action).copied().ok_or_else(|| {
+            format!(
+                "Invalid action {}, there are {}",
+                action,
+                self.spec.action_count()
+            )
+        })?;
+        self.keypad.set_state(keys);
+        for _ in 0..self.spec.frame_skip {
+            self.clock.run_frame(&mut self.cpu, &self.keypad);
+            if self.is_done() {
+                break;
+            }
+        }
+        let watched = self.read_watched();
+        let reward = self
+            .spec
+            .rewards
+            .iter()
+            .zip(self.watched.iter().zip(&watched))
+            .map(|(&(_, factor), (&old, &new))| (new as f64 - old as f64) * factor)
+            .sum();
+        self.watched = watched;
+        Ok(Step {
+            observation: self.observation(),
+            reward,
+            done: self.is_done(),
+        })
+    }
+
+    /// The display as one byte per pixel, 1 for set pixels, row by row
+    pub fn observation(&self) -> Vec<u8> {
+        self.cpu.frame_buffer().iter().map(|&on| on as u8).collect()
+    }
+
+    pub fn display_size(&self) -> (usize, usize) {
+        self.cpu.display_size()
+    }
+
+    pub fn spec(&self) -> &EnvSpec {
+        &self.spec
+    }
+
+    fn read_watched(&self) -> Vec<u8> {
+        let memory = self.cpu.memory();
+        self.spec
+            .rewards
+            .iter()
+            .map(|&(address, _)| memory[address as usize])
+            .collect()
+    }
+
+    fn is_done(&self) -> bool {
+        let memory = self.cpu.memory();
+        let condition_met = self.spec.done.iter().any(|&(address, condition, value)| {
+            let current = memory[address as usize];
+            match condition {
+                Condition::Equal => current == value,
+                Condition::NotEqual => current != value,
+                Condition::Less => current < value,
+                Condition::Greater => current > value,
+            }
+        });
+        condition_met
+            || self.cpu.panic_message().is_some()
+            || self.clock.frame() >= self.spec.max_frames
+    }
+}
+
+/// Many environments of the same ROM, stepped in parallel on all cores
+pub struct Batch {
+    environments: Vec<Environment>,
+    spec: EnvSpec,
+}
+
+impl Batch {
+    /// `size` environments, each with its own seed derived from `seed`
+    pub fn new(program: &[u8], spec: &EnvSpec, size: usize, seed: u64) -> Self {
+        let environments = (0..size as u64)
+            .map(|index| Environment::new(program, spec.clone(), seed ^ (index << 32)))
+            .collect();
+        Self {
+            environments,
+            spec: spec.clone(),
+        }
+    }
+
+    /// Step every environment with its action. Environments that are done are reset,
+    /// the returned observation is then the first one of the new episode.
+    pub fn step(&mut self, actions: &[usize]) -> Result<Vec<Step>, String> {
+        if actions.len() != self.environments.len() {
+            return Err(format!(
+                "Got {} actions for {} environments",
+                actions.len(),
+                self.environments.len()
+            ));
+        }
+        if let Some(&action) = actions
+            .iter()
+            .find(|&&action| action >= self.spec.action_count())
+        {
+            return Err(format!(
+                "Invalid action {}, there are {}",
+                action,
+                self.spec.action_count()
+            ));
+        }
+        let threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+        let chunk_size = self.environments.len().div_ceil(threads).max(1);
+        thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .environments
+                .chunks_mut(chunk_size)
+                .zip(actions.chunks(chunk_size))
+                .map(|(environments, actions)| {
+                    scope.spawn(move || {
+                        environments
+                            .iter_mut()
+                            .zip(actions)
+                            .map(|(environment, &action)| {
+                                let mut step = environment
+                                    .step(action)
+                                    .expect("Actions were checked before");
+                                if step.done {
+                                    step.observation = environment.reset();
+                                }
+                                step
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            Ok(handles
+                .into_iter()
+                .flat_map(|handle| handle.join().expect("Environment thread panicked"))
+                .collect())
+        })
+    }
+
+    pub fn size(&self) -> usize {
+        self.environments.len()
+    }
+
+    /// Width and height of the observations
+    pub fn display_size(&self) -> (usize, usize) {
+        self.environments
+            .first()
+            .map_or((0, 0), Environment::display_size)
+    }
+
+    /// The spec all environments share
+    pub fn spec(&self) -> &EnvSpec {
+        &self.spec
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Draws a 0 at a random position and stores the random number at 0x300
+    const RANDOM_PROGRAM: [u8; 14] = [
+        0xC0, 0xFF, // RND V0 FF
+        0xA3, 0x00, // LD I 300
+        0xF0, 0x55, // LD [I] V0
+        0x00, 0xE0, // CLS
+        0xA0, 0x00, // LD I 000
+        0xD0, 0x05, // DRW V0 V0 5
+        0x12, 0x00, // JP 200
+    ];
+
+    const SPEC: &str = "frame_skip 2\nmax_frames 20\naction none\naction 5\nreward 300 1.0";
+
+    fn spec() -> EnvSpec {
+        EnvSpec::parse(SPEC).expect("Spec doesn't parse")
+    }
+
+    /// Observations and rewards of 25 steps, across the end of the first episode
+    fn run(environment: &mut Environment) -> Vec<(Vec<u8>, f64)> {
+        let mut steps = vec![(environment.reset(), 0.0)];
+        for index in 0..25 {
+            let step = environment.step(index % 2).expect("Action is invalid");
+            steps.push((step.observation, step.reward));
+            if step.done {
+                steps.push((environment.reset(), 0.0));
+            }
+        }
+        steps
+    }
+
+    #[test]
+    fn same_seed_and_actions_give_the_same_episode() {
+        let mut first = Environment::new(&RANDOM_PROGRAM, spec(), 3);
+        let mut second = Environment::new(&RANDOM_PROGRAM, spec(), 3);
+        assert_eq!(run(&mut first), run(&mut second));
+
+        let mut other_seed = Environment::new(&RANDOM_PROGRAM, spec(), 4);
+        assert_ne!(run(&mut first), run(&mut other_seed));
+    }
+
+    #[test]
+    fn batch_steps_like_single_environments() {
+        let mut batch = Batch::new(&RANDOM_PROGRAM, &spec(), 3, 5);
+        let mut environments: Vec<_> = (0..3)
+            .map(|index| Environment::new(&RANDOM_PROGRAM, spec(), 5 ^ (index << 32)))
+            .collect();
+        for actions in [[0, 1, 0], [1, 1, 0], [0, 0, 1]] {
+            let steps = batch.step(&actions).expect("Actions are invalid");
+            for ((step, environment), action) in steps.iter().zip(&mut environments).zip(actions) {
+                let expected = environment.step(action).expect("Action is invalid");
+                assert_eq!(step.observation, expected.observation);
+                assert_eq!(step.reward, expected.reward);
+                if expected.done {
+                    environment.reset();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_actions_are_rejected() {
+        let mut environment = Environment::new(&RANDOM_PROGRAM, spec(), 0);
+        assert!(environment.step(2).is_err());
+
+        let mut batch = Batch::new(&RANDOM_PROGRAM, &spec(), 2, 0);
+        assert!(batch.step(&[0]).is_err());
+        assert!(batch.step(&[0, 2]).is_err());
+        assert!(batch.step(&[0, 1]).is_ok());
+    }
+}
@@ -0,0 +1,283 @@
+use std::{
+    io, thread,
+    time::{Duration, Instant},
+};
+
+use crate::machine::{Machine, FRAMES_PER_SECOND};
+
+const FRAME_DURATION: Duration = Duration::from_micros(1_000_000 / FRAMES_PER_SECOND as u64);
+
+/// Shows the display, e.g. in a window, a terminal or a capture file
+pub trait DisplaySink {
+    /// Called after every frame, also while paused. `changed` tells whether the frame
+    /// buffer changed since the last call.
+    fn show(&mut self, machine: &Machine, changed: bool, paused: bool) -> io::Result<()>;
+}
+
+/// Feeds the keypad, e.g. from a keyboard
+pub trait InputSource {
+    /// Called before every frame to update the keypad and handle the keys of the
+    /// frontend itself, like pause and resets. Returns true if the frontend should quit.
+    fn poll(&mut self, machine: &mut Machine, paused: &mut bool) -> io::Result<bool>;
+}
+
+/// Plays the beeper
+pub trait AudioSink {
+    /// Called after every frame with whether the sound timer is running
+    fn beep(&mut self, on: bool);
+}
+
+/// Paces the frames
+pub trait TimeSource {
+    /// Return when the next frame is due
+    fn wait_for_frame(&mut self);
+}
+
+/// Run the machine frame by frame until the input source quits. A watched ROM is
+/// reloaded between frames when it changes.
+pub fn run(
+    machine: &mut Machine,
+    display: &mut impl DisplaySink,
+    input: &mut impl InputSource,
+    audio: &mut impl AudioSink,
+    time: &mut impl TimeSource,
+) -> io::Result<()> {
+    let mut paused = false;
+    loop {
+        machine.reload_if_changed();
+        if step(
+            machine,
+            display,
+            input,
+            audio,
+            &mut paused,
+            Machine::run_frame,
+        )? {
+            return Ok(());
+        }
+        time.wait_for_frame();
+    }
+}
+
+/// One frame of a frontend: poll the input, let `emulate` run the machine unless it
+/// is paused, then show the display and play the beeper. Frontends that pace the
+/// instructions themselves, like the window, call this instead of `run`. Returns true
+/// if the frontend should quit.
+pub fn step(
+    machine: &mut Machine,
+    display: &mut impl DisplaySink,
+    input: &mut impl InputSource,
+    audio: &mut impl AudioSink,
+    paused: &mut bool,
+    emulate: impl FnOnce(&mut Machine),
+) -> io::Result<bool> {
+    if input.poll(machine, paused)? {
+        return Ok(true);
+    }
+    if !*paused {
+        emulate(machine);
+    }
+    let changed = machine.cpu.take_redraw();
+    display.show(machine, changed, *paused)?;
+    audio.beep(!*paused && machine.cpu.sound_timer() > 0);
+    Ok(false)
+}
+
+/// Runs at the 60 Hz of the original hardware
+pub struct RealTime {
+    next_frame: Instant,
+}
+
+impl Default for RealTime {
+    fn default() -> Self {
+        Self {
+            next_frame: Instant::now(),
+        }
+    }
+}
+
+impl TimeSource for RealTime {
+    fn wait_for_frame(&mut self) {
+        self.next_frame += FRAME_DURATION;
+        if let Some(wait) = self.next_frame.checked_duration_since(Instant::now()) {
+            thread::sleep(wait);
+        }
+    }
+}
+
+/// Runs as fast as possible, for runs without a user
+pub struct Unthrottled;
+
+impl TimeSource for Unthrottled {
+    fn wait_for_frame(&mut self) {}
+}
+
+/// Drops the sound
+pub struct Silence;
+
+impl AudioSink for Silence {
+    fn beep(&mut self, _on: bool) {}
+}
+
+/// Presses no keys and quits after a number of frames or when the CPU panicked. The
+/// machine can still get input from a movie.
+pub struct FrameLimit {
+    frames: u64,
+    remaining: u64,
+}
+
+impl FrameLimit {
+    pub fn new(frames: u64) -> Self {
+        Self {
+            frames,
+            remaining: frames,
+        }
+    }
+
+    /// Frames run so far, counted across resets of the machine
+    pub fn frames_run(&self) -> u64 {
+        self.frames - self.remaining
+    }
+}
+
+impl InputSource for FrameLimit {
+    fn poll(&mut self, machine: &mut Machine, _paused: &mut bool) -> io::Result<bool> {
+        if self.remaining == 0 || machine.cpu.panic_message().is_some() {
+            return Ok(true);
+        }
+        self.remaining -= 1;
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8::{
+        cpu::{NoConsole, CPU},
+        DEFAULT_FRAME_BUFFER_SIZE, DEFAULT_MAX_STACK_SIZE, DEFAULT_MEMORY_SIZE, FONT,
+    };
+
+    /// LD V0 5, LD ST V0, LD I 0, DRW V0 V1 5 and a loop forever
+    const PROGRAM: [u8; 10] = [0x60, 0x05, 0xF0, 0x18, 0xA0, 0x00, 0xD0, 0x15, 0x12, 0x08];
+
+    /// Remembers what it was shown
+    #[derive(Default)]
+    struct TestDisplay {
+        /// Whether the frame buffer changed and whether the machine was paused
+        frames: Vec<(bool, bool)>,
+    }
+
+    impl DisplaySink for TestDisplay {
+        fn show(&mut self, _machine: &Machine, changed: bool, paused: bool) -> io::Result<()> {
+            self.frames.push((changed, paused));
+            Ok(())
+        }
+    }
+
+    /// Presses a key and toggles the pause at given frames, and quits after some frames
+    struct TestInput {
+        frame: u64,
+        frames: u64,
+        press: Option<(u64, usize)>,
+        pause: Option<u64>,
+    }
+
+    impl InputSource for TestInput {
+        fn poll(&mut self, machine: &mut Machine, paused: &mut bool) -> io::Result<bool> {
+            if self.frame == self.frames {
+                return Ok(true);
+            }
+            if let Some((_, key)) = self.press.filter(|&(frame, _)| frame == self.frame) {
+                machine.keypad.key_down(key);
+            }
+            if self.pause == Some(self.frame) {
+                *paused = !*paused;
+            }
+            self.frame += 1;
+            Ok(false)
+        }
+    }
+
+    #[derive(Default)]
+    struct TestAudio {
+        beeps: Vec<bool>,
+    }
+
+    impl AudioSink for TestAudio {
+        fn beep(&mut self, on: bool) {
+            self.beeps.push(on);
+        }
+    }
+
+    fn machine() -> Machine {
+        let mut cpu = CPU::new(
+            &FONT,
+            DEFAULT_MEMORY_SIZE,
+            DEFAULT_FRAME_BUFFER_SIZE,
+            DEFAULT_MAX_STACK_SIZE,
+        );
+        cpu.set_console(Box::new(NoConsole));
+        let mut machine = Machine::new(cpu, PROGRAM.to_vec(), 600);
+        machine.frame_timers = true;
+        machine
+    }
+
+    #[test]
+    fn run_shows_frames_plays_beeper_and_feeds_keys() {
+        let mut machine = machine();
+        let mut display = TestDisplay::default();
+        let mut input = TestInput {
+            frame: 0,
+            frames: 3,
+            press: Some((1, 0xA)),
+            pause: None,
+        };
+        let mut audio = TestAudio::default();
+        run(
+            &mut machine,
+            &mut display,
+            &mut input,
+            &mut audio,
+            &mut Unthrottled,
+        )
+        .expect("Run failed");
+
+        // Only the first frame draws
+        assert_eq!(
+            display.frames,
+            [(true, false), (false, false), (false, false)]
+        );
+        assert_eq!(audio.beeps, [true, true, true]);
+        assert!(machine.keypad.is_key_pressed(0xA));
+        assert_eq!(machine.frame(), 3);
+    }
+
+    #[test]
+    fn paused_frames_are_shown_without_running() {
+        let mut machine = machine();
+        let mut display = TestDisplay::default();
+        let mut input = TestInput {
+            frame: 0,
+            frames: 3,
+            press: None,
+            pause: Some(1),
+        };
+        let mut audio = TestAudio::default();
+        run(
+            &mut machine,
+            &mut display,
+            &mut input,
+            &mut audio,
+            &mut Unthrottled,
+        )
+        .expect("Run failed");
+
+        assert_eq!(
+            display.frames,
+            [(true, false), (false, true), (false, true)]
+        );
+        assert_eq!(audio.beeps, [true, false, false]);
+        assert_eq!(machine.frame(), 1);
+    }
+}
@@ -0,0 +1,736 @@
+use std::{
+    collections::HashSet,
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    cheats::{Cheat, CheatSearch, Comparison},
+    config::Config,
+    coverage,
+    machine::{Machine, FRAMES_PER_SECOND},
+    profiler,
+};
+
+const FRAME_DURATION: Duration = Duration::from_micros(1_000_000 / FRAMES_PER_SECOND as u64);
+/// Largest packet the client may send
+const PACKET_SIZE: usize = 0x4000;
+
+/// Signals reported when the target stops
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+/// Registers in the order of the `g` packet, with their size in bytes.
+/// Values are sent big endian like the opcodes in memory.
+const REGISTERS: [(&str, usize); 21] = [
+    ("v0", 1),
+    ("v1", 1),
+    ("v2", 1),
+    ("v3", 1),
+    ("v4", 1),
+    ("v5", 1),
+    ("v6", 1),
+    ("v7", 1),
+    ("v8", 1),
+    ("v9", 1),
+    ("va", 1),
+    ("vb", 1),
+    ("vc", 1),
+    ("vd", 1),
+    ("ve", 1),
+    ("vf", 1),
+    ("i", 2),
+    ("pc", 2),
+    ("sp", 1),
+    ("dt", 1),
+    ("st", 1),
+];
+
+/// Input from the client
+enum Input {
+    Packet(String),
+    /// Ctrl+C, sent as a single 0x03 byte outside of a packet
+    Interrupt,
+    Disconnected,
+}
+
+/// Answer to a packet
+enum Response {
+    Reply(String),
+    /// Resume execution, the reply is sent once the target stops again
+    Continue,
+    /// End the session after sending the reply, if there is one
+    Close(Option<String>),
+    /// Console output of a monitor command, sent as `O` packet followed by `OK`
+    Output(String),
+}
+
+/// Remote serial protocol stub, lets GDB or any other RSP client inspect and control the
+/// machine: registers, memory, single steps, software breakpoints and continue.
+/// Since GDB has no CHIP-8 architecture, the registers are described in `target.xml`.
+/// GDB can't read the symbols of a ROM either, so breakpoints by name and stack traces
+/// with names are monitor commands: `monitor break <name>`, `monitor delete <name>`
+/// and `monitor stack`. The cheat finder is run with `monitor cheat ...` as well.
+struct GdbStub {
+    machine: Machine,
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    breakpoints: HashSet<u16>,
+    /// Memory search of the `cheat` monitor commands
+    search: Option<CheatSearch>,
+    /// Whether packets are acknowledged with `+`, clients can turn this off
+    acknowledge: bool,
+}
+
+/// Wait for a debugger on the configured port and serve it until it detaches
+pub fn run(config: &Config, machine: Machine) -> io::Result<()> {
+    let port = config.gdb.unwrap_or_default();
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    println!(
+        "Waiting for debugger on {}, e.g. `target remote {}`",
+        listener.local_addr()?,
+        listener.local_addr()?
+    );
+    let (stream, address) = listener.accept()?;
+    println!("Debugger connected from {}", address);
+    let mut stub = GdbStub::new(machine, stream)?;
+    stub.serve()?;
+    println!("Debugger detached after {} frames", stub.machine.frame());
+    if let Some(path) = &config.profile {
+        profiler::save_report(&stub.machine.cpu, &stub.machine.symbols, path);
+    }
+    if let Some(path) = &config.coverage {
+        coverage::save_report(
+            &stub.machine.cpu,
+            &stub.machine.symbols,
+            stub.machine.program().len(),
+            path,
+        );
+    }
+    Ok(())
+}
+
+impl GdbStub {
+    fn new(machine: Machine, stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            machine,
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            breakpoints: HashSet::new(),
+            search: None,
+            acknowledge: true,
+        })
+    }
+
+    fn serve(&mut self) -> io::Result<()> {
+        loop {
+            let packet = match self.read_input()? {
+                Input::Packet(packet) => packet,
+                // The target is already stopped
+                Input::Interrupt => continue,
+                Input::Disconnected => return Ok(()),
+            };
+            match self.handle_packet(&packet) {
+                Response::Reply(reply) => self.send(&reply)?,
+                Response::Continue => {
+                    let reply = self.resume()?;
+                    self.send(&reply)?;
+                }
+                Response::Close(reply) => {
+                    if let Some(reply) = reply {
+                        self.send(&reply)?;
+                    }
+                    return Ok(());
+                }
+                Response::Output(text) => {
+                    self.send(&format!("O{}", encode_hex(text.as_bytes())))?;
+                    self.send("OK")?;
+                }
+            }
+        }
+    }
+
+    fn handle_packet(&mut self, packet: &str) -> Response {
+        let command = packet.chars().next().unwrap_or_default();
+        let arguments = &packet[command.len_utf8().min(packet.len())..];
+        let reply = match command {
+            '?' => stop_reply(SIGTRAP),
+            'g' => self.read_registers(),
+            'p' => self.read_register(arguments),
+            'm' => self.read_memory(arguments),
+            'M' => self.write_memory(arguments),
+            's' => {
+                self.machine.emulate_cycle();
+                stop_reply(self.stop_signal().unwrap_or(SIGTRAP))
+            }
+            'c' => return Response::Continue,
+            'Z' => self.change_breakpoint(arguments, true),
+            'z' => self.change_breakpoint(arguments, false),
+            'H' => String::from("OK"),
+            'D' => return Response::Close(Some(String::from("OK"))),
+            'k' => return Response::Close(None),
+            _ => {
+                if let Some(command) = packet.strip_prefix("qRcmd,") {
+                    return match decode_hex(command) {
+                        Some(command) => Response::Output(
+                            self.monitor_command(&String::from_utf8_lossy(&command)),
+                        ),
+                        None => Response::Reply(String::from("E01")),
+                    };
+                }
+                self.handle_query(packet)
+            }
+        };
+        Response::Reply(reply)
+    }
+
+    /// General queries, unsupported ones get an empty reply
+    fn handle_query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return format!("PacketSize={:x};qXfer:features:read+", PACKET_SIZE);
+        }
+        if let Some(arguments) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            return read_target_description(arguments);
+        }
+        match packet {
+            "QStartNoAckMode" => {
+                self.acknowledge = false;
+                String::from("OK")
+            }
+            "qAttached" => String::from("1"),
+            "qC" => String::from("QC1"),
+            "qfThreadInfo" => String::from("m1"),
+            "qsThreadInfo" => String::from("l"),
+            _ => String::new(),
+        }
+    }
+
+    /// Run a `monitor` command and return its output
+    fn monitor_command(&mut self, command: &str) -> String {
+        let symbols = &self.machine.symbols;
+        let mut words = command.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some(action @ ("break" | "delete")), Some(location)) => {
+                let Some(address) = symbols.resolve(location) else {
+                    return format!("No symbol {}\n", location);
+                };
+                if action == "break" {
+                    self.breakpoints.insert(address);
+                    format!(
+                        "Breakpoint at {:04X} {}\n",
+                        address,
+                        symbols.describe(address)
+                    )
+                } else {
+                    self.breakpoints.remove(&address);
+                    format!("Deleted breakpoint at {:04X}\n", address)
+                }
+            }
+            (Some("stack"), None) => {
+                let cpu = &self.machine.cpu;
+                let mut output = format!("#0 {:04X} {}\n", cpu.pc(), symbols.describe(cpu.pc()));
+                for (depth, &address) in cpu.stack().iter().rev().enumerate() {
+                    output += &format!(
+                        "#{} {:04X} {}\n",
+                        depth + 1,
+                        address,
+                        symbols.describe(address)
+                    );
+                }
+                output
+            }
+            (Some("cheat"), Some(action)) => self.cheat_command(action, words.collect()),
+            _ => String::from(
+                "Commands: break <symbol or address>, delete <symbol or address>, stack,\n\
+                 cheat search|equal <value>|changed|unchanged|increased|decreased|list,\n\
+                 cheat freeze|poke <address> <value> [name], cheat remove <address>, cheat show\n",
+            ),
+        }
+    }
+
+    /// `monitor cheat ...`: search memory for values and set cheats. Addresses are hex or
+    /// symbols, values decimal or hex with `0x`.
+    fn cheat_command(&mut self, action: &str, arguments: Vec<&str>) -> String {
+        let memory = self.machine.cpu.memory();
+        let comparison = match (action, arguments.first()) {
+            ("equal", Some(value)) => match parse_value(value) {
+                Some(value) => Some(Comparison::Equal(value)),
+                None => return format!("Invalid value {}\n", value),
+            },
+            ("changed", _) => Some(Comparison::Changed),
+            ("unchanged", _) => Some(Comparison::Unchanged),
+            ("increased", _) => Some(Comparison::Increased),
+            ("decreased", _) => Some(Comparison::Decreased),
+            _ => None,
+        };
+        if let Some(comparison) = comparison {
+            return match &mut self.search {
+                Some(search) => {
+                    search.filter(memory, comparison);
+                    list_candidates(search, memory)
+                }
+                None => String::from("Start a search with `cheat search` first\n"),
+            };
+        }
+
+        let Some(cheats) = &mut self.machine.cheats else {
+            return String::new();
+        };
+        match (action, &arguments[..]) {
+            ("search", []) => {
+                let search = CheatSearch::new(memory);
+                let output = format!("{} candidates\n", search.candidates().len());
+                self.search = Some(search);
+                output
+            }
+            ("list", []) => match &self.search {
+                Some(search) => list_candidates(search, memory),
+                None => String::from("Start a search with `cheat search` first\n"),
+            },
+            (kind @ ("freeze" | "poke"), [address, value, name @ ..]) => {
+                let (Some(address), Some(value)) =
+                    (self.machine.symbols.resolve(address), parse_value(value))
+                else {
+                    return format!("Invalid address {} or value {}\n", address, value);
+                };
+                let cheat = Cheat {
+                    address,
+                    value,
+                    frozen: kind == "freeze",
+                    name: name.join(" "),
+                };
+                match cheats.add(cheat) {
+                    Ok(()) => format!("Saved cheat at {:04X} to {}\n", address, cheats.path()),
+                    Err(error) => format!("Couldn't save {}: {}\n", cheats.path(), error),
+                }
+            }
+            ("remove", [address]) => {
+                let Some(address) = self.machine.symbols.resolve(address) else {
+                    return format!("Invalid address {}\n", address);
+                };
+                match cheats.remove(address) {
+                    Ok(true) => format!("Removed cheat at {:04X}\n", address),
+                    Ok(false) => format!("No cheat at {:04X}\n", address),
+                    Err(error) => format!("Couldn't save {}: {}\n", cheats.path(), error),
+                }
+            }
+            ("show", []) => {
+                let mut output = format!("Cheats in {}:\n", cheats.path());
+                for cheat in cheats.cheats() {
+                    output += &format!(
+                        "{:04X} = {:02X} {} {}\n",
+                        cheat.address,
+                        cheat.value,
+                        if cheat.frozen { "freeze" } else { "poke" },
+                        cheat.name
+                    );
+                }
+                output
+            }
+            _ => format!("Unknown cheat command {}\n", action),
+        }
+    }
+
+    /// Run at the original speed until a breakpoint is hit, the CPU panics or the client
+    /// interrupts, and return the stop reply
+    fn resume(&mut self) -> io::Result<String> {
+        // Leave a breakpoint at the current instruction before checking for the next one
+        self.machine.emulate_cycle();
+        let mut next_frame = Instant::now() + FRAME_DURATION;
+        loop {
+            for _ in 0..self.machine.cycles_per_frame() {
+                if let Some(signal) = self.stop_signal() {
+                    return Ok(stop_reply(signal));
+                }
+                self.machine.emulate_cycle();
+            }
+            if self.interrupted()? {
+                return Ok(stop_reply(SIGINT));
+            }
+            if let Some(wait) = next_frame.checked_duration_since(Instant::now()) {
+                thread::sleep(wait);
+            }
+            next_frame += FRAME_DURATION;
+        }
+    }
+
+    /// Why execution has to stop before the next cycle, if it has to
+    fn stop_signal(&self) -> Option<u8> {
+        let cpu = &self.machine.cpu;
+        if cpu.panic_message().is_some() {
+            Some(SIGILL)
+        } else if self.breakpoints.contains(&cpu.pc()) {
+            Some(SIGTRAP)
+        } else {
+            None
+        }
+    }
+
+    /// Whether the client sent an interrupt while the target was running
+    fn interrupted(&mut self) -> io::Result<bool> {
+        if self.reader.buffer().is_empty() {
+            self.reader.get_ref().set_nonblocking(true)?;
+            let result = self.reader.fill_buf().map(|buffer| buffer.len());
+            self.reader.get_ref().set_nonblocking(false)?;
+            match result {
+                Ok(0) => return Err(io::Error::from(ErrorKind::UnexpectedEof)),
+                Ok(_) => (),
+                Err(error) if error.kind() == ErrorKind::WouldBlock => return Ok(false),
+                Err(error) => return Err(error),
+            }
+        }
+        let interrupted = self.reader.buffer().contains(&0x03);
+        if interrupted {
+            let length = self.reader.buffer().len();
+            self.reader.consume(length);
+        }
+        Ok(interrupted)
+    }
+
+    fn read_registers(&self) -> String {
+        (0..REGISTERS.len())
+            .map(|register| self.register_hex(register))
+            .collect()
+    }
+
+    fn read_register(&self, register: &str) -> String {
+        match usize::from_str_radix(register, 16) {
+            Ok(register) if register < REGISTERS.len() => self.register_hex(register),
+            _ => String::from("E01"),
+        }
+    }
+
+    fn register_hex(&self, register: usize) -> String {
+        let cpu = &self.machine.cpu;
+        match register {
+            0..=15 => format!("{:02x}", cpu.registers()[register]),
+            16 => format!("{:04x}", cpu.i_register()),
+            17 => format!("{:04x}", cpu.pc()),
+            18 => format!("{:02x}", cpu.stack().len()),
+            19 => format!("{:02x}", cpu.delay_timer()),
+            _ => format!("{:02x}", cpu.sound_timer()),
+        }
+    }
+
+    /// `m<address>,<length>`
+    fn read_memory(&self, arguments: &str) -> String {
+        let Some((address, length)) = parse_address_length(arguments) else {
+            return String::from("E01");
+        };
+        let range = address
+            .checked_add(length)
+            .and_then(|end| self.machine.cpu.memory().get(address..end));
+        match range {
+            Some(bytes) => encode_hex(bytes),
+            None => String::from("E02"),
+        }
+    }
+
+    /// `M<address>,<length>:<hex data>`
+    fn write_memory(&mut self, arguments: &str) -> String {
+        let Some((range, data)) = arguments.split_once(':') else {
+            return String::from("E01");
+        };
+        let (Some((address, length)), Some(data)) = (parse_address_length(range), decode_hex(data))
+        else {
+            return String::from("E01");
+        };
+        if data.len() != length || !self.machine.cpu.write_memory(address, &data) {
+            return String::from("E02");
+        }
+        String::from("OK")
+    }
+
+    /// `Z0,<address>,<kind>` and `z0,<address>,<kind>`, only software breakpoints
+    /// are supported
+    fn change_breakpoint(&mut self, arguments: &str, insert: bool) -> String {
+        let mut parts = arguments.split(',');
+        if parts.next() != Some("0") {
+            return String::new();
+        }
+        let Some(address) = parts
+            .next()
+            .and_then(|address| u16::from_str_radix(address, 16).ok())
+        else {
+            return String::from("E01");
+        };
+        if insert {
+            self.breakpoints.insert(address);
+        } else {
+            self.breakpoints.remove(&address);
+        }
+        String::from("OK")
+    }
+
+    /// Read the next packet or interrupt, acknowledging packets with a valid checksum
+    fn read_input(&mut self) -> io::Result<Input> {
+        loop {
+            let mut byte = [0];
+            if self.reader.read(&mut byte)? == 0 {
+                return Ok(Input::Disconnected);
+            }
+            match byte[0] {
+                0x03 => return Ok(Input::Interrupt),
+                b'$' => (),
+                // Acknowledgements and noise between packets
+                _ => continue,
+            }
+
+            // The data of the packet and the `#` after it
+            let mut data = Vec::new();
+            let mut packet = (&mut self.reader).take(PACKET_SIZE as u64 + 1);
+            if packet.read_until(b'#', &mut data)? == 0 {
+                return Ok(Input::Disconnected);
+            }
+            if data.pop() != Some(b'#') {
+                if data.len() < PACKET_SIZE {
+                    return Ok(Input::Disconnected);
+                }
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Packet longer than {:#x} bytes", PACKET_SIZE),
+                ));
+            }
+            let mut checksum = [0; 2];
+            self.reader.read_exact(&mut checksum)?;
+            let expected = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|checksum| u8::from_str_radix(checksum, 16).ok());
+            if expected != Some(checksum_of(&data)) {
+                if self.acknowledge {
+                    self.writer.write_all(b"-")?;
+                }
+                continue;
+            }
+            if self.acknowledge {
+                self.writer.write_all(b"+")?;
+            }
+            return Ok(Input::Packet(String::from_utf8_lossy(&data).into_owned()));
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        self.writer.write_all(packet.as_bytes())?;
+        self.writer.flush()
+    }
+}
+
+/// Number of search candidates listed with their value
+const LISTED_CANDIDATES: usize = 16;
+
+fn list_candidates(search: &CheatSearch, memory: &[u8]) -> String {
+    let candidates = search.candidates();
+    let mut output = format!("{} candidates\n", candidates.len());
+    for &address in candidates.iter().take(LISTED_CANDIDATES) {
+        let value = memory[address as usize];
+        output += &format!("{:04X} = {:02X} ({})\n", address, value, value);
+    }
+    output
+}
+
+/// Decimal, or hex with `0x`
+fn parse_value(value: &str) -> Option<u8> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+fn stop_reply(signal: u8) -> String {
+    format!("S{:02x}", signal)
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+fn parse_address_length(arguments: &str) -> Option<(usize, usize)> {
+    let (address, length) = arguments.split_once(',')?;
+    let address = usize::from_str_radix(address, 16).ok()?;
+    let length = usize::from_str_radix(length, 16).ok()?;
+    Some((address, length))
+}
+
+fn encode_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(data: &str) -> Option<Vec<u8>> {
+    if !data.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..data.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(data.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+/// Register description GDB reads before it accesses any registers
+fn target_description() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?>\n\
+         <!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n\
+         <target version=\"1.0\">\n\
+         <feature name=\"org.chip8.cpu\">\n",
+    );
+    for (number, (name, size)) in REGISTERS.iter().enumerate() {
+        let kind = match *name {
+            "pc" => "code_ptr",
+            "i" => "data_ptr",
+            _ => "int",
+        };
+        xml += &format!(
+            "<reg name=\"{}\" bitsize=\"{}\" regnum=\"{}\" type=\"{}\"/>\n",
+            name,
+            size * 8,
+            number,
+            kind
+        );
+    }
+    xml += "</feature>\n</target>\n";
+    xml
+}
+
+/// `<offset>,<length>` of the description, `m` marks a partial and `l` the last chunk
+fn read_target_description(arguments: &str) -> String {
+    let Some((offset, length)) = parse_address_length(arguments) else {
+        return String::from("E01");
+    };
+    let xml = target_description();
+    let start = offset.min(xml.len());
+    let end = (start + length).min(xml.len());
+    let marker = if end == xml.len() { 'l' } else { 'm' };
+    format!("{}{}", marker, &xml[start..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8::{
+        cpu::{NoConsole, CPU},
+        DEFAULT_FRAME_BUFFER_SIZE, DEFAULT_MAX_STACK_SIZE, DEFAULT_MEMORY_SIZE, FONT,
+    };
+
+    /// CALL 204, then JP 202 forever after returning from LD V1 7 and RET
+    const PROGRAM: [u8; 8] = [0x22, 0x04, 0x12, 0x02, 0x61, 0x07, 0x00, 0xEE];
+
+    /// Sends packets like GDB and returns the replies
+    struct Client {
+        stream: TcpStream,
+    }
+
+    impl Client {
+        fn request(&mut self, packet: &str) -> String {
+            let packet = format!("${}#{:02x}", packet, checksum_of(packet.as_bytes()));
+            self.stream.write_all(packet.as_bytes()).unwrap();
+            let mut ack = [0];
+            self.stream.read_exact(&mut ack).unwrap();
+            assert_eq!(ack[0], b'+');
+            self.reply()
+        }
+
+        fn reply(&mut self) -> String {
+            let mut reply = Vec::new();
+            let mut byte = [0];
+            while byte[0] != b'$' {
+                self.stream.read_exact(&mut byte).unwrap();
+            }
+            loop {
+                self.stream.read_exact(&mut byte).unwrap();
+                if byte[0] == b'#' {
+                    break;
+                }
+                reply.push(byte[0]);
+            }
+            let mut checksum = [0; 2];
+            self.stream.read_exact(&mut checksum).unwrap();
+            assert_eq!(
+                u8::from_str_radix(std::str::from_utf8(&checksum).unwrap(), 16),
+                Ok(checksum_of(&reply))
+            );
+            String::from_utf8(reply).unwrap()
+        }
+    }
+
+    /// Stub for the next connection to `listener`, debugging `PROGRAM`
+    fn accept(listener: &TcpListener) -> GdbStub {
+        let mut cpu = CPU::new(
+            &FONT,
+            DEFAULT_MEMORY_SIZE,
+            DEFAULT_FRAME_BUFFER_SIZE,
+            DEFAULT_MAX_STACK_SIZE,
+        );
+        cpu.set_console(Box::new(NoConsole));
+        let machine = Machine::new(cpu, PROGRAM.to_vec(), 600);
+        let (stream, _) = listener.accept().unwrap();
+        GdbStub::new(machine, stream).unwrap()
+    }
+
+    #[test]
+    fn stub_serves_registers_memory_steps_and_breakpoints() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut client = Client {
+                stream: TcpStream::connect(address).unwrap(),
+            };
+            let mut replies = Vec::new();
+            for packet in ["g", "s", "p12", "m204,2", "M300,2:abcd", "m300,2"] {
+                replies.push(client.request(packet));
+            }
+            replies.push(client.request(&format!("qRcmd,{}", encode_hex(b"stack"))));
+            replies.push(client.reply());
+            for packet in ["Z0,202,2", "c", "p11", "p12", "p1", "z0,202,2", "D"] {
+                replies.push(client.request(packet));
+            }
+            replies
+        });
+
+        let mut stub = accept(&listener);
+        stub.serve().unwrap();
+        let replies = client.join().unwrap();
+
+        // V0-VF, I, PC and an empty stack
+        assert_eq!(replies[0], format!("{}00000200000000", "00".repeat(16)));
+        assert_eq!(replies[1], "S05");
+        // SP is the depth of the stack inside the subroutine
+        assert_eq!(replies[2], "01");
+        assert_eq!(replies[3], "6107");
+        assert_eq!(replies[4], "OK");
+        assert_eq!(replies[5], "abcd");
+        let stack = decode_hex(&replies[6][1..]).unwrap();
+        assert_eq!(String::from_utf8(stack).unwrap().lines().count(), 2);
+        assert_eq!(replies[7], "OK");
+        assert_eq!(replies[8], "OK");
+        // Stopped at the breakpoint after returning from the subroutine
+        assert_eq!(replies[9], "S05");
+        assert_eq!(replies[10], "0202");
+        assert_eq!(replies[11], "00");
+        assert_eq!(replies[12], "07");
+        assert_eq!(replies[13], "OK");
+        assert_eq!(replies[14], "OK");
+        assert_eq!(stub.machine.cpu.memory()[0x300..0x302], [0xAB, 0xCD]);
+    }
+
+    #[test]
+    fn packets_longer_than_advertised_drop_the_connection() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            let packet = format!("$m{}", "0".repeat(PACKET_SIZE));
+            // The stub may close the connection before it read everything
+            let _ = stream.write_all(packet.as_bytes());
+        });
+
+        let mut stub = accept(&listener);
+        let error = stub.serve().expect_err("Long packet was accepted");
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        client.join().unwrap();
+    }
+}
@@ -0,0 +1,126 @@
+use std::{io, process};
+
+use crate::{
+    capture::Recorder,
+    config::Config,
+    coverage,
+    frontend::{self, DisplaySink, FrameLimit, Silence, Unthrottled},
+    machine::{Machine, FRAMES_PER_SECOND},
+    profiler, screenshot,
+};
+
+/// Frames to run without a movie if no number was configured
+const DEFAULT_FRAMES: u64 = 600;
+
+/// Adds every frame to the capture, if one is running
+struct CaptureDisplay {
+    recorder: Option<Recorder>,
+}
+
+impl DisplaySink for CaptureDisplay {
+    fn show(&mut self, machine: &Machine, _changed: bool, _paused: bool) -> io::Result<()> {
+        if let Some(recorder) = &mut self.recorder {
+            let (width, height) = machine.cpu.display_size();
+            if let Err(error) = recorder.add_frame(machine.cpu.frame_buffer(), width, height, 1) {
+                println!("Capture failed: {}", error);
+                self.recorder = None;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Run the ROM without a window for the configured number of frames, capturing the frames
+/// if requested, then save a screenshot of the final frame if one was requested.
+/// With a movie and no frame count the run lasts one second longer than the movie.
+pub fn run(config: &Config, mut machine: Machine) {
+    let frames = config.frames.unwrap_or_else(|| match &machine.movie {
+        Some(movie) => movie.last_frame() + FRAMES_PER_SECOND as u64,
+        None => DEFAULT_FRAMES,
+    });
+
+    let recorder = config.record.as_ref().and_then(|path| {
+        let (width, height) = machine.cpu.display_size();
+        let scale = config.screenshot_scale.max(1);
+        match Recorder::create(path, width, height, scale, &config.palette) {
+            Ok(recorder) => Some(recorder),
+            Err(error) => {
+                println!("Couldn't start capture {}: {}", path, error);
+                None
+            }
+        }
+    });
+
+    let mut display = CaptureDisplay { recorder };
+    let mut limit = FrameLimit::new(frames);
+    frontend::run(
+        &mut machine,
+        &mut display,
+        &mut limit,
+        &mut Silence,
+        &mut Unthrottled,
+    )
+    .expect("Headless run failed");
+    println!("Ran {} frames", limit.frames_run());
+    if let Some(path) = &config.profile {
+        profiler::save_report(&machine.cpu, &machine.symbols, path);
+    }
+    // Without a report the coverage is only tracked for the minimum
+    let coverage = match &config.coverage {
+        Some(path) => coverage::save_report(
+            &machine.cpu,
+            &machine.symbols,
+            machine.program().len(),
+            path,
+        ),
+        None => machine.cpu.coverage.as_ref().map(|coverage| {
+            let summary = coverage.summary(machine.program().len());
+            println!("Coverage: {:.1}%", summary.percent());
+            summary
+        }),
+    };
+
+    if let (Some(recorder), Some(path)) = (display.recorder, &config.record) {
+        match recorder.finish() {
+            Ok(written) => println!("Captured {} frames to {}", written, path),
+            Err(error) => println!("Couldn't finish capture {}: {}", path, error),
+        }
+    }
+
+    if let Some(path) = &config.screenshot {
+        let (width, height) = machine.cpu.display_size();
+        let scale = config.screenshot_scale.max(1);
+        match screenshot::save_png(
+            path,
+            machine.cpu.frame_buffer(),
+            width,
+            height,
+            scale,
+            &config.palette,
+        ) {
+            Ok(()) => println!("Saved screenshot to {}", path),
+            Err(error) => println!("Couldn't save screenshot to {}: {}", path, error),
+        }
+    }
+
+    if let Some(minimum) = config.coverage_min {
+        match coverage {
+            Some(coverage) if coverage.percent() < minimum => {
+                println!(
+                    "Coverage {:.1}% is below the required {}%",
+                    coverage.percent(),
+                    minimum
+                );
+                process::exit(1);
+            }
+            Some(_) => (),
+            None => {
+                println!(
+                    "Coverage couldn't be checked against the required {}%",
+                    minimum
+                );
+                process::exit(1);
+            }
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use speedy2d::{color::Color, shape::Rectangle, Graphics2D};
+
+use crate::{
+    overlay::{self, GLYPH_HEIGHT},
+    profiler::Profiler,
+};
+
+/// Bytes per row of the map, 4 KiB of memory make a square
+const COLUMNS: usize = 64;
+const BORDER: f32 = 2.0;
+const LABEL_SCALE: f32 = 2.0;
+
+/// Width and height in screen pixels the map takes up with `cell` sized squares
+pub fn size(profiler: &Profiler, cell: f32) -> (f32, f32) {
+    let rows = profiler.address_counts().len().div_ceil(COLUMNS);
+    (
+        COLUMNS as f32 * cell + BORDER * 2.0,
+        GLYPH_HEIGHT * LABEL_SCALE + rows as f32 * cell + BORDER * 2.0,
+    )
+}
+
+/// Draw the execution counts of all memory addresses as a grid with its top left corner at
+/// (x, y), one `cell` sized square per byte. Colors go from dark blue for rarely executed
+/// addresses to yellow for the hottest one on a logarithmic scale, so loops stand out
+/// without hiding code that only runs once. The PC is marked white.
+pub fn draw(graphics: &mut Graphics2D, profiler: &Profiler, pc: u16, x: f32, y: f32, cell: f32) {
+    let counts = profiler.address_counts();
+    let (width, height) = size(profiler, cell);
+    let map_y = y + GLYPH_HEIGHT * LABEL_SCALE;
+    graphics.draw_rectangle(
+        Rectangle::from_tuples((x, y), (x + width, y + height)),
+        Color::from_rgba(0.0, 0.0, 0.0, 0.8),
+    );
+    overlay::draw_text(
+        graphics,
+        &format!("HEATMAP {}", profiler.total()),
+        x + BORDER,
+        y + BORDER,
+        LABEL_SCALE,
+        Color::YELLOW,
+    );
+
+    let max = counts.iter().copied().max().unwrap_or(0);
+    if max == 0 {
+        return;
+    }
+    let log_max = (max as f32).ln_1p();
+    for (address, &count) in counts.iter().enumerate() {
+        let color = if address == pc as usize {
+            Color::WHITE
+        } else if count == 0 {
+            continue;
+        } else {
+            heat_color((count as f32).ln_1p() / log_max)
+        };
+        let cell_x = x + BORDER + (address % COLUMNS) as f32 * cell;
+        let cell_y = map_y + BORDER + (address / COLUMNS) as f32 * cell;
+        graphics.draw_rectangle(
+            Rectangle::from_tuples((cell_x, cell_y), (cell_x + cell, cell_y + cell)),
+            color,
+        );
+    }
+}
+
+/// Blue over red to yellow for a heat between 0 and 1
+fn heat_color(heat: f32) -> Color {
+    if heat < 0.5 {
+        let t = heat * 2.0;
+        Color::from_rgb(0.8 * t + 0.1, 0.0, 0.6 * (1.0 - t) + 0.1)
+    } else {
+        let t = (heat - 0.5) * 2.0;
+        Color::from_rgb(0.9, t, 0.1)
+    }
+}
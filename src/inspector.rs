@@ -0,0 +1,174 @@
+use speedy2d::{color::Color, shape::Rectangle, Graphics2D};
+
+use crate::{
+    cpu::CPU,
+    disassembler,
+    overlay::{self, GLYPH_HEIGHT, GLYPH_WIDTH},
+    symbols::Symbols,
+};
+
+/// Characters per line, wide enough for a memory row
+const COLUMNS: usize = 29;
+const PADDING: f32 = 2.0;
+
+const MEMORY_ROW_BYTES: usize = 8;
+/// Memory rows shown around the PC and from I on
+const PC_MEMORY_ROWS: usize = 8;
+const I_MEMORY_ROWS: usize = 4;
+/// Instructions disassembled before and after the PC
+const CODE_BEFORE: usize = 4;
+const CODE_AFTER: usize = 6;
+/// Stack entries shown, the most recent first
+const STACK_ENTRIES: usize = 6;
+
+const TEXT_COLOR: Color = Color::LIGHT_GRAY;
+const HEADING_COLOR: Color = Color::YELLOW;
+const PC_COLOR: Color = Color::GREEN;
+const I_COLOR: Color = Color::CYAN;
+const SPRITE_COLOR: Color = Color::MAGENTA;
+
+/// A part of a line with its own color
+type Span = (String, Color);
+
+/// Width in screen pixels the panel takes up at the given font scale
+pub fn width(scale: f32) -> f32 {
+    (COLUMNS as f32 * GLYPH_WIDTH + PADDING * 2.0) * scale
+}
+
+/// Draw the live state of the CPU: registers, timers, stack, a disassembly around the PC
+/// and memory around the PC and I. The panel is a column of the full `height` at `x`.
+pub fn draw(
+    graphics: &mut Graphics2D,
+    cpu: &CPU,
+    symbols: &Symbols,
+    x: f32,
+    height: f32,
+    scale: f32,
+) {
+    graphics.draw_rectangle(
+        Rectangle::from_tuples((x, 0.0), (x + width(scale), height)),
+        Color::from_rgb(0.08, 0.08, 0.08),
+    );
+    for (row, line) in lines(cpu, symbols).iter().enumerate() {
+        let y = (PADDING + row as f32 * GLYPH_HEIGHT) * scale;
+        let mut column = 0;
+        for (text, color) in line {
+            let span_x = x + (PADDING + column as f32 * GLYPH_WIDTH) * scale;
+            overlay::draw_text(graphics, text, span_x, y, scale, *color);
+            column += text.chars().count();
+        }
+    }
+}
+
+fn lines(cpu: &CPU, symbols: &Symbols) -> Vec<Vec<Span>> {
+    let mut lines = vec![
+        vec![
+            (format!("PC {:04X}", cpu.pc()), PC_COLOR),
+            (format!("  I {:04X}", cpu.i_register()), I_COLOR),
+        ],
+        text(format!(
+            "DT {:>3}  ST {:>3}",
+            cpu.delay_timer(),
+            cpu.sound_timer()
+        )),
+        Vec::new(),
+    ];
+    for (index, values) in cpu.registers().chunks(4).enumerate() {
+        let registers: Vec<String> = values
+            .iter()
+            .enumerate()
+            .map(|(offset, value)| format!("V{:X} {:02X}", index * 4 + offset, value))
+            .collect();
+        lines.push(text(registers.join("  ")));
+    }
+
+    lines.push(Vec::new());
+    lines.push(heading(format!("STACK  SP {}", cpu.stack().len())));
+    for (depth, address) in cpu.stack().iter().rev().take(STACK_ENTRIES).enumerate() {
+        let index = cpu.stack().len() - 1 - depth;
+        let location = if symbols.is_empty() {
+            String::new()
+        } else {
+            symbols.describe(*address)
+        };
+        lines.push(text(format!("{:>2} {:04X} {}", index, address, location)));
+    }
+
+    lines.push(Vec::new());
+    lines.push(heading(String::from("CODE")));
+    let pc = cpu.pc() as usize;
+    let first = pc.saturating_sub(CODE_BEFORE * 2);
+    for address in (first..=pc + CODE_AFTER * 2).step_by(2) {
+        let Some(instruction) = cpu.instruction_at(address) else {
+            break;
+        };
+        if let Some(name) = symbols.name(address as u16) {
+            lines.push(text(format!(" {}:", name)));
+        }
+        let line = format!(
+            "{}{:04X} {:04X} {}",
+            if address == pc { '>' } else { ' ' },
+            address,
+            instruction,
+            disassembler::disassemble_with_symbols(instruction, symbols)
+        );
+        let color = if address == pc { PC_COLOR } else { TEXT_COLOR };
+        lines.push(vec![(line, color)]);
+    }
+
+    lines.push(Vec::new());
+    lines.push(heading(String::from("MEMORY AT PC")));
+    let pc_row = pc / MEMORY_ROW_BYTES;
+    lines.extend(memory_lines(cpu, pc_row.saturating_sub(2), PC_MEMORY_ROWS));
+    lines.push(heading(String::from("MEMORY AT I")));
+    let i_row = cpu.i_register() as usize / MEMORY_ROW_BYTES;
+    lines.extend(memory_lines(cpu, i_row, I_MEMORY_ROWS));
+    lines
+}
+
+/// Hex dump of `rows` rows starting at row `first`, highlighting the bytes of the
+/// instruction at the PC, the byte at I and the sprite drawn last
+fn memory_lines(cpu: &CPU, first: usize, rows: usize) -> Vec<Vec<Span>> {
+    let memory = cpu.memory();
+    let pc = cpu.pc() as usize;
+    let i = cpu.i_register() as usize;
+    let sprite = cpu
+        .last_sprite()
+        .map(|(address, length)| address as usize..address as usize + length as usize);
+
+    let mut lines = Vec::new();
+    for row in first..first + rows {
+        let start = row * MEMORY_ROW_BYTES;
+        if start >= memory.len() {
+            break;
+        }
+        let end = (start + MEMORY_ROW_BYTES).min(memory.len());
+        let mut line = vec![(format!("{:04X}", start), TEXT_COLOR)];
+        for (address, value) in memory[start..end].iter().enumerate() {
+            let address = start + address;
+            let color = if address == pc || address == pc + 1 {
+                PC_COLOR
+            } else if address == i {
+                I_COLOR
+            } else if sprite
+                .as_ref()
+                .is_some_and(|sprite| sprite.contains(&address))
+            {
+                SPRITE_COLOR
+            } else {
+                TEXT_COLOR
+            };
+            line.push((format!(" {:02X}", value), color));
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+fn text(line: String) -> Vec<Span> {
+    vec![(line, TEXT_COLOR)]
+}
+
+fn heading(line: String) -> Vec<Span> {
+    vec![(line, HEADING_COLOR)]
+}
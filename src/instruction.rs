@@ -0,0 +1,146 @@
+/// A decoded instruction. The CPU executes it, and the disassembler and the analysis
+/// describe it, so all of them agree on what an opcode means.
+///
+/// Registers are the X and Y nibbles of the opcode, `u8` values the NN byte and `u16`
+/// values the NNN address.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Instruction {
+    /// 00E0
+    ClearScreen,
+    /// 00EE
+    Return,
+    /// 1NNN
+    Jump(u16),
+    /// 2NNN
+    Call(u16),
+    /// 3XNN
+    SkipIfEqual(u8, u8),
+    /// 4XNN
+    SkipIfNotEqual(u8, u8),
+    /// 5XY0
+    SkipIfRegistersEqual(u8, u8),
+    /// 6XNN
+    Load(u8, u8),
+    /// 7XNN
+    Add(u8, u8),
+    /// 8XY0
+    LoadRegister(u8, u8),
+    /// 8XY1
+    Or(u8, u8),
+    /// 8XY2
+    And(u8, u8),
+    /// 8XY3
+    Xor(u8, u8),
+    /// 8XY4
+    AddRegister(u8, u8),
+    /// 8XY5
+    Subtract(u8, u8),
+    /// 8XY6
+    ShiftRight(u8, u8),
+    /// 8XY7
+    SubtractFrom(u8, u8),
+    /// 8XYE
+    ShiftLeft(u8, u8),
+    /// 9XY0
+    SkipIfRegistersNotEqual(u8, u8),
+    /// ANNN
+    LoadI(u16),
+    /// BNNN
+    JumpWithOffset(u16),
+    /// CXNN
+    Random(u8, u8),
+    /// DXYN
+    Draw(u8, u8, u8),
+    /// EX9E
+    SkipIfPressed(u8),
+    /// EXA1
+    SkipIfNotPressed(u8),
+    /// FX07
+    LoadDelayTimer(u8),
+    /// FX0A
+    WaitForKey(u8),
+    /// FX15
+    SetDelayTimer(u8),
+    /// FX18
+    SetSoundTimer(u8),
+    /// FX1E
+    AddToI(u8),
+    /// FX33
+    StoreDecimal(u8),
+    /// FX55
+    StoreRegisters(u8),
+    /// FX65
+    LoadRegisters(u8),
+    /// An opcode the CPU doesn't know, it panics there
+    Unknown(u16),
+}
+
+impl Instruction {
+    pub fn decode(opcode: u16) -> Self {
+        let x = ((opcode & 0x0F00) >> 8) as u8;
+        let y = ((opcode & 0x00F0) >> 4) as u8;
+        let n = (opcode & 0x000F) as u8;
+        let nn = (opcode & 0x00FF) as u8;
+        let nnn = opcode & 0x0FFF;
+
+        match opcode >> 12 {
+            0x0 => match opcode {
+                0x00E0 => Instruction::ClearScreen,
+                0x00EE => Instruction::Return,
+                _ => Instruction::Unknown(opcode),
+            },
+            0x1 => Instruction::Jump(nnn),
+            0x2 => Instruction::Call(nnn),
+            0x3 => Instruction::SkipIfEqual(x, nn),
+            0x4 => Instruction::SkipIfNotEqual(x, nn),
+            // The last nibble isn't checked, like on the original interpreter
+            0x5 => Instruction::SkipIfRegistersEqual(x, y),
+            0x6 => Instruction::Load(x, nn),
+            0x7 => Instruction::Add(x, nn),
+            0x8 => match n {
+                0x0 => Instruction::LoadRegister(x, y),
+                0x1 => Instruction::Or(x, y),
+                0x2 => Instruction::And(x, y),
+                0x3 => Instruction::Xor(x, y),
+                0x4 => Instruction::AddRegister(x, y),
+                0x5 => Instruction::Subtract(x, y),
+                0x6 => Instruction::ShiftRight(x, y),
+                0x7 => Instruction::SubtractFrom(x, y),
+                0xE => Instruction::ShiftLeft(x, y),
+                _ => Instruction::Unknown(opcode),
+            },
+            0x9 => Instruction::SkipIfRegistersNotEqual(x, y),
+            0xA => Instruction::LoadI(nnn),
+            0xB => Instruction::JumpWithOffset(nnn),
+            0xC => Instruction::Random(x, nn),
+            0xD => Instruction::Draw(x, y, n),
+            0xE => match nn {
+                0x9E => Instruction::SkipIfPressed(x),
+                0xA1 => Instruction::SkipIfNotPressed(x),
+                _ => Instruction::Unknown(opcode),
+            },
+            _ => match nn {
+                0x07 => Instruction::LoadDelayTimer(x),
+                0x0A => Instruction::WaitForKey(x),
+                0x15 => Instruction::SetDelayTimer(x),
+                0x18 => Instruction::SetSoundTimer(x),
+                0x1E => Instruction::AddToI(x),
+                0x33 => Instruction::StoreDecimal(x),
+                0x55 => Instruction::StoreRegisters(x),
+                0x65 => Instruction::LoadRegisters(x),
+                _ => Instruction::Unknown(opcode),
+            },
+        }
+    }
+
+    /// The address of jumps, calls and `LD I`, which can be named by a symbol
+    pub fn address(&self) -> Option<u16> {
+        match *self {
+            Instruction::Jump(address)
+            | Instruction::Call(address)
+            | Instruction::LoadI(address)
+            | Instruction::JumpWithOffset(address) => Some(address),
+            _ => None,
+        }
+    }
+}
@@ -1,40 +1,40 @@
-use speedy2d::window::VirtualKeyCode;
-use std::collections::HashMap;
-
-lazy_static::lazy_static! {
-    /// Real Keypad:
-    /// 1 2 3 C
-    /// 4 5 6 D
-    /// 7 8 9 E
-    /// A 0 B F
-    /// Emulated Keypad on computer keyboard:
-    /// 1 2 3 4
-    /// Q W E R
-    /// A S D F
-    /// ` Z X C
-    pub static ref KEY_MAP: HashMap<VirtualKeyCode, usize> = {
-        let mut map = HashMap::new();
-        map.insert(VirtualKeyCode::Key1, 1);
-        map.insert(VirtualKeyCode::Key2, 2);
-        map.insert(VirtualKeyCode::Key3, 3);
-        map.insert(VirtualKeyCode::Key4, 0xC);
-        map.insert(VirtualKeyCode::Q, 4);
-        map.insert(VirtualKeyCode::W, 5);
-        map.insert(VirtualKeyCode::E, 6);
-        map.insert(VirtualKeyCode::R, 0xD);
-        map.insert(VirtualKeyCode::A, 7);
-        map.insert(VirtualKeyCode::S, 8);
-        map.insert(VirtualKeyCode::D, 9);
-        map.insert(VirtualKeyCode::F, 0xE);
-        map.insert(VirtualKeyCode::Grave, 0xA);
-        map.insert(VirtualKeyCode::Z, 0);
-        map.insert(VirtualKeyCode::X, 0xB);
-        map.insert(VirtualKeyCode::C, 0xF);
-        map
+/// Keypad key of a character typed on the computer keyboard, for frontends that receive
+/// characters. The window maps key codes the same way.
+///
+/// Real Keypad:
+/// 1 2 3 C
+/// 4 5 6 D
+/// 7 8 9 E
+/// A 0 B F
+/// Emulated Keypad on computer keyboard:
+/// 1 2 3 4
+/// Q W E R
+/// A S D F
+/// ` Z X C
+pub fn key_for_char(c: char) -> Option<usize> {
+    let id = match c.to_ascii_lowercase() {
+        '1' => 1,
+        '2' => 2,
+        '3' => 3,
+        '4' => 0xC,
+        'q' => 4,
+        'w' => 5,
+        'e' => 6,
+        'r' => 0xD,
+        'a' => 7,
+        's' => 8,
+        'd' => 9,
+        'f' => 0xE,
+        '`' => 0xA,
+        'z' => 0,
+        'x' => 0xB,
+        'c' => 0xF,
+        _ => return None,
     };
+    Some(id)
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct Keypad {
     keys: [bool; 16],
 }
@@ -64,4 +64,20 @@ impl Keypad {
     pub fn is_key_pressed(&self, id: usize) -> bool {
         self.keys[id]
     }
+
+    /// All keys as a bit mask, bit N is set if key N is pressed
+    pub fn state(&self) -> u16 {
+        let mut state = 0;
+        for (id, &pressed) in self.keys.iter().enumerate() {
+            state |= (pressed as u16) << id;
+        }
+        state
+    }
+
+    /// Press exactly the keys whose bits are set in the mask, see `state`
+    pub fn set_state(&mut self, state: u16) {
+        for (id, key) in self.keys.iter_mut().enumerate() {
+            *key = state & (1 << id) != 0;
+        }
+    }
 }
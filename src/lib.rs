@@ -0,0 +1,65 @@
+//! The CHIP-8 core without a frontend, shared by the emulator, the Python bindings, the
+//! WebAssembly build and the libretro core. It doesn't depend on speedy2d, a clock or
+//! OS entropy, the frontends of the binary are only built with the `window` feature.
+//!
+//! Without the `std` feature the CPU builds with `no_std` and the `alloc` feature, e.g.
+//! for microcontrollers. The frontend then provides the output with `cpu::Console`,
+//! and the profiler, coverage, symbols and save state files aren't available.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+#[cfg(feature = "alloc")]
+pub mod clock;
+#[cfg(feature = "std")]
+pub mod coverage;
+#[cfg(feature = "alloc")]
+pub mod cpu;
+#[cfg(feature = "std")]
+pub mod disassembler;
+#[cfg(feature = "std")]
+pub mod environment;
+pub mod instruction;
+pub mod keypad;
+#[cfg(feature = "libretro")]
+mod libretro;
+#[cfg(feature = "std")]
+pub mod profiler;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "alloc")]
+pub mod state;
+#[cfg(feature = "std")]
+pub mod symbols;
+#[cfg(feature = "wasm")]
+mod wasm;
+
+/// Number of frames per second of the original hardware, timers and the display run at this rate
+pub const FRAMES_PER_SECOND: u32 = 60;
+/// Speed of all frontends unless they are configured otherwise
+pub const DEFAULT_INSTRUCTIONS_PER_SECOND: u32 = 10000;
+
+pub const DEFAULT_MEMORY_SIZE: usize = 4 * 1024;
+pub const DEFAULT_FRAME_BUFFER_SIZE: usize = 64 * 32;
+pub const DEFAULT_MAX_STACK_SIZE: usize = 32;
+
+pub const FONT: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
@@ -0,0 +1,477 @@
+// The libretro API defines what the frontend has to pass to these functions
+#![allow(clippy::missing_safety_doc)]
+
+use std::{
+    ffi::{c_char, c_uint, c_void, CStr},
+    ptr, slice,
+    sync::Mutex,
+};
+
+use crate::{
+    clock::FrameClock, cpu::CPU, keypad::Keypad, state::SaveState, DEFAULT_FRAME_BUFFER_SIZE,
+    DEFAULT_INSTRUCTIONS_PER_SECOND, DEFAULT_MAX_STACK_SIZE, DEFAULT_MEMORY_SIZE, FONT,
+    FRAMES_PER_SECOND,
+};
+
+const RETRO_API_VERSION: c_uint = 1;
+const RETRO_ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+const RETRO_PIXEL_FORMAT_XRGB8888: c_uint = 1;
+const RETRO_DEVICE_JOYPAD: c_uint = 1;
+const RETRO_DEVICE_KEYBOARD: c_uint = 3;
+const RETRO_MEMORY_SYSTEM_RAM: c_uint = 2;
+const RETRO_REGION_NTSC: c_uint = 0;
+
+const SAMPLE_RATE: u32 = 44100;
+const BEEP_FREQUENCY: u32 = 440;
+const BEEP_VOLUME: i16 = 4000;
+/// Longest panic message kept in save states, libretro needs an upper bound of their size
+const MAX_PANIC_MESSAGE: usize = 256;
+
+/// CHIP-8 key of every RetroPad button, in the order of the RETRO_DEVICE_ID_JOYPAD ids
+/// B, Y, Select, Start, Up, Down, Left, Right, A, X, L, R, L2, R2, L3 and R3. The
+/// directions are on 2, 4, 6 and 8 and A on 5, like most games use them.
+const JOYPAD_KEYS: [usize; 16] = [
+    0x0, 0x3, 0xC, 0xD, 0x2, 0x8, 0x4, 0x6, 0x5, 0x1, 0x7, 0x9, 0xA, 0xB, 0xE, 0xF,
+];
+/// Keyboard keys (RETROK codes) of every CHIP-8 key, the same layout as the window:
+/// 1 2 3 4 / Q W E R / A S D F / ` Z X C
+const KEYBOARD_KEYS: [(c_uint, usize); 16] = [
+    (b'1' as c_uint, 0x1),
+    (b'2' as c_uint, 0x2),
+    (b'3' as c_uint, 0x3),
+    (b'4' as c_uint, 0xC),
+    (b'q' as c_uint, 0x4),
+    (b'w' as c_uint, 0x5),
+    (b'e' as c_uint, 0x6),
+    (b'r' as c_uint, 0xD),
+    (b'a' as c_uint, 0x7),
+    (b's' as c_uint, 0x8),
+    (b'd' as c_uint, 0x9),
+    (b'f' as c_uint, 0xE),
+    (b'`' as c_uint, 0xA),
+    (b'z' as c_uint, 0x0),
+    (b'x' as c_uint, 0xB),
+    (b'c' as c_uint, 0xF),
+];
+
+#[repr(C)]
+pub struct SystemInfo {
+    library_name: *const c_char,
+    library_version: *const c_char,
+    valid_extensions: *const c_char,
+    need_fullpath: bool,
+    block_extract: bool,
+}
+
+#[repr(C)]
+pub struct GameGeometry {
+    base_width: c_uint,
+    base_height: c_uint,
+    max_width: c_uint,
+    max_height: c_uint,
+    aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct SystemTiming {
+    fps: f64,
+    sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct SystemAvInfo {
+    geometry: GameGeometry,
+    timing: SystemTiming,
+}
+
+#[repr(C)]
+pub struct GameInfo {
+    path: *const c_char,
+    data: *const c_void,
+    size: usize,
+    meta: *const c_char,
+}
+
+type EnvironmentFn = unsafe extern "C" fn(command: c_uint, data: *mut c_void) -> bool;
+type VideoRefreshFn =
+    unsafe extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+type AudioSampleFn = unsafe extern "C" fn(left: i16, right: i16);
+type AudioSampleBatchFn = unsafe extern "C" fn(data: *const i16, frames: usize) -> usize;
+type InputPollFn = unsafe extern "C" fn();
+type InputStateFn =
+    unsafe extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+
+/// Functions of the frontend, it sets them before loading a game
+#[derive(Clone, Copy)]
+struct Callbacks {
+    environment: Option<EnvironmentFn>,
+    video_refresh: Option<VideoRefreshFn>,
+    audio_sample_batch: Option<AudioSampleBatchFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>,
+}
+
+static CALLBACKS: Mutex<Callbacks> = Mutex::new(Callbacks {
+    environment: None,
+    video_refresh: None,
+    audio_sample_batch: None,
+    input_poll: None,
+    input_state: None,
+});
+
+/// The loaded game. Frontends may call the core from different threads, though never
+/// at the same time.
+static CORE: Mutex<Option<Core>> = Mutex::new(None);
+
+struct Core {
+    cpu: CPU,
+    keypad: Keypad,
+    program: Vec<u8>,
+    clock: FrameClock,
+    /// The display in XRGB8888
+    pixels: Vec<u32>,
+    /// Stereo samples of one frame
+    audio: Vec<i16>,
+    /// Samples of the square wave played so far, so it continues across frames
+    beep_samples: u32,
+    /// Address and value of the enabled cheats, written every frame
+    cheats: Vec<Option<(u16, u8)>>,
+}
+
+impl Core {
+    fn new(program: Vec<u8>) -> Self {
+        let mut cpu = CPU::new(
+            &FONT,
+            DEFAULT_MEMORY_SIZE,
+            DEFAULT_FRAME_BUFFER_SIZE,
+            DEFAULT_MAX_STACK_SIZE,
+        );
+        cpu.set_program(&program);
+        Self {
+            cpu,
+            keypad: Keypad::new(),
+            program,
+            clock: FrameClock::new(DEFAULT_INSTRUCTIONS_PER_SECOND),
+            pixels: Vec::new(),
+            audio: Vec::new(),
+            beep_samples: 0,
+            cheats: Vec::new(),
+        }
+    }
+
+    fn read_input(&mut self, input_state: InputStateFn) {
+        let mut state = 0;
+        for (id, &key) in JOYPAD_KEYS.iter().enumerate() {
+            if unsafe { input_state(0, RETRO_DEVICE_JOYPAD, 0, id as c_uint) } != 0 {
+                state |= 1 << key;
+            }
+        }
+        for &(code, key) in &KEYBOARD_KEYS {
+            if unsafe { input_state(0, RETRO_DEVICE_KEYBOARD, 0, code) } != 0 {
+                state |= 1 << key;
+            }
+        }
+        self.keypad.set_state(state);
+    }
+
+    fn render(&mut self) -> (usize, usize) {
+        let (width, height) = self.cpu.display_size();
+        self.pixels.clear();
+        self.pixels.extend(
+            self.cpu.frame_buffer()[..width * height]
+                .iter()
+                .map(|&on| if on { 0x00FF_FFFF } else { 0 }),
+        );
+        (width, height)
+    }
+
+    /// A square wave while the sound timer runs, silence otherwise
+    fn render_audio(&mut self) {
+        let samples = SAMPLE_RATE / FRAMES_PER_SECOND;
+        let half_period = SAMPLE_RATE / BEEP_FREQUENCY / 2;
+        let beeping = self.cpu.sound_timer() > 0;
+        self.audio.clear();
+        for _ in 0..samples {
+            let sample = match (beeping, self.beep_samples / half_period % 2) {
+                (false, _) => 0,
+                (true, 0) => BEEP_VOLUME,
+                (true, _) => -BEEP_VOLUME,
+            };
+            self.audio.extend_from_slice(&[sample, sample]);
+            self.beep_samples = self.beep_samples.wrapping_add(1);
+        }
+    }
+
+    /// Snapshot for `retro_serialize`, with the panic message cut to `MAX_PANIC_MESSAGE`
+    fn save_state(&self) -> SaveState {
+        let mut cpu = self.cpu.save_state();
+        if let Some(message) = &mut cpu.panic_message {
+            let mut end = message.len().min(MAX_PANIC_MESSAGE);
+            while !message.is_char_boundary(end) {
+                end -= 1;
+            }
+            message.truncate(end);
+        }
+        SaveState {
+            cpu,
+            keys: self.keypad.state(),
+            cycle: self.clock.cycle(),
+        }
+    }
+
+    /// Size of the largest save state, which libretro needs for every state: with a full
+    /// stack and the longest panic message
+    fn serialize_size(&self) -> usize {
+        let mut state = self.save_state();
+        state.cpu.stack = vec![0; DEFAULT_MAX_STACK_SIZE];
+        state.cpu.panic_message = Some(" ".repeat(MAX_PANIC_MESSAGE));
+        state.to_bytes().len()
+    }
+}
+
+fn callbacks() -> Callbacks {
+    *CALLBACKS.lock().unwrap()
+}
+
+fn with_core<T: Default>(f: impl FnOnce(&mut Core) -> T) -> T {
+    match CORE.lock().unwrap().as_mut() {
+        Some(core) => f(core),
+        None => T::default(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut SystemInfo) {
+    *info = SystemInfo {
+        library_name: c"CHIP-8".as_ptr(),
+        library_version: c"0.1.0".as_ptr(),
+        valid_extensions: c"ch8".as_ptr(),
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut SystemAvInfo) {
+    *info = SystemAvInfo {
+        geometry: GameGeometry {
+            base_width: 64,
+            base_height: 32,
+            max_width: 64,
+            max_height: 32,
+            aspect_ratio: 2.0,
+        },
+        timing: SystemTiming {
+            fps: FRAMES_PER_SECOND as f64,
+            sample_rate: SAMPLE_RATE as f64,
+        },
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(callback: EnvironmentFn) {
+    CALLBACKS.lock().unwrap().environment = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(callback: VideoRefreshFn) {
+    CALLBACKS.lock().unwrap().video_refresh = Some(callback);
+}
+
+/// Audio is always passed in batches
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_callback: AudioSampleFn) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(callback: AudioSampleBatchFn) {
+    CALLBACKS.lock().unwrap().audio_sample_batch = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(callback: InputPollFn) {
+    CALLBACKS.lock().unwrap().input_poll = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(callback: InputStateFn) {
+    CALLBACKS.lock().unwrap().input_state = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    *CORE.lock().unwrap() = None;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const GameInfo) -> bool {
+    let Some(game) = game.as_ref() else {
+        return false;
+    };
+    if game.data.is_null() || 0x200 + game.size > DEFAULT_MEMORY_SIZE {
+        return false;
+    }
+    if let Some(environment) = callbacks().environment {
+        let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+        let format = &mut format as *mut c_uint as *mut c_void;
+        if !environment(RETRO_ENVIRONMENT_SET_PIXEL_FORMAT, format) {
+            return false;
+        }
+    }
+    let program = slice::from_raw_parts(game.data as *const u8, game.size).to_vec();
+    *CORE.lock().unwrap() = Some(Core::new(program));
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(
+    _game_type: c_uint,
+    _info: *const GameInfo,
+    _num_info: usize,
+) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    *CORE.lock().unwrap() = None;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    RETRO_REGION_NTSC
+}
+
+/// Start the program again with cleared memory
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    with_core(|core| {
+        core.cpu.hard_reset();
+        core.cpu.set_program(&core.program);
+        core.clock.reset();
+    })
+}
+
+/// Emulate one frame: read the input, execute the instructions of the frame and hand
+/// the display and the beeper to the frontend
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    let callbacks = callbacks();
+    if let Some(input_poll) = callbacks.input_poll {
+        unsafe { input_poll() };
+    }
+    with_core(|core| {
+        if let Some(input_state) = callbacks.input_state {
+            core.read_input(input_state);
+        }
+        for &(address, value) in core.cheats.iter().flatten() {
+            core.cpu.write_memory(address as usize, &[value]);
+        }
+        core.clock.run_frame(&mut core.cpu, &core.keypad);
+
+        let (width, height) = core.render();
+        if let Some(video_refresh) = callbacks.video_refresh {
+            let data = core.pixels.as_ptr() as *const c_void;
+            unsafe { video_refresh(data, width as c_uint, height as c_uint, width * 4) };
+        }
+        core.render_audio();
+        if let Some(audio_sample_batch) = callbacks.audio_sample_batch {
+            unsafe { audio_sample_batch(core.audio.as_ptr(), core.audio.len() / 2) };
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    with_core(|core| core.serialize_size())
+}
+
+/// Write a save state in the format of the emulator's save state files, padded with
+/// zeros to the size of `retro_serialize_size`
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    let state = with_core(|core| core.save_state().to_bytes());
+    if data.is_null() || state.is_empty() || state.len() > size {
+        return false;
+    }
+    let target = slice::from_raw_parts_mut(data as *mut u8, size);
+    target[..state.len()].copy_from_slice(&state);
+    target[state.len()..].fill(0);
+    true
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    if data.is_null() {
+        return false;
+    }
+    let Ok(state) = SaveState::from_bytes(slice::from_raw_parts(data as *const u8, size)) else {
+        return false;
+    };
+    with_core(|core| {
+        if core.cpu.load_state(&state.cpu).is_err() {
+            return false;
+        }
+        core.keypad.set_state(state.keys);
+        core.clock.set_cycle(state.cycle);
+        true
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {
+    with_core(|core| core.cheats.clear())
+}
+
+/// Cheat codes are the hex address and value of a byte that is set every frame, like
+/// `03F0 09` or `03F0:09`
+#[no_mangle]
+pub unsafe extern "C" fn retro_cheat_set(index: c_uint, enabled: bool, code: *const c_char) {
+    let cheat = if code.is_null() {
+        None
+    } else {
+        CStr::from_ptr(code).to_str().ok().and_then(parse_cheat)
+    };
+    with_core(|core| {
+        let index = index as usize;
+        if core.cheats.len() <= index {
+            core.cheats.resize(index + 1, None);
+        }
+        core.cheats[index] = cheat.filter(|_| enabled);
+    })
+}
+
+fn parse_cheat(code: &str) -> Option<(u16, u8)> {
+    let mut parts = code.split([' ', ':', '+']).filter(|part| !part.is_empty());
+    let address = u16::from_str_radix(parts.next()?, 16).ok()?;
+    let value = u8::from_str_radix(parts.next()?, 16).ok()?;
+    Some((address, value))
+}
+
+/// The whole memory as system RAM, for the cheat search and achievements of frontends
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    if id != RETRO_MEMORY_SYSTEM_RAM {
+        return ptr::null_mut();
+    }
+    let data = with_core(|core| Some(core.cpu.memory_mut().as_mut_ptr()));
+    data.map_or(ptr::null_mut(), |data| data as *mut c_void)
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    if id != RETRO_MEMORY_SYSTEM_RAM {
+        return 0;
+    }
+    with_core(|core| core.cpu.memory().len())
+}
@@ -0,0 +1,314 @@
+use crate::{
+    cheats::Cheats,
+    clock::FrameClock,
+    cpu::CPU,
+    keypad::Keypad,
+    movie::{Movie, MovieEvent, MovieRecorder},
+    reload::RomWatcher,
+    script::Script,
+    state::SaveState,
+    symbols::Symbols,
+    trace::{Snapshot, Tracer},
+};
+
+pub use chip8::FRAMES_PER_SECOND;
+
+/// A CHIP-8 machine without any frontend: the CPU, the state of the keypad and the loaded
+/// program. Frontends feed key presses into `keypad` and drive it with `emulate_cycle`
+/// or `run_frame`.
+pub struct Machine {
+    pub cpu: CPU,
+    pub keypad: Keypad,
+    /// Input that is played back, it overrides the keypad whenever it changes
+    pub movie: Option<Movie>,
+    /// Records the keypad state into a movie file
+    pub movie_recorder: Option<MovieRecorder>,
+    /// Logs the executed instructions
+    pub tracer: Option<Tracer>,
+    /// Label names of the program, empty without a symbol file
+    pub symbols: Symbols,
+    /// Automates the machine through hooks, see `Script`
+    pub script: Option<Script>,
+    /// Memory values that are set at the start of every frame
+    pub cheats: Option<Cheats>,
+    /// Reloads the program when its file changes, see `reload_if_changed`
+    pub watcher: Option<RomWatcher>,
+    /// Count the timers down once per frame instead of by the duration of the cycles,
+    /// so runs only depend on the input
+    pub frame_timers: bool,
+    program: Vec<u8>,
+    clock: FrameClock,
+}
+
+impl Machine {
+    pub fn new(mut cpu: CPU, program: Vec<u8>, instructions_per_second: u32) -> Self {
+        cpu.set_program(&program);
+        Self {
+            cpu,
+            keypad: Keypad::new(),
+            movie: None,
+            movie_recorder: None,
+            tracer: None,
+            symbols: Symbols::default(),
+            script: None,
+            cheats: None,
+            watcher: None,
+            frame_timers: false,
+            program,
+            clock: FrameClock::new(instructions_per_second),
+        }
+    }
+
+    pub fn program(&self) -> &[u8] {
+        &self.program
+    }
+
+    pub fn instructions_per_second(&self) -> u32 {
+        self.clock.instructions_per_second()
+    }
+
+    pub fn cycles_per_frame(&self) -> u32 {
+        self.clock.cycles_per_frame()
+    }
+
+    /// Number of 60 Hz frames completed since the last reset
+    pub fn frame(&self) -> u64 {
+        self.clock.frame()
+    }
+
+    /// Execute a single instruction, advancing the timers by the duration of one cycle
+    pub fn emulate_cycle(&mut self) {
+        if self.clock.starts_frame() {
+            self.start_frame();
+        }
+        if let Some(script) = &self.script {
+            let pc = self.cpu.pc();
+            if self.cpu.is_running() && script.hooks_instruction(pc) {
+                self.run_script(|script, machine| script.on_instruction(machine, pc));
+            }
+        }
+        let elapsed_nanos = if self.frame_timers {
+            0
+        } else {
+            1_000_000_000 / self.instructions_per_second() as u64
+        };
+        if self.tracer.is_some() {
+            self.emulate_traced_cycle(elapsed_nanos);
+        } else {
+            self.cpu.emulate_cycle(elapsed_nanos, &self.keypad);
+        }
+        self.clock.advance();
+        if self.script.is_some() {
+            self.run_script(|script, machine| script.on_memory_writes(machine));
+            if self.clock.starts_frame() {
+                let frame = self.frame() - 1;
+                self.run_script(|script, machine| script.on_frame(machine, frame));
+            }
+        }
+    }
+
+    /// Show a message of the machine, on the console of the CPU
+    pub fn log(&mut self, message: &str) {
+        self.cpu.log(message);
+    }
+
+    /// Call a hook of the script, which may change the machine. A script that fails
+    /// is stopped.
+    fn run_script(&mut self, hook: impl FnOnce(&mut Script, &mut Machine) -> Result<(), String>) {
+        let Some(mut script) = self.script.take() else {
+            return;
+        };
+        match hook(&mut script, self) {
+            Ok(()) => self.script = Some(script),
+            Err(error) => self.log(&format!("Script failed, stopping: {}", error)),
+        }
+    }
+
+    /// Execute a cycle and hand the executed instruction and its effects to the tracer
+    fn emulate_traced_cycle(&mut self, elapsed_nanos: u64) {
+        let executes = self.cpu.is_running();
+        let pc = self.cpu.pc();
+        let opcode = self.cpu.instruction_at(pc as usize).unwrap_or(0);
+        // Timers count down before the instruction, which didn't change them
+        if self.cpu.panic_message().is_none() {
+            self.cpu.update_timers(elapsed_nanos);
+        }
+        let before = Snapshot::of(&self.cpu);
+        let frame = self.frame();
+        self.cpu.emulate_cycle(0, &self.keypad);
+
+        let Some(tracer) = &mut self.tracer else {
+            return;
+        };
+        let mut result = Ok(());
+        if executes {
+            let after = Snapshot::of(&self.cpu);
+            let cycle = self.clock.cycle();
+            result = tracer.record(cycle, frame, pc, opcode, before, after);
+        }
+        if let (true, Some(message)) = (executes, self.cpu.panic_message()) {
+            result = result.and_then(|()| tracer.record_panic(message));
+        }
+        if let Err(error) = result {
+            self.log(&format!("Couldn't write trace, stopping: {}", error));
+            self.tracer = None;
+        }
+    }
+
+    /// Execute all instructions up to the end of the current 60 Hz frame
+    pub fn run_frame(&mut self) {
+        // Not compared by frame number, a reset of a movie starts frame 0 again
+        loop {
+            self.emulate_cycle();
+            if self.clock.starts_frame() {
+                return;
+            }
+        }
+    }
+
+    /// Apply and record the input and the cheats at the beginning of a frame, and close
+    /// the previous frame of the profiler
+    fn start_frame(&mut self) {
+        // Resets of the movie come first, they start a new frame 0
+        let events = match &mut self.movie {
+            Some(movie) => movie.next_frame(),
+            None => Vec::new(),
+        };
+        for event in events {
+            match event {
+                MovieEvent::Keys(state) => self.keypad.set_state(state),
+                MovieEvent::SoftReset => self.restart(false),
+                MovieEvent::HardReset => self.restart(true),
+            }
+        }
+        if let Some(cheats) = &mut self.cheats {
+            cheats.apply(&mut self.cpu);
+        }
+        if let (Some(profiler), true) = (&mut self.cpu.profiler, self.clock.cycle() > 0) {
+            profiler.end_frame();
+        }
+        if self.frame_timers {
+            self.clock.update_timers(&mut self.cpu);
+        }
+        if let Some(recorder) = &mut self.movie_recorder {
+            if let Err(error) = recorder.record(self.keypad.state()) {
+                self.log(&format!("Couldn't record movie, stopping: {}", error));
+                self.movie_recorder = None;
+            }
+        }
+        if let Some(tracer) = &mut self.tracer {
+            if let Err(error) = tracer.flush() {
+                self.log(&format!("Couldn't write trace, stopping: {}", error));
+                self.tracer = None;
+            }
+        }
+    }
+
+    /// Snapshot of the CPU, keypad and cycle count, see `SaveState`
+    pub fn save_state(&self) -> SaveState {
+        SaveState {
+            cpu: self.cpu.save_state(),
+            keys: self.keypad.state(),
+            cycle: self.clock.cycle(),
+        }
+    }
+
+    /// Continue from a saved state. It has to come from a machine with the same
+    /// memory and display size.
+    pub fn load_state(&mut self, state: &SaveState) -> Result<(), String> {
+        self.cpu.load_state(&state.cpu)?;
+        self.keypad.set_state(state.keys);
+        self.clock.set_cycle(state.cycle);
+        Ok(())
+    }
+
+    /// Reinitialize the CPU and load the program again, memory outside of it is kept
+    pub fn soft_reset(&mut self) {
+        self.reset(false);
+    }
+
+    /// Replace the program and start it with cleared memory
+    pub fn load_program(&mut self, program: Vec<u8>) {
+        self.program = program;
+        self.hard_reset();
+    }
+
+    /// Load the program again if the watched file changed, keeping the memory outside of
+    /// it if the watcher is configured to, along with its cheats and symbols. Returns
+    /// whether it was reloaded.
+    pub fn reload_if_changed(&mut self) -> bool {
+        let Some(watcher) = &mut self.watcher else {
+            return false;
+        };
+        let Some(program) = watcher.poll() else {
+            return false;
+        };
+        let keep_memory = watcher.keep_memory;
+        let mut errors = Vec::new();
+        if let Some(path) = &watcher.symbols {
+            match Symbols::load(path) {
+                Ok(symbols) => {
+                    if let Some(tracer) = &mut self.tracer {
+                        tracer.set_symbols(symbols.clone());
+                    }
+                    self.symbols = symbols;
+                }
+                Err(error) => errors.push(format!("Couldn't load symbols: {}", error)),
+            }
+        }
+        // Cheats belong to the hash of the ROM
+        if let Some(cheats) = &self.cheats {
+            match cheats.for_program(&program) {
+                Ok(cheats) => self.cheats = Some(cheats),
+                Err(error) => errors.push(format!("Couldn't load cheats: {}", error)),
+            }
+        }
+        if !errors.is_empty() {
+            watcher.report_error(errors.join("\n"));
+        }
+
+        if keep_memory {
+            // A longer old version would stay behind the end of the new one
+            let end = 0x200 + self.program.len();
+            self.cpu.memory_mut()[0x200..end].fill(0);
+        }
+        self.program = program;
+        // Not a reset of the frontend, a recorded movie doesn't note it
+        self.restart(!keep_memory);
+        true
+    }
+
+    /// Reinitialize the CPU with cleared memory and load the program again
+    pub fn hard_reset(&mut self) {
+        self.reset(true);
+    }
+
+    /// Reset requested by the frontend: a movie plays from the beginning again, and the
+    /// recorded movie notes the reset
+    fn reset(&mut self, clear_memory: bool) {
+        self.restart(clear_memory);
+        if let Some(movie) = &mut self.movie {
+            movie.rewind();
+        }
+        if let Some(recorder) = &mut self.movie_recorder {
+            if let Err(error) = recorder.record_reset(clear_memory) {
+                self.log(&format!("Couldn't record movie, stopping: {}", error));
+                self.movie_recorder = None;
+            }
+        }
+    }
+
+    /// Reinitialize the CPU and load the program again, clearing the memory if asked to
+    fn restart(&mut self, clear_memory: bool) {
+        if clear_memory {
+            self.cpu.hard_reset();
+        } else {
+            self.cpu.reset();
+        }
+        self.cpu.set_program(&self.program);
+        self.clock.reset();
+        if let Some(cheats) = &mut self.cheats {
+            cheats.reset();
+        }
+    }
+}
@@ -1,26 +1,64 @@
-mod cpu;
-mod keypad;
+mod analysis;
+mod capture;
+mod cheats;
+mod config;
+mod frontend;
+mod gdb;
+mod headless;
+mod heatmap;
+mod inspector;
+mod machine;
+mod movie;
+mod overlay;
+mod palette;
+mod phosphor;
+mod reload;
+mod renderer;
+mod rl;
+mod rpc;
+mod screenshot;
+mod script;
+mod trace;
+mod tui;
 
 use std::{
-    fs::File,
-    io::Read,
-    thread,
+    io, process, thread,
     time::{Duration, Instant},
 };
 
 use crate::cpu::*;
-use keypad::{Keypad, KEY_MAP};
+use capture::Recorder;
+use cheats::Cheats;
+use chip8::{
+    clock, coverage, cpu, disassembler, environment, instruction, keypad, profiler, state,
+    symbols,
+    DEFAULT_FRAME_BUFFER_SIZE, DEFAULT_INSTRUCTIONS_PER_SECOND, DEFAULT_MAX_STACK_SIZE,
+    DEFAULT_MEMORY_SIZE, FONT,
+};
+use config::Config;
+use coverage::Coverage;
+use frontend::{AudioSink, DisplaySink, InputSource, RealTime, TimeSource};
+use machine::Machine;
+use movie::{Movie, MovieRecorder};
+use phosphor::{PersistenceMode, Phosphor};
+use profiler::Profiler;
+use reload::RomWatcher;
+use renderer::Renderer;
+use rpc::Server;
+use script::Script;
 use speedy2d::{
     color::Color,
     dimen::UVec2,
+    image::{ImageDataType, ImageSmoothingMode},
     shape::Rectangle,
-    window::{VirtualKeyCode, WindowCreationOptions, WindowHandler, WindowSize},
-    Window,
+    window::{
+        KeyScancode, VirtualKeyCode, WindowCreationOptions, WindowFullscreenMode, WindowHandler,
+        WindowHelper, WindowSize, WindowStartupInfo,
+    },
+    Graphics2D, Window,
 };
-
-const DEFAULT_MEMORY_SIZE: usize = 4 * 1024;
-const DEFAULT_FRAME_BUFFER_SIZE: usize = 64 * 32;
-const DEFAULT_MAX_STACK_SIZE: usize = 32;
+use symbols::Symbols;
+use trace::Tracer;
 
 const SCREEN_WIDTH: u32 = 1280;
 const SCREEN_HEIGHT: u32 = 640;
@@ -34,145 +72,569 @@ const TC5: &str = "test-programs/6-keypad.ch8";
 const G1: &str = "games/br8kout.ch8";
 const G2: &str = "games/spaceracer.ch8";
 
-const FONT: [u8; 80] = [
-    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-    0x20, 0x60, 0x20, 0x20, 0x70, // 1
-    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
-];
+const MIN_SPEED: f32 = 0.125;
+const MAX_SPEED: f32 = 16.0;
+const OVERLAY_SCALE: f32 = 3.0;
+const INSPECTOR_SCALE: f32 = 2.0;
+/// Size of the square of a memory address in the heatmap
+const HEATMAP_CELL: f32 = 3.0;
+
+/// Upper bound for the time emulated in one tick, so the emulator doesn't try
+/// to catch up forever after the window was stalled
+const MAX_TICK_DURATION: Duration = Duration::from_millis(250);
 
 static SECOND: Duration = Duration::from_secs(1);
 
+/// Events sent to the window from the timer thread
+enum EmulatorEvent {
+    Tick,
+}
+
+/// How long notifications like saved screenshots stay in the overlay
+const NOTIFICATION_DURATION: Duration = Duration::from_secs(3);
+
+/// Shows the display in the window. The frame buffer goes through the phosphor
+/// simulation, whose intensities are drawn in `on_draw`.
+struct WindowDisplay {
+    phosphor: Phosphor,
+    /// Whether the screen content changed since it was last drawn
+    dirty: bool,
+}
+
+impl DisplaySink for WindowDisplay {
+    /// Feed the frame buffer into the phosphor simulation if anything could have changed
+    fn show(&mut self, machine: &Machine, changed: bool, _paused: bool) -> io::Result<()> {
+        if changed || self.phosphor.mode() != PersistenceMode::Off {
+            self.dirty |= self.phosphor.update(machine.cpu.frame_buffer());
+        }
+        Ok(())
+    }
+}
+
+/// Keys of the window that control the machine, collected by the window events and
+/// handled at the start of the next tick
+#[derive(Default)]
+struct WindowInput {
+    /// Key and whether it was pressed or released
+    events: Vec<(VirtualKeyCode, bool)>,
+}
+
+impl InputSource for WindowInput {
+    fn poll(&mut self, machine: &mut Machine, paused: &mut bool) -> io::Result<bool> {
+        for (code, pressed) in self.events.drain(..) {
+            match code {
+                VirtualKeyCode::Space if pressed => *paused = !*paused,
+                // Execute a single instruction while paused
+                VirtualKeyCode::N if pressed && *paused => machine.emulate_cycle(),
+                VirtualKeyCode::F5 if pressed => machine.soft_reset(),
+                VirtualKeyCode::F6 if pressed => machine.hard_reset(),
+                _ => match key_for_virtual_key(code) {
+                    Some(id) if pressed => machine.keypad.key_down(id),
+                    Some(id) => machine.keypad.key_up(id),
+                    None => (),
+                },
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// The window has no sound output, the beeper is shown in the overlay instead
+#[derive(Default)]
+struct WindowBeeper {
+    on: bool,
+}
+
+impl AudioSink for WindowBeeper {
+    fn beep(&mut self, on: bool) {
+        self.on = on;
+    }
+}
+
 struct Emulator {
-    cpu: CPU,
-    debug_mode: bool,
-    keypad: Keypad,
-    last_cycle: Instant,
-    target_cycle_duration: Duration,
+    machine: Machine,
+    config: Config,
+    paused: bool,
+    speed: f32,
+    cycles_due: f64,
+    last_tick: Instant,
+    show_overlay: bool,
+    show_inspector: bool,
+    show_heatmap: bool,
+    display: WindowDisplay,
+    input: WindowInput,
+    beeper: WindowBeeper,
+    persistence_modes: Vec<PersistenceMode>,
+    renderer: Renderer,
+    window_size: UVec2,
+    fullscreen: bool,
+    drawn_overlay: Vec<String>,
+    notification: Option<(String, Instant)>,
+    recorder: Option<Recorder>,
+    last_captured_frame: u64,
+    /// Remote control through JSON-RPC
+    rpc: Option<Server>,
 
     cycle_counter: usize,
-    fps_duratin_counter: Instant,
-    fps_measurement_duration: Duration,
+    frame_counter: usize,
+    ips: usize,
+    fps: usize,
+    busy_time: Duration,
+    cpu_usage: f32,
+    tick_counter: u32,
+    tick_interval_sum: Duration,
+    worst_tick_interval: Duration,
+    average_tick_interval: Duration,
+    max_tick_interval: Duration,
+    stats_started: Instant,
 }
 
 impl Emulator {
-    fn new(cpu: CPU, target_fps: u64, debug_mode: bool) -> Self {
-        let duration = Duration::from_micros(1_000_000 / target_fps);
+    fn new(machine: Machine, config: Config, paused: bool) -> Self {
+        let persistence_modes = config.persistence_modes();
+        let phosphor = Phosphor::new(persistence_modes[0], machine.cpu.frame_buffer().len());
         Self {
-            cpu,
-            debug_mode,
-            keypad: Keypad::new(),
+            machine,
+            config,
+            paused,
+            speed: 1.0,
+            cycles_due: 0.0,
+            last_tick: Instant::now(),
+            show_overlay: true,
+            show_inspector: false,
+            show_heatmap: false,
+            display: WindowDisplay {
+                phosphor,
+                dirty: true,
+            },
+            input: WindowInput::default(),
+            beeper: WindowBeeper::default(),
+            persistence_modes,
+            renderer: Renderer::new(),
+            window_size: UVec2::new(SCREEN_WIDTH, SCREEN_HEIGHT),
+            fullscreen: false,
+            drawn_overlay: Vec::new(),
+            notification: None,
+            recorder: None,
+            last_captured_frame: 0,
+            rpc: None,
             cycle_counter: 0,
-            target_cycle_duration: duration,
-            fps_measurement_duration: Duration::new(0, 0),
-            last_cycle: Instant::now(),
-            fps_duratin_counter: Instant::now(),
+            frame_counter: 0,
+            ips: 0,
+            fps: 0,
+            busy_time: Duration::new(0, 0),
+            cpu_usage: 0.0,
+            tick_counter: 0,
+            tick_interval_sum: Duration::new(0, 0),
+            worst_tick_interval: Duration::new(0, 0),
+            average_tick_interval: Duration::new(0, 0),
+            max_tick_interval: Duration::new(0, 0),
+            stats_started: Instant::now(),
         }
     }
 
-    fn emulate_cycle(&mut self) {
-        // self.print_fps();
+    /// Run all cycles that are due since the last tick and update the display state
+    fn tick(&mut self, helper: &mut WindowHelper<EmulatorEvent>) {
+        let started = Instant::now();
+        let elapsed = started - self.last_tick;
+        self.last_tick = started;
+        self.record_tick_interval(elapsed);
 
-        let delta = self.last_cycle.elapsed();
-        self.cpu.emulate_cycle(delta, &self.keypad);
-        self.synch_fps(delta);
+        if self.machine.reload_if_changed() {
+            self.cycles_due = 0.0;
+            self.notify(format!("Reloaded {}", self.config.rom));
+        }
+        let paused = self.paused;
+        if let Some(server) = &self.rpc {
+            if server.handle(&mut self.machine, &mut self.paused) {
+                helper.terminate_loop();
+                return;
+            }
+        }
+        // The instructions due are paced by the time since the last tick and the speed,
+        // not frame by frame
+        let speed = self.speed;
+        let cycles_due = &mut self.cycles_due;
+        let cycle_counter = &mut self.cycle_counter;
+        frontend::step(
+            &mut self.machine,
+            &mut self.display,
+            &mut self.input,
+            &mut self.beeper,
+            &mut self.paused,
+            |machine| {
+                *cycles_due += elapsed.min(MAX_TICK_DURATION).as_secs_f64()
+                    * machine.instructions_per_second() as f64
+                    * speed as f64;
+                while *cycles_due >= 1.0 {
+                    machine.emulate_cycle();
+                    *cycle_counter += 1;
+                    *cycles_due -= 1.0;
+                }
+            },
+        )
+        .expect("Couldn't update the display");
+        if self.paused != paused {
+            self.cycles_due = 0.0;
+        }
+        if let Some((_, shown)) = &self.notification {
+            if shown.elapsed() > NOTIFICATION_DURATION {
+                self.notification = None;
+            }
+        }
+        self.capture_frames();
+
+        self.busy_time += started.elapsed();
+        self.update_stats();
+        // The inspector and the heatmap show the machine state, which changes with every
+        // instruction
+        let panels_changed = (self.show_inspector || self.show_heatmap) && !self.paused;
+        if self.display.dirty || panels_changed || self.overlay_lines() != self.drawn_overlay {
+            helper.request_redraw();
+        }
+    }
+
+    /// Add the frames completed since the last tick to the running capture
+    fn capture_frames(&mut self) {
+        let frame = self.machine.frame();
+        // Resets restart the frame count
+        let frames = frame.saturating_sub(self.last_captured_frame) as u32;
+        self.last_captured_frame = frame;
+        if let Some(recorder) = &mut self.recorder {
+            let cpu = &self.machine.cpu;
+            let (width, height) = cpu.display_size();
+            if let Err(error) = recorder.add_frame(cpu.frame_buffer(), width, height, frames) {
+                self.recorder = None;
+                self.notify(format!("Capture failed: {}", error));
+            }
+        }
+    }
 
-        self.cycle_counter += 1;
-        self.last_cycle = Instant::now();
+    /// Start capturing a GIF into the capture directory, or finish the running capture
+    fn toggle_capture(&mut self) {
+        if let Some(recorder) = self.recorder.take() {
+            match recorder.finish() {
+                Ok(frames) => self.notify(format!("Saved capture with {} frames", frames)),
+                Err(error) => self.notify(format!("Capture failed: {}", error)),
+            }
+            return;
+        }
+        let (width, height) = self.machine.cpu.display_size();
+        let path = screenshot::timestamped_path(&self.config.capture_dir, "gif");
+        let scale = self.config.screenshot_scale.max(1);
+        match Recorder::create(&path, width, height, scale, &self.config.palette) {
+            Ok(recorder) => {
+                self.recorder = Some(recorder);
+                self.last_captured_frame = self.machine.frame();
+                self.notify(format!("Recording {}", path));
+            }
+            Err(error) => self.notify(format!("Capture failed: {}", error)),
+        }
+    }
+
+    fn record_tick_interval(&mut self, interval: Duration) {
+        self.tick_counter += 1;
+        self.tick_interval_sum += interval;
+        self.worst_tick_interval = self.worst_tick_interval.max(interval);
+    }
+
+    /// Turn the counters into per second values once a second has passed
+    fn update_stats(&mut self) {
+        let elapsed = self.stats_started.elapsed();
+        if elapsed < SECOND {
+            return;
+        }
+        self.ips = (self.cycle_counter as f64 / elapsed.as_secs_f64()).round() as usize;
+        self.fps = (self.frame_counter as f64 / elapsed.as_secs_f64()).round() as usize;
+        self.cpu_usage = (self.busy_time.as_secs_f64() / elapsed.as_secs_f64() * 100.0) as f32;
+        if self.tick_counter > 0 {
+            self.average_tick_interval = self.tick_interval_sum / self.tick_counter;
+        }
+        self.max_tick_interval = self.worst_tick_interval;
+
+        self.cycle_counter = 0;
+        self.frame_counter = 0;
+        self.busy_time = Duration::new(0, 0);
+        self.tick_counter = 0;
+        self.tick_interval_sum = Duration::new(0, 0);
+        self.worst_tick_interval = Duration::new(0, 0);
+        self.stats_started = Instant::now();
+    }
+
+    /// Show a message in the overlay for a few seconds
+    fn notify(&mut self, message: String) {
+        println!("{}", message);
+        self.notification = Some((message, Instant::now()));
+    }
+
+    /// Save the current frame buffer as PNG into the screenshot directory.
+    /// A configured scale of 0 uses the current window scale.
+    fn take_screenshot(&mut self) {
+        let cpu = &self.machine.cpu;
+        let (width, height) = cpu.display_size();
+        let scale = match self.config.screenshot_scale {
+            0 => {
+                let viewport = self.viewport();
+                let viewport_width = viewport.bottom_right().x - viewport.top_left().x;
+                (viewport_width / width as f32).floor().max(1.0) as usize
+            }
+            scale => scale,
+        };
+        let path = screenshot::timestamped_path(&self.config.screenshot_dir, "png");
+        let result = screenshot::save_png(
+            &path,
+            cpu.frame_buffer(),
+            width,
+            height,
+            scale,
+            &self.config.palette,
+        );
+        match result {
+            Ok(()) => self.notify(format!("Saved screenshot {}", path)),
+            Err(error) => self.notify(format!("Screenshot failed: {}", error)),
+        }
+    }
+
+    /// Show or hide the heatmap, profiling starts the first time it is shown
+    fn toggle_heatmap(&mut self) {
+        self.show_heatmap = !self.show_heatmap;
+        let cpu = &mut self.machine.cpu;
+        if cpu.profiler.is_none() {
+            cpu.profiler = Some(Profiler::new(cpu.memory().len()));
+        }
     }
 
-    fn synch_fps(&mut self, delta: Duration) {
-        if delta > self.target_cycle_duration {
+    /// Write the profiler report to the configured file or the current directory
+    fn save_profile(&mut self) {
+        let Some(profiler) = &self.machine.cpu.profiler else {
+            self.notify(String::from("Not profiling, start with F2 or --profile"));
             return;
+        };
+        let path = match &self.config.profile {
+            Some(path) => path.clone(),
+            None => screenshot::timestamped_path(".", "txt"),
+        };
+        match profiler.write_report(&path, &self.machine.cpu, &self.machine.symbols) {
+            Ok(()) => self.notify(format!("Saved profile {}", path)),
+            Err(error) => self.notify(format!("Profile failed: {}", error)),
+        }
+    }
+
+    fn change_speed(&mut self, factor: f32) {
+        self.speed = (self.speed * factor).clamp(MIN_SPEED, MAX_SPEED);
+    }
+
+    /// Switch to the next anti-flicker mode
+    fn cycle_persistence(&mut self) {
+        let current = self.display.phosphor.mode();
+        let index = self
+            .persistence_modes
+            .iter()
+            .position(|&mode| mode == current)
+            .unwrap_or(0);
+        let next = self.persistence_modes[(index + 1) % self.persistence_modes.len()];
+        self.display.phosphor.set_mode(next);
+        self.display.dirty |= self
+            .display
+            .phosphor
+            .update(self.machine.cpu.frame_buffer());
+    }
+
+    fn toggle_fullscreen(&mut self, helper: &mut WindowHelper<EmulatorEvent>) {
+        self.fullscreen = !self.fullscreen;
+        helper.set_fullscreen_mode(if self.fullscreen {
+            WindowFullscreenMode::FullscreenBorderless
+        } else {
+            WindowFullscreenMode::Windowed
+        });
+    }
+
+    /// Area of the window the display is drawn to: as large as possible while keeping the
+    /// aspect ratio of the display, centered, leaving the remaining space as letterbox bars.
+    /// With integer scaling the size is snapped down to a whole multiple of the display size.
+    /// The inspector panel takes up the right side of the window while it is shown.
+    fn viewport(&self) -> Rectangle {
+        let (display_width, display_height) = self.machine.cpu.display_size();
+        let mut window_width = self.window_size.x as f32;
+        if self.show_inspector {
+            window_width = (window_width - inspector::width(INSPECTOR_SCALE)).max(0.0);
+        }
+        let window_height = self.window_size.y as f32;
+        let mut scale =
+            (window_width / display_width as f32).min(window_height / display_height as f32);
+        if self.config.integer_scaling && scale >= 1.0 {
+            scale = scale.floor();
         }
-        let difference = self.target_cycle_duration - delta;
-        //println!("Difference: {:?}", difference);
-        thread::sleep(difference);
+        let width = display_width as f32 * scale;
+        let height = display_height as f32 * scale;
+        let x = ((window_width - width) / 2.0).floor();
+        let y = ((window_height - height) / 2.0).floor();
+        Rectangle::from_tuples((x, y), (x + width, y + height))
     }
 
-    fn print_fps(&mut self) {
-        let difference = self.fps_duratin_counter.elapsed();
-        self.fps_duratin_counter = Instant::now();
-        self.fps_measurement_duration += difference;
-        if self.fps_measurement_duration > SECOND {
-            self.fps_measurement_duration = Duration::from_secs(0);
-            println!("Cycles this second: {}", self.cycle_counter);
-            self.cycle_counter = 0;
+    fn overlay_lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if self.paused {
+            lines.push(String::from("PAUSED"));
+        }
+        if let Some(message) = self.machine.cpu.panic_message() {
+            lines.push(format!("PANIC: {}", message));
+        }
+        if let Some(watcher) = &self.machine.watcher {
+            lines.extend(watcher.error_lines());
+        }
+        if let Some((message, _)) = &self.notification {
+            lines.push(message.clone());
+        }
+        if self.recorder.is_some() {
+            lines.push(String::from("REC"));
         }
+        if self.beeper.on {
+            lines.push(String::from("BEEP"));
+        }
+        if let Some(script) = &self.machine.script {
+            lines.extend(script.hud());
+        }
+        if self.show_overlay {
+            lines.push(format!("SPEED X{}", self.speed));
+            lines.push(format!("FPS {} IPS {}", self.fps, self.ips));
+            lines.push(format!("CPU {:.1}%", self.cpu_usage));
+            lines.push(format!(
+                "TICK {:.1}MS MAX {:.1}MS",
+                self.average_tick_interval.as_secs_f32() * 1000.0,
+                self.max_tick_interval.as_secs_f32() * 1000.0
+            ));
+            lines.push(format!(
+                "PERSISTENCE {}",
+                self.display.phosphor.mode().name()
+            ));
+        }
+        lines
     }
 }
 
-impl WindowHandler for Emulator {
-    fn on_draw(
-        &mut self,
-        helper: &mut speedy2d::window::WindowHelper<()>,
-        graphics: &mut speedy2d::Graphics2D,
-    ) {
-        if !self.debug_mode {
-            self.emulate_cycle();
-        }
-
-        if self.cpu.redraw || self.debug_mode || true {
-            self.cpu.redraw = false;
-            graphics.clear_screen(Color::DARK_GRAY);
-            let width: f32 = SCREEN_WIDTH as f32 / 64.0;
-            let height: f32 = SCREEN_HEIGHT as f32 / 32.0;
-            for y in 0..32 {
-                for x in 0..64 {
-                    if self.cpu.frame_buffer[y * 64 + x] {
-                        let y: f32 = y as f32;
-                        let x: f32 = x as f32;
-                        graphics.draw_rectangle(
-                            Rectangle::from_tuples(
-                                (width * x, height * y),
-                                (width * x + width, height * y + height),
-                            ),
-                            Color::WHITE,
-                        );
-                    }
+impl WindowHandler<EmulatorEvent> for Emulator {
+    fn on_start(&mut self, helper: &mut WindowHelper<EmulatorEvent>, info: WindowStartupInfo) {
+        self.window_size = *info.viewport_size_pixels();
+        if self.config.fullscreen {
+            self.toggle_fullscreen(helper);
+        }
+
+        let sender = helper.create_user_event_sender();
+        thread::spawn(move || {
+            // Emulation runs in batches at the display rate of the original hardware
+            let mut time = RealTime::default();
+            loop {
+                time.wait_for_frame();
+                if sender.send_event(EmulatorEvent::Tick).is_err() {
+                    break;
                 }
             }
+        });
+    }
+
+    fn on_user_event(&mut self, helper: &mut WindowHelper<EmulatorEvent>, event: EmulatorEvent) {
+        match event {
+            EmulatorEvent::Tick => self.tick(helper),
         }
+    }
 
+    fn on_resize(&mut self, helper: &mut WindowHelper<EmulatorEvent>, size_pixels: UVec2) {
+        self.window_size = size_pixels;
+        self.display.dirty = true;
         helper.request_redraw();
     }
 
+    fn on_fullscreen_status_changed(
+        &mut self,
+        _helper: &mut WindowHelper<EmulatorEvent>,
+        fullscreen: bool,
+    ) {
+        self.fullscreen = fullscreen;
+    }
+
+    fn on_draw(&mut self, _helper: &mut WindowHelper<EmulatorEvent>, graphics: &mut Graphics2D) {
+        let started = Instant::now();
+        self.frame_counter += 1;
+
+        let (display_width, display_height) = self.machine.cpu.display_size();
+        let viewport = self.viewport();
+        let pixel_size = (viewport.bottom_right().x - viewport.top_left().x) / display_width as f32;
+        self.renderer.set_style(
+            pixel_size,
+            self.config.pixel_gap,
+            self.config.rounded_pixels,
+        );
+        let size = self.renderer.image_size(display_width, display_height);
+        let pixels = self.renderer.render(
+            self.display.phosphor.intensity(),
+            display_width,
+            display_height,
+            &self.config.palette,
+        );
+        let image = graphics
+            .create_image_from_raw_pixels(
+                ImageDataType::RGBA,
+                ImageSmoothingMode::NearestNeighbor,
+                UVec2::new(size.0, size.1),
+                pixels,
+            )
+            .expect("Couldn't upload the frame buffer");
+        graphics.clear_screen(Color::BLACK);
+        graphics.draw_rectangle_image(viewport, &image);
+
+        if self.show_inspector {
+            let x = self.window_size.x as f32 - inspector::width(INSPECTOR_SCALE);
+            let height = self.window_size.y as f32;
+            inspector::draw(
+                graphics,
+                &self.machine.cpu,
+                &self.machine.symbols,
+                x,
+                height,
+                INSPECTOR_SCALE,
+            );
+        }
+        if let (true, Some(profiler)) = (self.show_heatmap, &self.machine.cpu.profiler) {
+            let (_, height) = heatmap::size(profiler, HEATMAP_CELL);
+            let y = self.window_size.y as f32 - height;
+            let pc = self.machine.cpu.pc();
+            heatmap::draw(graphics, profiler, pc, 0.0, y, HEATMAP_CELL);
+        }
+        self.drawn_overlay = self.overlay_lines();
+        overlay::draw_panel(graphics, &self.drawn_overlay, 0.0, 0.0, OVERLAY_SCALE);
+        self.display.dirty = false;
+        self.busy_time += started.elapsed();
+    }
+
     fn on_key_down(
         &mut self,
-        helper: &mut speedy2d::window::WindowHelper<()>,
-        virtual_key_code: Option<speedy2d::window::VirtualKeyCode>,
-        _scancode: speedy2d::window::KeyScancode,
+        helper: &mut WindowHelper<EmulatorEvent>,
+        virtual_key_code: Option<VirtualKeyCode>,
+        _scancode: KeyScancode,
     ) {
         match virtual_key_code {
             Some(vcode) => match vcode {
-                VirtualKeyCode::B => self.cpu.print_frame_buffer(),
-                VirtualKeyCode::M => self.cpu.print_memory(),
-                VirtualKeyCode::P => self.cpu.print_registers(),
-                VirtualKeyCode::N => {
-                    if self.debug_mode {
-                        self.emulate_cycle();
-                    }
-                }
-                VirtualKeyCode::L => self.cpu.detailed_logging = !self.cpu.detailed_logging,
-                VirtualKeyCode::I => self.cpu.print_value_at_i(),
-                _ => {
-                    if KEY_MAP.contains_key(&vcode) {
-                        let id = KEY_MAP[&vcode];
-                        self.keypad.key_down(id);
-                    }
+                VirtualKeyCode::B => self.machine.cpu.print_frame_buffer(),
+                VirtualKeyCode::M => self.machine.cpu.print_memory(),
+                VirtualKeyCode::P => self.machine.cpu.print_registers(),
+                VirtualKeyCode::Equals => self.change_speed(2.0),
+                VirtualKeyCode::Minus => self.change_speed(0.5),
+                VirtualKeyCode::Key0 => self.speed = 1.0,
+                VirtualKeyCode::Tab => self.show_overlay = !self.show_overlay,
+                VirtualKeyCode::F1 => self.show_inspector = !self.show_inspector,
+                VirtualKeyCode::F2 => self.toggle_heatmap(),
+                VirtualKeyCode::F3 => self.save_profile(),
+                VirtualKeyCode::V => self.cycle_persistence(),
+                VirtualKeyCode::F11 => self.toggle_fullscreen(helper),
+                VirtualKeyCode::F10 => self.toggle_capture(),
+                VirtualKeyCode::F12 => self.take_screenshot(),
+                VirtualKeyCode::L => {
+                    self.machine.cpu.detailed_logging = !self.machine.cpu.detailed_logging
                 }
+                VirtualKeyCode::I => self.machine.cpu.print_value_at_i(),
+                // The keypad, pause, single steps and resets
+                _ => self.input.events.push((vcode, true)),
             },
             _ => (),
         };
@@ -181,43 +643,162 @@ impl WindowHandler for Emulator {
 
     fn on_key_up(
         &mut self,
-        helper: &mut speedy2d::window::WindowHelper<()>,
+        helper: &mut WindowHelper<EmulatorEvent>,
         virtual_key_code: Option<VirtualKeyCode>,
-        _scancode: speedy2d::window::KeyScancode,
+        _scancode: KeyScancode,
     ) {
         if let Some(vcode) = virtual_key_code {
-            if KEY_MAP.contains_key(&vcode) {
-                let id = KEY_MAP[&vcode];
-                self.keypad.key_up(id);
-            }
+            self.input.events.push((vcode, false));
         }
         helper.request_redraw();
     }
 }
 
-fn read_ch8(file_path: &str) -> Vec<u8> {
-    let mut file = File::open(file_path).expect("Couldn't open file");
-    let mut contents = Vec::new();
-    file.read_to_end(&mut contents).expect("Couldn't read file");
+/// Keypad key of a key of the computer keyboard, the same layout as `key_for_char`
+fn key_for_virtual_key(code: VirtualKeyCode) -> Option<usize> {
+    let id = match code {
+        VirtualKeyCode::Key1 => 1,
+        VirtualKeyCode::Key2 => 2,
+        VirtualKeyCode::Key3 => 3,
+        VirtualKeyCode::Key4 => 0xC,
+        VirtualKeyCode::Q => 4,
+        VirtualKeyCode::W => 5,
+        VirtualKeyCode::E => 6,
+        VirtualKeyCode::R => 0xD,
+        VirtualKeyCode::A => 7,
+        VirtualKeyCode::S => 8,
+        VirtualKeyCode::D => 9,
+        VirtualKeyCode::F => 0xE,
+        VirtualKeyCode::Grave => 0xA,
+        VirtualKeyCode::Z => 0,
+        VirtualKeyCode::X => 0xB,
+        VirtualKeyCode::C => 0xF,
+        _ => return None,
+    };
+    Some(id)
+}
+
+/// Read the ROM, or assemble it first if it is an Octo source
+fn read_ch8(file_path: &str, assembler: &str) -> Vec<u8> {
+    let contents = match reload::read_rom(file_path, assembler) {
+        Ok(contents) => contents,
+        Err(error) => {
+            println!("Couldn't load {}: {}", file_path, error);
+            process::exit(1);
+        }
+    };
     println!("Program length: {}", contents.len());
     return contents;
 }
 
 fn main() {
-    let program = read_ch8(G1);
+    let config = Config::from_args(G1);
+    let program = read_ch8(&config.rom, &config.assembler);
+    let symbols = match &config.symbols {
+        Some(path) => Symbols::load(path).expect("Couldn't load symbols"),
+        None => Symbols::default(),
+    };
+    if let Some(path) = &config.env {
+        if let Err(error) = rl::run_random(&config, &program, path) {
+            println!("Environment failed: {}", error);
+        }
+        return;
+    }
+    if let Some(path) = &config.analyze {
+        if let Err(error) = analysis::run(&program, &symbols, path) {
+            println!("Analysis failed: {}", error);
+        }
+        return;
+    }
     let mut cpu = CPU::new(
         &FONT,
         DEFAULT_MEMORY_SIZE,
         DEFAULT_FRAME_BUFFER_SIZE,
         DEFAULT_MAX_STACK_SIZE,
     );
-    cpu.set_program(&program);
+    cpu.seed_rng(config.seed.unwrap_or_else(rand::random));
+    if config.profile.is_some() {
+        cpu.profiler = Some(Profiler::new(DEFAULT_MEMORY_SIZE));
+    }
+    if config.coverage.is_some() || config.coverage_min.is_some() {
+        cpu.coverage = Some(Coverage::new(DEFAULT_MEMORY_SIZE));
+    }
+    let mut machine = Machine::new(cpu, program, DEFAULT_INSTRUCTIONS_PER_SECOND);
+    if let Some(path) = &config.movie {
+        machine.movie = Some(Movie::load(path).expect("Couldn't load movie"));
+    }
+    if let Some(path) = &config.record_movie {
+        machine.movie_recorder =
+            Some(MovieRecorder::create(path).expect("Couldn't create movie file"));
+    }
+    if let Some(path) = &config.trace {
+        machine.tracer = Some(
+            Tracer::create(
+                path,
+                config.trace_filter,
+                config.trace_ring,
+                symbols.clone(),
+            )
+            .expect("Couldn't create trace file"),
+        );
+    }
+    machine.symbols = symbols;
+    machine.frame_timers = config.frame_timers;
+    if config.watch {
+        let mut watcher = RomWatcher::new(&config.rom, &config.assembler, config.watch_keep_ram);
+        watcher.symbols = config.symbols.clone();
+        machine.watcher = Some(watcher);
+    }
+    let cheats = Cheats::load(&config.cheat_dir, machine.program()).expect("Couldn't load cheats");
+    if !cheats.cheats().is_empty() {
+        println!(
+            "Loaded {} cheats from {}",
+            cheats.cheats().len(),
+            cheats.path()
+        );
+    }
+    machine.cheats = Some(cheats);
+    if let Some(path) = &config.script {
+        let scale = config.screenshot_scale.max(1);
+        let script =
+            Script::load(path, &mut machine, config.palette, scale).expect("Couldn't load script");
+        machine.script = Some(script);
+    }
+    if let (Some(_), true) = (config.rpc, config.headless) {
+        if let Err(error) = rpc::run(&config, machine) {
+            println!("JSON-RPC server failed: {}", error);
+        }
+        return;
+    }
+    if config.headless {
+        headless::run(&config, machine);
+        return;
+    }
+    if config.gdb.is_some() {
+        if let Err(error) = gdb::run(&config, machine) {
+            println!("Debugger connection failed: {}", error);
+        }
+        return;
+    }
+    if config.tui {
+        if let Err(error) = tui::run(&config, machine) {
+            println!("Terminal frontend failed: {}", error);
+        }
+        return;
+    }
+
     let options = WindowCreationOptions::new_windowed(
         WindowSize::PhysicalPixels(UVec2::new(SCREEN_WIDTH, SCREEN_HEIGHT)),
         None,
     )
-    .with_vsync(false);
-    let window = Window::new_with_options("Title", options).unwrap();
+    .with_vsync(false)
+    .with_resizable(true);
+    let window = Window::new_with_user_events("Title", options).unwrap();
 
-    window.run_loop(Emulator::new(cpu, 10000, false));
+    let mut emulator = Emulator::new(machine, config, false);
+    if let Some(port) = emulator.config.rpc {
+        let server = Server::start(port, &emulator.config.assembler);
+        emulator.rpc = Some(server.expect("Couldn't start JSON-RPC server"));
+    }
+    window.run_loop(emulator);
 }
@@ -0,0 +1,190 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+};
+
+/// Something that happens at the start of a frame of a movie
+#[derive(Clone, Copy)]
+pub enum MovieEvent {
+    /// New state of all keys
+    Keys(u16),
+    SoftReset,
+    HardReset,
+}
+
+/// Keypad input of a session, stored as the frames at which the pressed keys changed.
+///
+/// The file format is plain text with one change per line: the frame number and the
+/// state of all 16 keys as hexadecimal bit mask (bit N set means key N is pressed),
+/// e.g. `120 0010`, or `soft_reset` or `hard_reset` for a reset of the machine at that
+/// frame. Lines starting with `#` are comments. Frames are counted from the start of
+/// the recording and keep counting across resets.
+pub struct Movie {
+    changes: Vec<(u64, MovieEvent)>,
+    position: usize,
+    /// Frame that starts next
+    frame: u64,
+}
+
+impl Movie {
+    pub fn load(path: &str) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        let mut changes = Vec::new();
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parsed = line
+                .split_once(char::is_whitespace)
+                .and_then(|(frame, event)| {
+                    let frame = frame.parse::<u64>().ok()?;
+                    let event = match event.trim() {
+                        "soft_reset" => MovieEvent::SoftReset,
+                        "hard_reset" => MovieEvent::HardReset,
+                        keys => MovieEvent::Keys(u16::from_str_radix(keys, 16).ok()?),
+                    };
+                    Some((frame, event))
+                });
+            match parsed {
+                Some(change) => changes.push(change),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "{}:{}: expected `<frame> <keys>` or `<frame> <reset>`",
+                            path,
+                            number + 1
+                        ),
+                    ))
+                }
+            }
+        }
+        // Stable, so a reset stays before the keys of the same frame
+        changes.sort_by_key(|&(frame, _)| frame);
+        Ok(Self {
+            changes,
+            position: 0,
+            frame: 0,
+        })
+    }
+
+    /// Start the next frame and return its events in the recorded order
+    pub fn next_frame(&mut self) -> Vec<MovieEvent> {
+        let mut events = Vec::new();
+        while let Some(&(frame, event)) = self.changes.get(self.position) {
+            if frame > self.frame {
+                break;
+            }
+            events.push(event);
+            self.position += 1;
+        }
+        self.frame += 1;
+        events
+    }
+
+    /// Play the movie from the beginning again
+    pub fn rewind(&mut self) {
+        self.position = 0;
+        self.frame = 0;
+    }
+
+    /// Frame of the last change, so playback knows how long the recording is
+    pub fn last_frame(&self) -> u64 {
+        self.changes.last().map(|&(frame, _)| frame).unwrap_or(0)
+    }
+}
+
+/// Writes the keypad state to a movie file whenever it changes, and the resets
+pub struct MovieRecorder {
+    writer: BufWriter<File>,
+    last_state: u16,
+    /// Frames recorded so far, the machine restarts its count on resets
+    frame: u64,
+}
+
+impl MovieRecorder {
+    pub fn create(path: &str) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "# frame keys")?;
+        Ok(Self {
+            writer,
+            last_state: 0,
+            frame: 0,
+        })
+    }
+
+    /// Note the key state at the start of a frame. Changes are flushed right away, since
+    /// the window may be closed without giving the recorder a chance to finish.
+    pub fn record(&mut self, state: u16) -> io::Result<()> {
+        if state != self.last_state {
+            self.last_state = state;
+            writeln!(self.writer, "{} {:04x}", self.frame, state)?;
+            self.writer.flush()?;
+        }
+        self.frame += 1;
+        Ok(())
+    }
+
+    /// Note a reset, which is played back at the start of the next frame
+    pub fn record_reset(&mut self, clear_memory: bool) -> io::Result<()> {
+        let event = if clear_memory {
+            "hard_reset"
+        } else {
+            "soft_reset"
+        };
+        writeln!(self.writer, "{} {}", self.frame, event)?;
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Events as text, to compare them with the expected ones
+    fn describe(events: Vec<MovieEvent>) -> Vec<String> {
+        events
+            .into_iter()
+            .map(|event| match event {
+                MovieEvent::Keys(state) => format!("{:04x}", state),
+                MovieEvent::SoftReset => String::from("soft_reset"),
+                MovieEvent::HardReset => String::from("hard_reset"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn playback_repeats_the_recording() {
+        let path = std::env::temp_dir().join(format!("chip8-movie-{}.txt", std::process::id()));
+        let path = path.to_string_lossy().into_owned();
+        let mut recorder = MovieRecorder::create(&path).unwrap();
+        for state in [0, 0x0010, 0x0010, 0] {
+            recorder.record(state).unwrap();
+        }
+        recorder.record_reset(true).unwrap();
+        recorder.record(0x0001).unwrap();
+        drop(recorder);
+
+        let mut movie = Movie::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(movie.last_frame(), 4);
+        let mut frames: Vec<Vec<String>> = (0..6).map(|_| describe(movie.next_frame())).collect();
+        assert_eq!(
+            frames,
+            [
+                vec![],
+                vec!["0010"],
+                vec![],
+                vec!["0000"],
+                // The reset comes before the keys of its frame
+                vec!["hard_reset", "0001"],
+                vec![],
+            ]
+        );
+
+        movie.rewind();
+        frames = (0..2).map(|_| describe(movie.next_frame())).collect();
+        assert_eq!(frames[1], ["0010"]);
+    }
+}
@@ -0,0 +1,128 @@
+use speedy2d::{color::Color, shape::Rectangle, Graphics2D};
+
+/// Width and height of a glyph in font pixels, plus one pixel of spacing
+pub const GLYPH_WIDTH: f32 = 4.0;
+pub const GLYPH_HEIGHT: f32 = 6.0;
+
+const PANEL_PADDING: f32 = 2.0;
+
+/// Tiny 3x5 bitmap font so the overlay doesn't need a font file.
+/// Every row is 3 bits wide, the most significant bit is the left pixel.
+/// Lowercase letters are drawn as uppercase.
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b100, 0b100],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        '=' => [0b000, 0b111, 0b000, 0b111, 0b000],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        '(' => [0b001, 0b010, 0b010, 0b010, 0b001],
+        ')' => [0b100, 0b010, 0b010, 0b010, 0b100],
+        '[' => [0b011, 0b010, 0b010, 0b010, 0b011],
+        ']' => [0b110, 0b010, 0b010, 0b010, 0b110],
+        '<' => [0b001, 0b010, 0b100, 0b010, 0b001],
+        '>' => [0b100, 0b010, 0b001, 0b010, 0b100],
+        '!' => [0b010, 0b010, 0b010, 0b000, 0b010],
+        '?' => [0b111, 0b001, 0b011, 0b000, 0b010],
+        '\'' => [0b010, 0b010, 0b000, 0b000, 0b000],
+        '#' => [0b101, 0b111, 0b101, 0b111, 0b101],
+        '*' => [0b101, 0b010, 0b111, 0b010, 0b101],
+        ' ' => [0; 5],
+        _ => [0b111, 0b111, 0b111, 0b111, 0b111],
+    }
+}
+
+/// Draw a single line of text with its top left corner at (x, y).
+/// `scale` is the size of one font pixel in screen pixels.
+pub fn draw_text(graphics: &mut Graphics2D, text: &str, x: f32, y: f32, scale: f32, color: Color) {
+    for (index, c) in text.chars().enumerate() {
+        let glyph_x = x + index as f32 * GLYPH_WIDTH * scale;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for column in 0..3 {
+                if bits & (0b100 >> column) != 0 {
+                    let px = glyph_x + column as f32 * scale;
+                    let py = y + row as f32 * scale;
+                    graphics.draw_rectangle(
+                        Rectangle::from_tuples((px, py), (px + scale, py + scale)),
+                        color,
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Width and height in screen pixels a panel with these lines would take up
+pub fn panel_size(lines: &[String], scale: f32) -> (f32, f32) {
+    let columns = lines
+        .iter()
+        .map(|line| line.chars().count())
+        .max()
+        .unwrap_or(0);
+    (
+        (columns as f32 * GLYPH_WIDTH + PANEL_PADDING * 2.0) * scale,
+        (lines.len() as f32 * GLYPH_HEIGHT + PANEL_PADDING * 2.0) * scale,
+    )
+}
+
+/// Draw a block of lines on a translucent background with its top left corner at (x, y)
+pub fn draw_panel(graphics: &mut Graphics2D, lines: &[String], x: f32, y: f32, scale: f32) {
+    if lines.is_empty() {
+        return;
+    }
+    let (width, height) = panel_size(lines, scale);
+    graphics.draw_rectangle(
+        Rectangle::from_tuples((x, y), (x + width, y + height)),
+        Color::from_rgba(0.0, 0.0, 0.0, 0.7),
+    );
+    for (index, line) in lines.iter().enumerate() {
+        draw_text(
+            graphics,
+            line,
+            x + PANEL_PADDING * scale,
+            y + (PANEL_PADDING + index as f32 * GLYPH_HEIGHT) * scale,
+            scale,
+            Color::YELLOW,
+        );
+    }
+}
@@ -0,0 +1,69 @@
+/// Colors used to draw the screen, stored as 0xRRGGBB.
+/// Index 0 is the background and 1 the foreground.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Palette {
+    pub colors: [u32; 2],
+}
+
+/// Names of all built-in palettes, in the order they are listed in the help text
+pub const PRESETS: [&str; 6] = ["classic", "green", "amber", "lcd", "octo", "grayscale"];
+
+impl Palette {
+    pub const fn new(colors: [u32; 2]) -> Self {
+        Self { colors }
+    }
+
+    /// Look up one of the built-in palettes by name
+    pub fn preset(name: &str) -> Option<Self> {
+        let colors = match name.to_ascii_lowercase().as_str() {
+            "classic" => [0x333333, 0xFFFFFF],
+            "green" => [0x001A00, 0x33FF66],
+            "amber" => [0x1A0F00, 0xFFB000],
+            "lcd" => [0x9BBC0F, 0x0F380F],
+            "octo" => [0x996600, 0xFFCC00],
+            "grayscale" => [0x000000, 0xFFFFFF],
+            _ => return None,
+        };
+        Some(Self::new(colors))
+    }
+
+    pub fn background(&self) -> u32 {
+        self.colors[0]
+    }
+
+    pub fn foreground(&self) -> u32 {
+        self.colors[1]
+    }
+
+    /// Linear interpolation between two colors, `amount` 0 gives `from` and 1 gives `to`
+    pub fn mix(from: u32, to: u32, amount: f32) -> u32 {
+        let amount = amount.clamp(0.0, 1.0);
+        let mut color = 0;
+        for shift in [16, 8, 0] {
+            let a = ((from >> shift) & 0xFF) as f32;
+            let b = ((to >> shift) & 0xFF) as f32;
+            let channel = (a + (b - a) * amount).round() as u32;
+            color |= channel << shift;
+        }
+        color
+    }
+
+    /// Parse a color written as `RRGGBB`, `#RRGGBB` or `0xRRGGBB`
+    pub fn parse_color(value: &str) -> Option<u32> {
+        let hex = value
+            .trim()
+            .trim_start_matches('#')
+            .trim_start_matches("0x")
+            .trim_start_matches("0X");
+        if hex.len() != 6 {
+            return None;
+        }
+        u32::from_str_radix(hex, 16).ok()
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::preset("classic").unwrap()
+    }
+}
@@ -0,0 +1,93 @@
+use std::collections::VecDeque;
+
+/// How the frame buffer is turned into what is shown on screen
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PersistenceMode {
+    /// Show the raw frame buffer
+    Off,
+    /// Lit pixels fade out, keeping this share of their intensity every frame
+    Decay(f32),
+    /// A pixel is lit if it was lit in any of the last N frames
+    Blend(usize),
+}
+
+impl PersistenceMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PersistenceMode::Off => "OFF",
+            PersistenceMode::Decay(_) => "DECAY",
+            PersistenceMode::Blend(_) => "BLEND",
+        }
+    }
+}
+
+/// Simulates the afterglow of a CRT to reduce the flicker of XOR drawn sprites.
+/// Keeps an intensity between 0 and 1 for every pixel.
+pub struct Phosphor {
+    mode: PersistenceMode,
+    intensity: Vec<f32>,
+    history: VecDeque<Vec<bool>>,
+}
+
+impl Phosphor {
+    pub fn new(mode: PersistenceMode, size: usize) -> Self {
+        Self {
+            mode,
+            intensity: vec![0.0; size],
+            history: VecDeque::new(),
+        }
+    }
+
+    pub fn mode(&self) -> PersistenceMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: PersistenceMode) {
+        self.mode = mode;
+        self.history.clear();
+    }
+
+    /// Feed the next frame, should be called at the display rate of 60 Hz.
+    /// Returns whether any pixel intensity changed.
+    pub fn update(&mut self, frame_buffer: &[bool]) -> bool {
+        if self.intensity.len() != frame_buffer.len() {
+            self.intensity = vec![0.0; frame_buffer.len()];
+            self.history.clear();
+        }
+        let previous = self.intensity.clone();
+
+        match self.mode {
+            PersistenceMode::Off => {
+                for (intensity, &lit) in self.intensity.iter_mut().zip(frame_buffer) {
+                    *intensity = lit as u8 as f32;
+                }
+            }
+            PersistenceMode::Decay(decay) => {
+                for (intensity, &lit) in self.intensity.iter_mut().zip(frame_buffer) {
+                    *intensity = if lit { 1.0 } else { *intensity * decay };
+                    // Snap faded pixels to black so the display eventually stops changing
+                    if *intensity < 0.01 {
+                        *intensity = 0.0;
+                    }
+                }
+            }
+            PersistenceMode::Blend(frames) => {
+                self.history.push_back(frame_buffer.to_vec());
+                while self.history.len() > frames.max(1) {
+                    self.history.pop_front();
+                }
+                for (index, intensity) in self.intensity.iter_mut().enumerate() {
+                    let lit = self.history.iter().any(|frame| frame[index]);
+                    *intensity = lit as u8 as f32;
+                }
+            }
+        }
+
+        previous != self.intensity
+    }
+
+    /// Intensity of every pixel, in the same layout as the frame buffer
+    pub fn intensity(&self) -> &[f32] {
+        &self.intensity
+    }
+}
@@ -0,0 +1,166 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+};
+
+use crate::{cpu::CPU, disassembler, instruction::Instruction, symbols::Symbols};
+
+/// Number of entries in the hot spot lists of the report
+const REPORT_ENTRIES: usize = 20;
+
+/// Counts executed instructions per address, per opcode family and per 60 Hz frame,
+/// and the iterations of loops closed by backward jumps
+pub struct Profiler {
+    address_counts: Vec<u64>,
+    family_counts: [u64; 16],
+    /// Iterations per loop, keyed by the address the loop starts at and the jump closing it
+    loops: HashMap<(u16, u16), u64>,
+    /// Instructions of every completed frame
+    frame_counts: Vec<u64>,
+    current_frame: u64,
+}
+
+impl Profiler {
+    pub fn new(memory_size: usize) -> Self {
+        Self {
+            address_counts: vec![0; memory_size],
+            family_counts: [0; 16],
+            loops: HashMap::new(),
+            frame_counts: Vec::new(),
+            current_frame: 0,
+        }
+    }
+
+    /// Count an instruction at `address` that continued at `next_pc`
+    pub fn record(&mut self, address: u16, instruction: u16, next_pc: u16) {
+        if let Some(count) = self.address_counts.get_mut(address as usize) {
+            *count += 1;
+        }
+        self.family_counts[(instruction >> 12) as usize] += 1;
+        let jumps = matches!(Instruction::decode(instruction), Instruction::Jump(_));
+        if jumps && next_pc <= address {
+            *self.loops.entry((next_pc, address)).or_insert(0) += 1;
+        }
+        self.current_frame += 1;
+    }
+
+    /// Close the current frame, called at every 60 Hz frame boundary
+    pub fn end_frame(&mut self) {
+        self.frame_counts.push(self.current_frame);
+        self.current_frame = 0;
+    }
+
+    /// Executions of the instruction at every address
+    pub fn address_counts(&self) -> &[u64] {
+        &self.address_counts
+    }
+
+    pub fn total(&self) -> u64 {
+        self.family_counts.iter().sum()
+    }
+
+    /// Write a plain text report with the instructions per frame, the opcode families,
+    /// the hottest addresses and loops to `path`, naming locations after the closest symbol
+    pub fn write_report(&self, path: &str, cpu: &CPU, symbols: &Symbols) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        let total = self.total();
+        let share = |count: u64| count as f64 * 100.0 / total.max(1) as f64;
+
+        writeln!(out, "Instructions: {}", total)?;
+        writeln!(out, "Frames: {}", self.frame_counts.len())?;
+        if !self.frame_counts.is_empty() {
+            let min = self.frame_counts.iter().min().unwrap_or(&0);
+            let max = self.frame_counts.iter().max().unwrap_or(&0);
+            let sum: u64 = self.frame_counts.iter().sum();
+            writeln!(
+                out,
+                "Instructions per frame: min {} avg {:.1} max {}",
+                min,
+                sum as f64 / self.frame_counts.len() as f64,
+                max
+            )?;
+        }
+
+        writeln!(out, "\nOpcode families:")?;
+        for (family, &count) in self.family_counts.iter().enumerate() {
+            if count > 0 {
+                writeln!(
+                    out,
+                    "  {:X}xxx {:>12} {:>6.2}%",
+                    family,
+                    count,
+                    share(count)
+                )?;
+            }
+        }
+
+        writeln!(out, "\nHottest addresses:")?;
+        let mut addresses: Vec<(usize, u64)> = self
+            .address_counts
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        addresses.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        for &(address, count) in addresses.iter().take(REPORT_ENTRIES) {
+            let instruction = cpu.instruction_at(address).unwrap_or(0);
+            writeln!(
+                out,
+                "  {:04X} {:04X} {:<16} {:>12} {:>6.2}%{}",
+                address,
+                instruction,
+                disassembler::disassemble_with_symbols(instruction, symbols),
+                count,
+                share(count),
+                location(symbols, address as u16)
+            )?;
+        }
+
+        writeln!(out, "\nHottest loops (instructions spent inside):")?;
+        let mut loops: Vec<((u16, u16), u64, u64)> = self
+            .loops
+            .iter()
+            .map(|(&(start, end), &iterations)| {
+                let body = &self.address_counts[start as usize..=end as usize];
+                ((start, end), iterations, body.iter().sum())
+            })
+            .collect();
+        loops.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(&b.0)));
+        for ((start, end), iterations, instructions) in loops.into_iter().take(REPORT_ENTRIES) {
+            writeln!(
+                out,
+                "  {:04X}-{:04X} {:>10} iterations {:>12} {:>6.2}%{}",
+                start,
+                end,
+                iterations,
+                instructions,
+                share(instructions),
+                location(symbols, start)
+            )?;
+        }
+        out.flush()
+    }
+}
+
+/// `address` relative to the closest symbol as extra report column, empty without symbols
+fn location(symbols: &Symbols, address: u16) -> String {
+    if symbols.is_empty() {
+        String::new()
+    } else {
+        format!("  {}", symbols.describe(address))
+    }
+}
+
+/// Write the report of the profiler of `cpu` to `path`, if it is profiling, and print
+/// the outcome. Used by the frontends when they finish.
+pub fn save_report(cpu: &CPU, symbols: &Symbols, path: &str) {
+    let Some(profiler) = &cpu.profiler else {
+        return;
+    };
+    match profiler.write_report(path, cpu, symbols) {
+        Ok(()) => println!("Saved profile to {}", path),
+        Err(error) => println!("Couldn't save profile to {}: {}", path, error),
+    }
+}
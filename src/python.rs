@@ -0,0 +1,382 @@
+use std::fs;
+
+use numpy::{PyArray1, PyArray2, PyArray3, PyArrayMethods};
+use pyo3::{
+    exceptions::{PyIOError, PyValueError},
+    prelude::*,
+    types::PyBytes,
+};
+
+use crate::{
+    clock::FrameClock,
+    cpu::CPU,
+    environment::{Batch, EnvSpec, Environment},
+    keypad::Keypad,
+    state::SaveState,
+    DEFAULT_FRAME_BUFFER_SIZE, DEFAULT_INSTRUCTIONS_PER_SECOND, DEFAULT_MAX_STACK_SIZE,
+    DEFAULT_MEMORY_SIZE, FONT,
+};
+
+/// The CPU and its keypad for Python, e.g. for tests and machine learning. Timers count
+/// down once per frame and the random numbers can be seeded, so runs only depend on the
+/// input.
+#[pyclass(name = "Chip8", module = "chip8")]
+pub struct Chip8 {
+    cpu: CPU,
+    keypad: Keypad,
+    program: Vec<u8>,
+    clock: FrameClock,
+}
+
+impl Chip8 {
+    fn check_key(key: usize) -> PyResult<usize> {
+        if key < 16 {
+            Ok(key)
+        } else {
+            Err(PyValueError::new_err(format!("Invalid key {}", key)))
+        }
+    }
+}
+
+#[pymethods]
+impl Chip8 {
+    #[new]
+    #[pyo3(signature = (rom=None, instructions_per_second=DEFAULT_INSTRUCTIONS_PER_SECOND, seed=None))]
+    fn new(rom: Option<&str>, instructions_per_second: u32, seed: Option<u64>) -> PyResult<Self> {
+        let mut cpu = CPU::new(
+            &FONT,
+            DEFAULT_MEMORY_SIZE,
+            DEFAULT_FRAME_BUFFER_SIZE,
+            DEFAULT_MAX_STACK_SIZE,
+        );
+        if let Some(seed) = seed {
+            cpu.seed_rng(seed);
+        }
+        let mut chip8 = Self {
+            cpu,
+            keypad: Keypad::new(),
+            program: Vec::new(),
+            clock: FrameClock::new(instructions_per_second),
+        };
+        if let Some(path) = rom {
+            chip8.load_rom(path)?;
+        }
+        Ok(chip8)
+    }
+
+    /// Load the ROM at `path` and start it with cleared memory
+    fn load_rom(&mut self, path: &str) -> PyResult<()> {
+        let program = fs::read(path).map_err(|error| PyIOError::new_err(error.to_string()))?;
+        self.load_program(&program)
+    }
+
+    /// Load a program from bytes and start it with cleared memory
+    fn load_program(&mut self, program: &[u8]) -> PyResult<()> {
+        if 0x200 + program.len() > DEFAULT_MEMORY_SIZE {
+            return Err(PyValueError::new_err("Program doesn't fit into the memory"));
+        }
+        self.program = program.to_vec();
+        self.reset();
+        Ok(())
+    }
+
+    /// Start the program again with cleared memory and no keys pressed
+    fn reset(&mut self) {
+        self.cpu.hard_reset();
+        self.cpu.set_program(&self.program);
+        self.keypad = Keypad::new();
+        self.clock.reset();
+    }
+
+    /// Seed the random numbers of CXNN
+    fn seed(&mut self, seed: u64) {
+        self.cpu.seed_rng(seed);
+    }
+
+    /// Execute `cycles` instructions
+    #[pyo3(signature = (cycles=1))]
+    fn step(&mut self, cycles: u64) {
+        for _ in 0..cycles {
+            self.clock.emulate_cycle(&mut self.cpu, &self.keypad);
+        }
+    }
+
+    /// Execute all instructions up to the end of the current frame, `frames` times
+    #[pyo3(signature = (frames=1))]
+    fn run_frames(&mut self, frames: u64) {
+        for _ in 0..frames {
+            self.clock.run_frame(&mut self.cpu, &self.keypad);
+        }
+    }
+
+    fn press(&mut self, key: usize) -> PyResult<()> {
+        self.keypad.key_down(Chip8::check_key(key)?);
+        Ok(())
+    }
+
+    fn release(&mut self, key: usize) -> PyResult<()> {
+        self.keypad.key_up(Chip8::check_key(key)?);
+        Ok(())
+    }
+
+    /// Pressed keys as bit mask, bit N is set if key N is pressed
+    #[getter]
+    fn keys(&self) -> u16 {
+        self.keypad.state()
+    }
+
+    #[setter]
+    fn set_keys(&mut self, state: u16) {
+        self.keypad.set_state(state);
+    }
+
+    /// The display as one byte per pixel, 1 for set pixels, row by row
+    fn framebuffer<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        let (width, height) = self.cpu.display_size();
+        let pixels: Vec<u8> = self.cpu.frame_buffer()[..width * height]
+            .iter()
+            .map(|&on| on as u8)
+            .collect();
+        PyBytes::new(py, &pixels)
+    }
+
+    /// The display as numpy array of shape (height, width), 1 for set pixels
+    fn framebuffer_array<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyArray2<u8>>> {
+        let (width, height) = self.cpu.display_size();
+        let pixels: Vec<u8> = self.cpu.frame_buffer()[..width * height]
+            .iter()
+            .map(|&on| on as u8)
+            .collect();
+        PyArray1::from_vec(py, pixels).reshape([height, width])
+    }
+
+    /// Width and height of the display in the current resolution
+    #[getter]
+    fn display_size(&self) -> (usize, usize) {
+        self.cpu.display_size()
+    }
+
+    /// Number of frames completed since the last reset
+    #[getter]
+    fn frame(&self) -> u64 {
+        self.clock.frame()
+    }
+
+    #[getter]
+    fn pc(&self) -> u16 {
+        self.cpu.pc()
+    }
+
+    #[getter]
+    fn i_register(&self) -> u16 {
+        self.cpu.i_register()
+    }
+
+    /// V0 to VF
+    #[getter]
+    fn registers(&self) -> Vec<u8> {
+        self.cpu.registers().to_vec()
+    }
+
+    fn set_register(&mut self, x: usize, value: u8) -> PyResult<()> {
+        if x >= 16 {
+            return Err(PyValueError::new_err(format!("Invalid register {}", x)));
+        }
+        self.cpu.set_register(x, value);
+        Ok(())
+    }
+
+    #[getter]
+    fn delay_timer(&self) -> u8 {
+        self.cpu.delay_timer()
+    }
+
+    #[getter]
+    fn sound_timer(&self) -> u8 {
+        self.cpu.sound_timer()
+    }
+
+    #[getter]
+    fn waiting_for_key(&self) -> bool {
+        self.cpu.is_waiting_for_key()
+    }
+
+    /// Why the CPU stopped, None while it runs
+    #[getter]
+    fn panic_message(&self) -> Option<String> {
+        self.cpu.panic_message().map(String::from)
+    }
+
+    fn read_memory<'py>(
+        &self,
+        py: Python<'py>,
+        address: usize,
+        length: usize,
+    ) -> PyResult<Bound<'py, PyBytes>> {
+        let range = address
+            .checked_add(length)
+            .and_then(|end| self.cpu.memory().get(address..end));
+        match range {
+            Some(bytes) => Ok(PyBytes::new(py, bytes)),
+            None => Err(PyValueError::new_err("Range is outside the memory")),
+        }
+    }
+
+    fn write_memory(&mut self, address: usize, data: &[u8]) -> PyResult<()> {
+        if !self.cpu.write_memory(address, data) {
+            return Err(PyValueError::new_err("Range is outside the memory"));
+        }
+        Ok(())
+    }
+
+    /// Snapshot of the whole machine, in the format of the emulator's save state files
+    fn save_state<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        let state = SaveState {
+            cpu: self.cpu.save_state(),
+            keys: self.keypad.state(),
+            cycle: self.clock.cycle(),
+        };
+        PyBytes::new(py, &state.to_bytes())
+    }
+
+    /// Continue from a snapshot taken with `save_state` or a save state file
+    fn load_state(&mut self, data: &[u8]) -> PyResult<()> {
+        let state = SaveState::from_bytes(data)
+            .map_err(|error| PyValueError::new_err(error.to_string()))?;
+        self.cpu
+            .load_state(&state.cpu)
+            .map_err(PyValueError::new_err)?;
+        self.keypad.set_state(state.keys);
+        self.clock.set_cycle(state.cycle);
+        Ok(())
+    }
+}
+
+/// Read a ROM and the spec of its environment
+fn load_environment(rom: &str, spec: &str) -> PyResult<(Vec<u8>, EnvSpec)> {
+    let program = fs::read(rom).map_err(|error| PyIOError::new_err(error.to_string()))?;
+    if 0x200 + program.len() > DEFAULT_MEMORY_SIZE {
+        return Err(PyValueError::new_err("Program doesn't fit into the memory"));
+    }
+    let spec = EnvSpec::load(spec).map_err(|error| PyIOError::new_err(error.to_string()))?;
+    Ok((program, spec))
+}
+
+/// Gym-style environment of a ROM and its spec file, see `src/environment.rs`
+#[pyclass(name = "Environment", module = "chip8")]
+pub struct PyEnvironment {
+    environment: Environment,
+}
+
+#[pymethods]
+impl PyEnvironment {
+    #[new]
+    #[pyo3(signature = (rom, spec, seed=0))]
+    fn new(rom: &str, spec: &str, seed: u64) -> PyResult<Self> {
+        let (program, spec) = load_environment(rom, spec)?;
+        Ok(Self {
+            environment: Environment::new(&program, spec, seed),
+        })
+    }
+
+    /// Start a new episode and return the first observation, of shape (height, width)
+    fn reset<'py>(&mut self, py: Python<'py>) -> PyResult<Bound<'py, PyArray2<u8>>> {
+        let (width, height) = self.environment.display_size();
+        let observation = self.environment.reset();
+        PyArray1::from_vec(py, observation).reshape([height, width])
+    }
+
+    /// Hold the keys of `action` for the frames of a step and return the observation,
+    /// the reward and whether the episode is done
+    fn step<'py>(
+        &mut self,
+        py: Python<'py>,
+        action: usize,
+    ) -> PyResult<(Bound<'py, PyArray2<u8>>, f64, bool)> {
+        let (width, height) = self.environment.display_size();
+        let step = self
+            .environment
+            .step(action)
+            .map_err(PyValueError::new_err)?;
+        let observation = PyArray1::from_vec(py, step.observation).reshape([height, width])?;
+        Ok((observation, step.reward, step.done))
+    }
+
+    #[getter]
+    fn action_count(&self) -> usize {
+        self.environment.spec().action_count()
+    }
+
+    /// Width and height of the observations
+    #[getter]
+    fn display_size(&self) -> (usize, usize) {
+        self.environment.display_size()
+    }
+}
+
+/// Observations, rewards and whether the episodes are done after a step of a batch
+type BatchStep<'py> = (Bound<'py, PyArray3<u8>>, Vec<f64>, Vec<bool>);
+
+/// Many environments of the same ROM, stepped in parallel on all cores
+#[pyclass(name = "Batch", module = "chip8")]
+pub struct PyBatch {
+    batch: Batch,
+}
+
+#[pymethods]
+impl PyBatch {
+    #[new]
+    #[pyo3(signature = (rom, spec, size, seed=0))]
+    fn new(rom: &str, spec: &str, size: usize, seed: u64) -> PyResult<Self> {
+        if size == 0 {
+            return Err(PyValueError::new_err(
+                "A batch needs at least one environment",
+            ));
+        }
+        let (program, spec) = load_environment(rom, spec)?;
+        Ok(Self {
+            batch: Batch::new(&program, &spec, size, seed),
+        })
+    }
+
+    /// Step every environment with its action and return the observations, of shape
+    /// (size, height, width), the rewards and whether the episodes are done. Environments
+    /// that are done start a new episode, their observation is the first one of it.
+    fn step<'py>(&mut self, py: Python<'py>, actions: Vec<usize>) -> PyResult<BatchStep<'py>> {
+        let (width, height) = self.batch.display_size();
+        let steps = self.batch.step(&actions).map_err(PyValueError::new_err)?;
+        let rewards = steps.iter().map(|step| step.reward).collect();
+        let done = steps.iter().map(|step| step.done).collect();
+        let observations = steps
+            .into_iter()
+            .flat_map(|step| step.observation)
+            .collect();
+        let observations =
+            PyArray1::from_vec(py, observations).reshape([self.batch.size(), height, width])?;
+        Ok((observations, rewards, done))
+    }
+
+    #[getter]
+    fn size(&self) -> usize {
+        self.batch.size()
+    }
+
+    #[getter]
+    fn action_count(&self) -> usize {
+        self.batch.spec().action_count()
+    }
+
+    /// Width and height of the observations
+    #[getter]
+    fn display_size(&self) -> (usize, usize) {
+        self.batch.display_size()
+    }
+}
+
+#[pymodule]
+fn chip8(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<Chip8>()?;
+    module.add_class::<PyEnvironment>()?;
+    module.add_class::<PyBatch>()?;
+    Ok(())
+}
@@ -0,0 +1,199 @@
+use std::{
+    env, fs,
+    process::{self, Command},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::DEFAULT_MEMORY_SIZE;
+
+/// How often the file is checked for changes
+const CHECK_INTERVAL: Duration = Duration::from_millis(250);
+/// Lines of an assembler error shown in the overlay
+const MAX_ERROR_LINES: usize = 4;
+
+/// Whether the ROM is an Octo source that has to be assembled first
+pub fn is_source(path: &str) -> bool {
+    path.to_ascii_lowercase().ends_with(".8o")
+}
+
+/// Read a ROM, or assemble it if it is an Octo source (`.8o`)
+pub fn read_rom(path: &str, assembler: &str) -> Result<Vec<u8>, String> {
+    let program = if is_source(path) {
+        assemble(path, assembler)?
+    } else {
+        fs::read(path).map_err(|error| error.to_string())?
+    };
+    if 0x200 + program.len() > DEFAULT_MEMORY_SIZE {
+        return Err(format!(
+            "Program of {} bytes doesn't fit into the memory",
+            program.len()
+        ));
+    }
+    Ok(program)
+}
+
+/// Assemble an Octo source with the configured command, which is called with the source
+/// and the output file, like `octo game.8o game.ch8`
+fn assemble(source: &str, assembler: &str) -> Result<Vec<u8>, String> {
+    let mut words = assembler.split_whitespace();
+    let program = words.next().ok_or("No assembler configured")?;
+    let output = env::temp_dir().join(format!("chip8-{}.ch8", process::id()));
+    let result = Command::new(program)
+        .args(words)
+        .arg(source)
+        .arg(&output)
+        .output()
+        .map_err(|error| format!("Couldn't run {}: {}", program, error))?;
+    if !result.status.success() {
+        // Assemblers differ in where they report errors
+        let mut message = String::from_utf8_lossy(&result.stderr).trim().to_string();
+        if message.is_empty() {
+            message = String::from_utf8_lossy(&result.stdout).trim().to_string();
+        }
+        if message.is_empty() {
+            message = format!("{} failed with {}", program, result.status);
+        }
+        return Err(message);
+    }
+    let rom = fs::read(&output).map_err(|error| format!("Couldn't read assembled ROM: {}", error));
+    let _ = fs::remove_file(&output);
+    rom
+}
+
+/// Watches the file of the ROM, or the Octo source it is assembled from, to load it
+/// again whenever it changes during ROM development.
+///
+/// The modification time is polled. A new version is only loaded once it stayed the
+/// same for a check, so files that are still being written are skipped.
+pub struct RomWatcher {
+    path: String,
+    assembler: String,
+    /// Keep the memory outside of the program when reloading, otherwise it is cleared
+    pub keep_memory: bool,
+    /// Symbol file that is loaded again with the ROM
+    pub symbols: Option<String>,
+    /// Modification time of the loaded version
+    loaded: Option<SystemTime>,
+    /// Modification time of a new version that was seen by the last check
+    pending: Option<SystemTime>,
+    last_check: Instant,
+    /// Why the last reload failed, until one succeeds
+    error: Option<String>,
+}
+
+impl RomWatcher {
+    /// Watch the ROM at `path`, whose current version is already loaded
+    pub fn new(path: &str, assembler: &str, keep_memory: bool) -> Self {
+        Self {
+            path: String::from(path),
+            assembler: String::from(assembler),
+            keep_memory,
+            symbols: None,
+            loaded: RomWatcher::modified(path),
+            pending: None,
+            last_check: Instant::now(),
+            error: None,
+        }
+    }
+
+    fn modified(path: &str) -> Option<SystemTime> {
+        fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    /// Returns the new program if the file changed and could be loaded. Errors are kept
+    /// for the overlay until a reload succeeds, see `error_lines`.
+    pub fn poll(&mut self) -> Option<Vec<u8>> {
+        if self.last_check.elapsed() < CHECK_INTERVAL {
+            return None;
+        }
+        self.last_check = Instant::now();
+        // Editors that replace the file remove it for a moment
+        let modified = RomWatcher::modified(&self.path)?;
+        if Some(modified) == self.loaded {
+            self.pending = None;
+            return None;
+        }
+        if Some(modified) != self.pending {
+            self.pending = Some(modified);
+            return None;
+        }
+        self.loaded = Some(modified);
+        self.pending = None;
+        match read_rom(&self.path, &self.assembler) {
+            Ok(program) => {
+                self.error = None;
+                Some(program)
+            }
+            Err(error) => {
+                self.error = Some(error);
+                None
+            }
+        }
+    }
+
+    /// Keep an error of loading what belongs to the new program, like its symbols, for
+    /// the overlay until the next reload
+    pub fn report_error(&mut self, error: String) {
+        self.error = Some(error);
+    }
+
+    /// Overlay lines with the error of the last reload, empty if it succeeded
+    pub fn error_lines(&self) -> Vec<String> {
+        let Some(error) = &self.error else {
+            return Vec::new();
+        };
+        let mut lines = vec![String::from("RELOAD FAILED")];
+        lines.extend(error.lines().take(MAX_ERROR_LINES).map(String::from));
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write the ROM with a modification time `seconds` after the epoch, so versions
+    /// differ even on file systems with coarse timestamps
+    fn write_rom(path: &str, program: &[u8], seconds: u64) {
+        fs::write(path, program).unwrap();
+        let file = fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))
+            .unwrap();
+    }
+
+    /// Poll as if the check interval passed
+    fn poll(watcher: &mut RomWatcher) -> Option<Vec<u8>> {
+        watcher.last_check = Instant::now() - CHECK_INTERVAL;
+        watcher.poll()
+    }
+
+    #[test]
+    fn poll_loads_versions_once_they_stop_changing() {
+        let path = env::temp_dir().join(format!("chip8-reload-{}.ch8", process::id()));
+        let path = path.to_string_lossy().into_owned();
+        write_rom(&path, &[0x12, 0x00], 1000);
+        let mut watcher = RomWatcher::new(&path, "", false);
+        assert_eq!(poll(&mut watcher), None);
+
+        write_rom(&path, &[0x00, 0xE0], 2000);
+        // Not checked again before the interval passed
+        assert_eq!(watcher.poll(), None);
+        // Seen once, it might still be written
+        assert_eq!(poll(&mut watcher), None);
+        assert_eq!(poll(&mut watcher), Some(vec![0x00, 0xE0]));
+        assert_eq!(poll(&mut watcher), None);
+
+        write_rom(&path, &[0; DEFAULT_MEMORY_SIZE], 3000);
+        assert_eq!(poll(&mut watcher), None);
+        assert_eq!(poll(&mut watcher), None);
+        assert_eq!(watcher.error_lines()[0], "RELOAD FAILED");
+
+        write_rom(&path, &[0x12, 0x00], 4000);
+        poll(&mut watcher);
+        assert_eq!(poll(&mut watcher), Some(vec![0x12, 0x00]));
+        assert!(watcher.error_lines().is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,150 @@
+use crate::palette::Palette;
+
+/// Resolution of one CHIP-8 pixel in the texture when a pixel style needs more than one texel
+const STYLED_CELL_SIZE: usize = 8;
+
+/// Turns the display intensities into RGBA pixel data that is uploaded as a single texture.
+/// Pixel gaps and rounded corners are baked into the texture by giving every
+/// CHIP-8 pixel a cell of several texels.
+pub struct Renderer {
+    cell_size: usize,
+    /// Coverage of the pixel for each texel of a cell, 1 means fully lit
+    cell_mask: Vec<f32>,
+    pixels: Vec<u8>,
+}
+
+impl Renderer {
+    pub fn new() -> Self {
+        Self {
+            cell_size: 1,
+            cell_mask: vec![1.0],
+            pixels: Vec::new(),
+        }
+    }
+
+    /// Recalculate the pixel shape, `pixel_size` and `gap` are in screen pixels
+    pub fn set_style(&mut self, pixel_size: f32, gap: f32, rounded: bool) {
+        if gap <= 0.0 && !rounded {
+            self.cell_size = 1;
+            self.cell_mask = vec![1.0];
+            return;
+        }
+
+        let cell = STYLED_CELL_SIZE;
+        let inset = (gap / pixel_size.max(1.0) * cell as f32 / 2.0).min(cell as f32 / 2.0);
+        let size = cell as f32 - inset * 2.0;
+        let radius = if rounded { size / 3.0 } else { 0.0 };
+        self.cell_size = cell;
+        self.cell_mask = vec![0.0; cell * cell];
+        for y in 0..cell {
+            for x in 0..cell {
+                // Distance of the texel center from the inner rectangle whose
+                // corners are the centers of the rounding circles
+                let px = x as f32 + 0.5;
+                let py = y as f32 + 0.5;
+                let dx = (inset + radius - px)
+                    .max(px - (cell as f32 - inset - radius))
+                    .max(0.0);
+                let dy = (inset + radius - py)
+                    .max(py - (cell as f32 - inset - radius))
+                    .max(0.0);
+                let inside = if radius > 0.0 {
+                    dx * dx + dy * dy <= radius * radius
+                } else {
+                    dx <= 0.0 && dy <= 0.0
+                };
+                self.cell_mask[y * cell + x] = inside as u8 as f32;
+            }
+        }
+    }
+
+    /// Size of the texture generated for a display of `width` x `height` pixels
+    pub fn image_size(&self, width: usize, height: usize) -> (u32, u32) {
+        (
+            (width * self.cell_size) as u32,
+            (height * self.cell_size) as u32,
+        )
+    }
+
+    /// Build the RGBA texture for a display of `width` x `height` pixels
+    pub fn render(
+        &mut self,
+        intensity: &[f32],
+        width: usize,
+        height: usize,
+        palette: &Palette,
+    ) -> &[u8] {
+        let cell = self.cell_size;
+        let row_length = width * cell;
+        self.pixels.resize(row_length * height * cell * 4, 0);
+
+        for y in 0..height {
+            for x in 0..width {
+                let value = intensity[y * width + x];
+                for cy in 0..cell {
+                    for cx in 0..cell {
+                        let amount = value * self.cell_mask[cy * cell + cx];
+                        let color =
+                            Palette::mix(palette.background(), palette.foreground(), amount);
+                        let texel = ((y * cell + cy) * row_length + x * cell + cx) * 4;
+                        self.pixels[texel] = (color >> 16) as u8;
+                        self.pixels[texel + 1] = (color >> 8) as u8;
+                        self.pixels[texel + 2] = color as u8;
+                        self.pixels[texel + 3] = 0xFF;
+                    }
+                }
+            }
+        }
+
+        &self.pixels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Intensities of a display with only the pixel at `lit` set
+    fn frame_buffer(width: usize, height: usize, lit: (usize, usize)) -> Vec<f32> {
+        let mut intensity = vec![0.0; width * height];
+        intensity[lit.1 * width + lit.0] = 1.0;
+        intensity
+    }
+
+    fn color_at(pixels: &[u8], width: usize, x: usize, y: usize) -> u32 {
+        let texel = (y * width + x) * 4;
+        u32::from_be_bytes([0, pixels[texel], pixels[texel + 1], pixels[texel + 2]])
+    }
+
+    #[test]
+    fn texture_follows_the_display_size() {
+        let palette = Palette::default();
+        let mut renderer = Renderer::new();
+        for (width, height) in [(64, 32), (128, 64), (64, 32)] {
+            let lit = (width - 1, height / 2);
+            let intensity = frame_buffer(width, height, lit);
+            assert_eq!(
+                renderer.image_size(width, height),
+                (width as u32, height as u32)
+            );
+            let pixels = renderer.render(&intensity, width, height, &palette);
+            assert_eq!(pixels.len(), width * height * 4);
+            assert_eq!(color_at(pixels, width, lit.0, lit.1), palette.foreground());
+            assert_eq!(color_at(pixels, width, 0, lit.1), palette.background());
+        }
+    }
+
+    #[test]
+    fn styled_pixels_fill_a_cell() {
+        let palette = Palette::default();
+        let mut renderer = Renderer::new();
+        renderer.set_style(16.0, 4.0, false);
+        let intensity = frame_buffer(64, 32, (1, 0));
+        assert_eq!(renderer.image_size(64, 32), (64 * 8, 32 * 8));
+        let pixels = renderer.render(&intensity, 64, 32, &palette);
+        // The gap of a quarter pixel leaves an edge of one texel around the pixel
+        assert_eq!(color_at(pixels, 64 * 8, 8 + 4, 4), palette.foreground());
+        assert_eq!(color_at(pixels, 64 * 8, 8, 4), palette.background());
+        assert_eq!(color_at(pixels, 64 * 8, 4, 4), palette.background());
+    }
+}
@@ -0,0 +1,62 @@
+use std::{io, time::Instant};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    config::Config,
+    environment::{Batch, EnvSpec},
+};
+
+/// Play episodes with random actions in a batch of environments and print the rewards
+/// and the speed, to check a spec file and measure the throughput
+pub fn run_random(config: &Config, program: &[u8], spec_path: &str) -> io::Result<()> {
+    let spec = EnvSpec::load(spec_path)?;
+    let seed = config.seed.unwrap_or(0);
+    let mut batch = Batch::new(program, &spec, config.env_batch.max(1), seed);
+    let (width, height) = batch.display_size();
+    println!(
+        "{} actions, observations of {}x{} pixels",
+        spec.action_count(),
+        width,
+        height
+    );
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut returns = vec![0.0; batch.size()];
+    let mut finished = Vec::new();
+    let mut steps = 0u64;
+    let started = Instant::now();
+    while finished.len() < config.env_episodes {
+        let actions: Vec<usize> = (0..batch.size())
+            .map(|_| rng.gen_range(0..spec.action_count()))
+            .collect();
+        let results = batch
+            .step(&actions)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+        for (index, step) in results.into_iter().enumerate() {
+            returns[index] += step.reward;
+            if step.done {
+                finished.push(returns[index]);
+                returns[index] = 0.0;
+            }
+        }
+        steps += batch.size() as u64;
+    }
+    let seconds = started.elapsed().as_secs_f64();
+    let mean = finished.iter().sum::<f64>() / finished.len().max(1) as f64;
+    let best = finished.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    println!(
+        "{} episodes in {} environments, mean reward {:.2}, best {:.2}",
+        finished.len(),
+        batch.size(),
+        mean,
+        best
+    );
+    println!(
+        "{} steps in {:.1}s, {:.0} steps/s, {:.0} frames/s",
+        steps,
+        seconds,
+        steps as f64 / seconds,
+        (steps * spec.frame_skip() as u64) as f64 / seconds
+    );
+    Ok(())
+}
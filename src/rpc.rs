@@ -0,0 +1,472 @@
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde_json::{json, Value};
+
+use crate::{
+    config::Config,
+    machine::{Machine, FRAMES_PER_SECOND},
+    reload,
+    state::SaveState,
+};
+
+const FRAME_DURATION: Duration = Duration::from_micros(1_000_000 / FRAMES_PER_SECOND as u64);
+
+/// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+/// Most cycles and frames a single `step` or `run_frames` may run, so a request can't
+/// keep the frontend busy for long
+const MAX_CYCLES: u64 = 10_000_000;
+const MAX_FRAMES: u64 = 10 * 60 * FRAMES_PER_SECOND as u64;
+
+/// A request from a client, handled by the frontend that owns the machine
+pub struct Call {
+    request: Value,
+    reply: Sender<String>,
+}
+
+/// JSON-RPC 2.0 server on localhost, one request or response per line.
+///
+/// Connections are served on their own threads, which pass every request to the
+/// frontend through a channel. The frontend executes them between cycles with `handle`,
+/// so the window and the headless core can both be remote controlled.
+pub struct Server {
+    calls: Receiver<Call>,
+    /// Command that assembles Octo sources passed to `load_rom`
+    assembler: String,
+}
+
+impl Server {
+    pub fn start(port: u16, assembler: &str) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        println!("JSON-RPC server listening on {}", listener.local_addr()?);
+        let (sender, calls) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let sender = sender.clone();
+                thread::spawn(move || {
+                    if let Err(error) = serve_connection(stream, sender) {
+                        println!("JSON-RPC connection failed: {}", error);
+                    }
+                });
+            }
+        });
+        Ok(Self {
+            calls,
+            assembler: String::from(assembler),
+        })
+    }
+
+    /// Execute all requests that arrived since the last call. `paused` is the pause
+    /// state of the frontend, which `run` and `pause` change. Returns true if a client
+    /// asked the frontend to quit with `shutdown`, later requests aren't executed then.
+    pub fn handle(&self, machine: &mut Machine, paused: &mut bool) -> bool {
+        while let Ok(call) = self.calls.try_recv() {
+            if call.execute(machine, paused, &self.assembler) {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl Call {
+    /// Execute the request and send the response, returns true for `shutdown`
+    fn execute(self, machine: &mut Machine, paused: &mut bool, assembler: &str) -> bool {
+        let id = self.request.get("id").cloned().unwrap_or(Value::Null);
+        let method = self.request.get("method").and_then(Value::as_str);
+        let result = match method {
+            Some(method) => {
+                let params = self.request.get("params").cloned().unwrap_or(json!({}));
+                execute(machine, paused, assembler, method, &params)
+            }
+            None => Err((INVALID_REQUEST, String::from("Missing method"))),
+        };
+        let shutdown = method == Some("shutdown") && result.is_ok();
+        let response = match result {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            Err((code, message)) => {
+                json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+            }
+        };
+        // The client may be gone already, then there's no one to tell
+        let _ = self.reply.send(response.to_string());
+        shutdown
+    }
+}
+
+/// Read requests from a client, hand them to the frontend and write back the responses
+fn serve_connection(stream: TcpStream, calls: Sender<Call>) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Value>(&line) {
+            Ok(request) => {
+                let (reply, response) = mpsc::channel();
+                if calls.send(Call { request, reply }).is_err() {
+                    return Ok(());
+                }
+                match response.recv() {
+                    Ok(response) => response,
+                    Err(_) => return Ok(()),
+                }
+            }
+            Err(error) => json!({
+                "jsonrpc": "2.0",
+                "id": null,
+                "error": {"code": PARSE_ERROR, "message": error.to_string()}
+            })
+            .to_string(),
+        };
+        writeln!(writer, "{}", response)?;
+    }
+    Ok(())
+}
+
+type RpcResult = Result<Value, (i64, String)>;
+
+fn execute(
+    machine: &mut Machine,
+    paused: &mut bool,
+    assembler: &str,
+    method: &str,
+    params: &Value,
+) -> RpcResult {
+    match method {
+        "load_rom" => {
+            let path = string_param(params, "path")?;
+            let program =
+                reload::read_rom(path, assembler).map_err(|error| (INVALID_PARAMS, error))?;
+            machine.load_program(program);
+            Ok(json!(null))
+        }
+        "step" => {
+            *paused = true;
+            let cycles = optional_number(params, "cycles")?.unwrap_or(1);
+            if cycles > MAX_CYCLES {
+                return Err((INVALID_PARAMS, format!("At most {} cycles", MAX_CYCLES)));
+            }
+            for _ in 0..cycles {
+                machine.emulate_cycle();
+            }
+            Ok(status(machine, *paused))
+        }
+        "run_frames" => {
+            *paused = true;
+            let frames = optional_number(params, "frames")?.unwrap_or(1);
+            if frames > MAX_FRAMES {
+                return Err((INVALID_PARAMS, format!("At most {} frames", MAX_FRAMES)));
+            }
+            for _ in 0..frames {
+                machine.run_frame();
+            }
+            Ok(status(machine, *paused))
+        }
+        "run" => {
+            *paused = false;
+            Ok(status(machine, *paused))
+        }
+        "pause" => {
+            *paused = true;
+            Ok(status(machine, *paused))
+        }
+        "status" => Ok(status(machine, *paused)),
+        // The response is sent before the frontend quits
+        "shutdown" => Ok(json!(null)),
+        "read_memory" => {
+            let address = number_param(params, "address")? as usize;
+            let length = number_param(params, "length")? as usize;
+            let range = address
+                .checked_add(length)
+                .and_then(|end| machine.cpu.memory().get(address..end));
+            match range {
+                Some(bytes) => Ok(json!(bytes)),
+                None => Err((INVALID_PARAMS, String::from("Range is outside the memory"))),
+            }
+        }
+        "write_memory" => {
+            let address = number_param(params, "address")? as usize;
+            let data: Vec<u8> = serde_json::from_value(params["data"].clone())
+                .map_err(|_| (INVALID_PARAMS, String::from("data must be a list of bytes")))?;
+            if !machine.cpu.write_memory(address, &data) {
+                return Err((INVALID_PARAMS, String::from("Range is outside the memory")));
+            }
+            Ok(json!(null))
+        }
+        "get_registers" => {
+            let cpu = &machine.cpu;
+            Ok(json!({
+                "v": cpu.registers(),
+                "i": cpu.i_register(),
+                "pc": cpu.pc(),
+                "stack": cpu.stack(),
+                "delay_timer": cpu.delay_timer(),
+                "sound_timer": cpu.sound_timer(),
+            }))
+        }
+        "set_registers" => {
+            // Everything is checked before anything changes
+            let values: Vec<u8> = match params.get("v") {
+                Some(values) => serde_json::from_value(values.clone())
+                    .map_err(|_| (INVALID_PARAMS, String::from("v must be a list of bytes")))?,
+                None => Vec::new(),
+            };
+            let memory_size = machine.cpu.memory().len() as u64;
+            let i = optional_number(params, "i")?;
+            if i.is_some_and(|i| i >= memory_size) {
+                return Err((INVALID_PARAMS, String::from("i is outside the memory")));
+            }
+            // Both bytes of the next instruction have to be inside the memory
+            let pc = optional_number(params, "pc")?;
+            if pc.is_some_and(|pc| pc >= memory_size - 1) {
+                return Err((INVALID_PARAMS, String::from("pc is outside the memory")));
+            }
+            for (x, &value) in values.iter().enumerate().take(16) {
+                machine.cpu.set_register(x, value);
+            }
+            if let Some(i) = i {
+                machine.cpu.set_i_register(i as u16);
+            }
+            if let Some(pc) = pc {
+                machine.cpu.set_pc(pc as u16);
+            }
+            Ok(json!(null))
+        }
+        "get_framebuffer" => {
+            let (width, height) = machine.cpu.display_size();
+            let rows: Vec<String> = machine
+                .cpu
+                .frame_buffer()
+                .chunks(width)
+                .take(height)
+                .map(|row| row.iter().map(|&on| if on { '1' } else { '0' }).collect())
+                .collect();
+            Ok(json!({"width": width, "height": height, "rows": rows}))
+        }
+        "set_keys" => {
+            let keys: Vec<usize> = serde_json::from_value(params["keys"].clone())
+                .map_err(|_| (INVALID_PARAMS, String::from("keys must be a list of keys")))?;
+            let state = keys
+                .iter()
+                .filter(|&&key| key < 16)
+                .fold(0, |state, &key| state | 1 << key);
+            machine.keypad.set_state(state);
+            Ok(json!(null))
+        }
+        "save_state" => {
+            let path = string_param(params, "path")?;
+            machine
+                .save_state()
+                .save(path)
+                .map_err(|error| (SERVER_ERROR, error.to_string()))?;
+            Ok(json!(null))
+        }
+        "load_state" => {
+            let path = string_param(params, "path")?;
+            let state = SaveState::load(path).map_err(|error| (SERVER_ERROR, error.to_string()))?;
+            machine
+                .load_state(&state)
+                .map_err(|error| (SERVER_ERROR, error))?;
+            Ok(json!(null))
+        }
+        _ => Err((METHOD_NOT_FOUND, format!("Unknown method {}", method))),
+    }
+}
+
+fn status(machine: &Machine, paused: bool) -> Value {
+    json!({
+        "frame": machine.frame(),
+        "pc": machine.cpu.pc(),
+        "paused": paused,
+        "waiting_for_key": machine.cpu.is_waiting_for_key(),
+        "panic": machine.cpu.panic_message(),
+    })
+}
+
+fn string_param<'a>(params: &'a Value, name: &str) -> Result<&'a str, (i64, String)> {
+    params[name]
+        .as_str()
+        .ok_or_else(|| (INVALID_PARAMS, format!("Missing string {}", name)))
+}
+
+fn number_param(params: &Value, name: &str) -> Result<u64, (i64, String)> {
+    optional_number(params, name)?.ok_or_else(|| (INVALID_PARAMS, format!("Missing {}", name)))
+}
+
+fn optional_number(params: &Value, name: &str) -> Result<Option<u64>, (i64, String)> {
+    match params.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => value
+            .as_u64()
+            .map(Some)
+            .ok_or_else(|| (INVALID_PARAMS, format!("{} must be a number", name))),
+    }
+}
+
+/// Serve requests without a window. The machine starts paused, so clients control
+/// exactly which cycles run, and runs at the original speed after `run`.
+pub fn run(config: &Config, mut machine: Machine) -> io::Result<()> {
+    let server = Server::start(config.rpc.unwrap_or_default(), &config.assembler)?;
+    let mut paused = true;
+    let mut next_frame = Instant::now();
+    loop {
+        let timeout = if paused {
+            FRAME_DURATION
+        } else {
+            next_frame.saturating_duration_since(Instant::now())
+        };
+        match server.calls.recv_timeout(timeout) {
+            Ok(call) => {
+                if call.execute(&mut machine, &mut paused, &server.assembler)
+                    || server.handle(&mut machine, &mut paused)
+                {
+                    return Ok(());
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+        if paused {
+            next_frame = Instant::now();
+        } else if Instant::now() >= next_frame {
+            machine.run_frame();
+            next_frame += FRAME_DURATION;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cpu::CPU, DEFAULT_FRAME_BUFFER_SIZE, DEFAULT_INSTRUCTIONS_PER_SECOND,
+        DEFAULT_MAX_STACK_SIZE, DEFAULT_MEMORY_SIZE, FONT,
+    };
+
+    fn machine() -> Machine {
+        let cpu = CPU::new(
+            &FONT,
+            DEFAULT_MEMORY_SIZE,
+            DEFAULT_FRAME_BUFFER_SIZE,
+            DEFAULT_MAX_STACK_SIZE,
+        );
+        // LD V0 05, JP 202
+        Machine::new(
+            cpu,
+            vec![0x60, 0x05, 0x12, 0x02],
+            DEFAULT_INSTRUCTIONS_PER_SECOND,
+        )
+    }
+
+    fn call(machine: &mut Machine, method: &str, params: Value) -> RpcResult {
+        execute(machine, &mut false, "", method, &params)
+    }
+
+    fn error_code(result: RpcResult) -> i64 {
+        result.expect_err("Call should fail").0
+    }
+
+    #[test]
+    fn methods_run_and_inspect_the_machine() {
+        let mut machine = machine();
+        let mut paused = false;
+        let status = execute(&mut machine, &mut paused, "", "step", &json!({})).unwrap();
+        assert!(paused);
+        assert_eq!(status["pc"], 0x202);
+        let registers = call(&mut machine, "get_registers", json!({})).unwrap();
+        assert_eq!(registers["v"][0], 5);
+
+        let status = call(&mut machine, "run_frames", json!({"frames": 2})).unwrap();
+        assert_eq!(status["frame"], 2);
+        let bytes = call(
+            &mut machine,
+            "read_memory",
+            json!({"address": 0x200, "length": 2}),
+        );
+        assert_eq!(bytes.unwrap(), json!([0x60, 0x05]));
+        call(
+            &mut machine,
+            "write_memory",
+            json!({"address": 0x300, "data": [1, 2]}),
+        )
+        .unwrap();
+        assert_eq!(machine.cpu.memory()[0x300..0x302], [1, 2]);
+        call(&mut machine, "set_keys", json!({"keys": [1, 15, 16]})).unwrap();
+        assert_eq!(machine.keypad.state(), 0x8002);
+    }
+
+    #[test]
+    fn invalid_calls_change_nothing() {
+        let mut machine = machine();
+        assert_eq!(
+            error_code(call(&mut machine, "fly", json!({}))),
+            METHOD_NOT_FOUND
+        );
+        let huge = json!({"address": u64::MAX, "length": 2});
+        assert_eq!(
+            error_code(call(&mut machine, "read_memory", huge)),
+            INVALID_PARAMS
+        );
+        let outside = json!({"address": DEFAULT_MEMORY_SIZE - 1, "data": [1, 2]});
+        assert_eq!(
+            error_code(call(&mut machine, "write_memory", outside)),
+            INVALID_PARAMS
+        );
+
+        let registers = json!({"v": [9], "i": DEFAULT_MEMORY_SIZE});
+        assert_eq!(
+            error_code(call(&mut machine, "set_registers", registers)),
+            INVALID_PARAMS
+        );
+        let registers = json!({"v": [9], "pc": DEFAULT_MEMORY_SIZE - 1});
+        assert_eq!(
+            error_code(call(&mut machine, "set_registers", registers)),
+            INVALID_PARAMS
+        );
+        assert_eq!(machine.cpu.registers()[0], 0);
+        assert_eq!(machine.cpu.pc(), 0x200);
+
+        let path = std::env::temp_dir().join(format!("chip8-rpc-{}.ch8", std::process::id()));
+        std::fs::write(&path, vec![0; DEFAULT_MEMORY_SIZE]).unwrap();
+        let load = json!({"path": path.to_string_lossy()});
+        assert_eq!(
+            error_code(call(&mut machine, "load_rom", load)),
+            INVALID_PARAMS
+        );
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(machine.program(), [0x60, 0x05, 0x12, 0x02]);
+    }
+
+    #[test]
+    fn responses_keep_the_request_id() {
+        let mut machine = machine();
+        let mut paused = false;
+        let (reply, response) = mpsc::channel();
+        let request = json!({"jsonrpc": "2.0", "id": 7, "method": "nothing"});
+        let shutdown = Call { request, reply }.execute(&mut machine, &mut paused, "");
+        let response: Value = serde_json::from_str(&response.recv().unwrap()).unwrap();
+        assert!(!shutdown);
+        assert_eq!(response["id"], 7);
+        assert_eq!(response["error"]["code"], METHOD_NOT_FOUND);
+
+        let (reply, response) = mpsc::channel();
+        let request = json!({"jsonrpc": "2.0", "id": "last", "method": "shutdown"});
+        assert!(Call { request, reply }.execute(&mut machine, &mut paused, ""));
+        let response: Value = serde_json::from_str(&response.recv().unwrap()).unwrap();
+        assert_eq!(response["id"], "last");
+        assert_eq!(response["result"], Value::Null);
+    }
+}
@@ -0,0 +1,78 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::palette::Palette;
+
+/// Turn a frame buffer into RGB pixel data, drawing every CHIP-8 pixel as a
+/// `scale` x `scale` block in the colors of the palette
+pub fn frame_to_rgb(
+    frame_buffer: &[bool],
+    width: usize,
+    height: usize,
+    scale: usize,
+    palette: &Palette,
+) -> Vec<u8> {
+    let scale = scale.max(1);
+    let mut pixels = Vec::with_capacity(width * scale * height * scale * 3);
+    for y in 0..height * scale {
+        for x in 0..width * scale {
+            let lit = frame_buffer[(y / scale) * width + x / scale];
+            let color = if lit {
+                palette.foreground()
+            } else {
+                palette.background()
+            };
+            pixels.extend_from_slice(&[(color >> 16) as u8, (color >> 8) as u8, color as u8]);
+        }
+    }
+    pixels
+}
+
+/// Encode a frame buffer as PNG, see `frame_to_rgb` for the parameters
+pub fn write_png<W: Write>(
+    writer: W,
+    frame_buffer: &[bool],
+    width: usize,
+    height: usize,
+    scale: usize,
+    palette: &Palette,
+) -> io::Result<()> {
+    let scale = scale.max(1);
+    let mut encoder = png::Encoder::new(writer, (width * scale) as u32, (height * scale) as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut png_writer = encoder.write_header()?;
+    png_writer.write_image_data(&frame_to_rgb(frame_buffer, width, height, scale, palette))?;
+    png_writer.finish()?;
+    Ok(())
+}
+
+/// Save a frame buffer as PNG file, see `frame_to_rgb` for the parameters
+pub fn save_png(
+    path: &str,
+    frame_buffer: &[bool],
+    width: usize,
+    height: usize,
+    scale: usize,
+    palette: &Palette,
+) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    write_png(file, frame_buffer, width, height, scale, palette)
+}
+
+/// File name for a new screenshot in `directory`, based on the current time
+pub fn timestamped_path(directory: &str, extension: &str) -> String {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_millis())
+        .unwrap_or(0);
+    format!(
+        "{}/chip8-{}.{}",
+        directory.trim_end_matches('/'),
+        millis,
+        extension
+    )
+}
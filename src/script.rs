@@ -0,0 +1,314 @@
+use std::{cell::RefCell, collections::HashMap, mem, rc::Rc};
+
+use rhai::{CallFnOptions, Dynamic, Engine, EvalAltResult, FuncArgs, Scope, AST, INT};
+
+use crate::{machine::Machine, palette::Palette, screenshot, state::SaveState};
+
+/// Changes a script asks for, applied to the machine once the hook returns
+enum Command {
+    SetRegister(usize, u8),
+    SetIRegister(u16),
+    KeyDown(usize),
+    KeyUp(usize),
+    Screenshot(String),
+    SaveState(String),
+    LoadState(String),
+}
+
+/// What the functions of the script API see and collect. Reads come from a copy of the
+/// registers taken before every hook, writes become commands. The memory isn't copied,
+/// the machine lends it to the script while a hook runs, so `poke` writes it right away.
+#[derive(Default)]
+struct Context {
+    pc: u16,
+    i_register: u16,
+    registers: [u8; 16],
+    memory: Vec<u8>,
+    keys: u16,
+    frame: u64,
+    commands: Vec<Command>,
+    /// Functions to call when the instruction at an address is about to be executed
+    instruction_hooks: HashMap<u16, String>,
+    hud: Vec<String>,
+}
+
+/// A Rhai script that automates the machine, e.g. to play a ROM in tests, as bot,
+/// to show a HUD or to implement cheats.
+///
+/// Scripts hook events by defining functions: `on_frame(frame)` is called at the end of
+/// every frame and `on_memory_write(address, value)` for every byte an instruction writes.
+/// `on_instruction(address, "name")` calls the function `name` before the instruction at
+/// `address` is executed. See the README for the functions scripts can call.
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    context: Rc<RefCell<Context>>,
+    on_frame: bool,
+    on_memory_write: bool,
+    palette: Palette,
+    screenshot_scale: usize,
+}
+
+impl Script {
+    /// Compile the script at `path` and run its top level statements, which usually
+    /// register instruction hooks
+    pub fn load(
+        path: &str,
+        machine: &mut Machine,
+        palette: Palette,
+        screenshot_scale: usize,
+    ) -> Result<Self, String> {
+        let context = Rc::new(RefCell::new(Context::default()));
+        let mut engine = Engine::new();
+        Script::register_api(&mut engine, &context);
+        let ast = engine
+            .compile_file(path.into())
+            .map_err(|error| error.to_string())?;
+        let defines = |name: &str| ast.iter_functions().any(|function| function.name == name);
+        let on_frame = defines("on_frame");
+        let on_memory_write = defines("on_memory_write");
+
+        // Swapped with the memory of the machine while a hook runs
+        context.borrow_mut().memory = vec![0; machine.cpu.memory().len()];
+        let mut script = Self {
+            engine,
+            ast,
+            scope: Scope::new(),
+            context,
+            on_frame,
+            on_memory_write,
+            palette,
+            screenshot_scale,
+        };
+        if on_memory_write {
+            machine.cpu.memory_writes = Some(Vec::new());
+        }
+        script.take_snapshot(machine);
+        let result = script
+            .engine
+            .run_ast_with_scope(&mut script.scope, &script.ast);
+        script.apply_commands(machine);
+        result.map_err(|error| error.to_string())?;
+        Ok(script)
+    }
+
+    fn register_api(engine: &mut Engine, context: &Rc<RefCell<Context>>) {
+        let shared = context.clone();
+        engine.register_fn("pc", move || shared.borrow().pc as INT);
+        let shared = context.clone();
+        engine.register_fn("frame", move || shared.borrow().frame as INT);
+        let shared = context.clone();
+        engine.register_fn("register", move |x: INT| {
+            shared.borrow().registers[x as usize & 0xF] as INT
+        });
+        let shared = context.clone();
+        engine.register_fn("set_register", move |x: INT, value: INT| {
+            let mut context = shared.borrow_mut();
+            context.registers[x as usize & 0xF] = value as u8;
+            let command = Command::SetRegister(x as usize & 0xF, value as u8);
+            context.commands.push(command);
+        });
+        let shared = context.clone();
+        engine.register_fn("i_register", move || shared.borrow().i_register as INT);
+        let shared = context.clone();
+        engine.register_fn(
+            "set_i_register",
+            move |value: INT| -> Result<(), Box<EvalAltResult>> {
+                let mut context = shared.borrow_mut();
+                if !(0..context.memory.len() as INT).contains(&value) {
+                    return Err(format!("I {:#X} is outside the memory", value).into());
+                }
+                context.i_register = value as u16;
+                context.commands.push(Command::SetIRegister(value as u16));
+                Ok(())
+            },
+        );
+        let shared = context.clone();
+        engine.register_fn("peek", move |address: INT| {
+            let context = shared.borrow();
+            context.memory.get(address as usize).copied().unwrap_or(0) as INT
+        });
+        let shared = context.clone();
+        engine.register_fn("poke", move |address: INT, value: INT| {
+            let mut context = shared.borrow_mut();
+            if let Some(byte) = context.memory.get_mut(address as usize) {
+                *byte = value as u8;
+            }
+        });
+        let shared = context.clone();
+        engine.register_fn("is_key_pressed", move |key: INT| {
+            shared.borrow().keys & (1 << (key & 0xF)) != 0
+        });
+        let shared = context.clone();
+        engine.register_fn("press", move |key: INT| {
+            let mut context = shared.borrow_mut();
+            context.keys |= 1 << (key & 0xF);
+            context.commands.push(Command::KeyDown(key as usize & 0xF));
+        });
+        let shared = context.clone();
+        engine.register_fn("release", move |key: INT| {
+            let mut context = shared.borrow_mut();
+            context.keys &= !(1 << (key & 0xF));
+            context.commands.push(Command::KeyUp(key as usize & 0xF));
+        });
+        let shared = context.clone();
+        engine.register_fn("screenshot", move |path: &str| {
+            let command = Command::Screenshot(String::from(path));
+            shared.borrow_mut().commands.push(command);
+        });
+        let shared = context.clone();
+        engine.register_fn("save_state", move |path: &str| {
+            let command = Command::SaveState(String::from(path));
+            shared.borrow_mut().commands.push(command);
+        });
+        let shared = context.clone();
+        engine.register_fn("load_state", move |path: &str| {
+            let command = Command::LoadState(String::from(path));
+            shared.borrow_mut().commands.push(command);
+        });
+        let shared = context.clone();
+        engine.register_fn("hud", move |text: &str| {
+            shared.borrow_mut().hud = text.lines().map(String::from).collect();
+        });
+        let shared = context.clone();
+        engine.register_fn("on_instruction", move |address: INT, function: &str| {
+            let hooks = &mut shared.borrow_mut().instruction_hooks;
+            hooks.insert(address as u16, String::from(function));
+        });
+    }
+
+    /// Lines the script wants to show on top of the display
+    pub fn hud(&self) -> Vec<String> {
+        self.context.borrow().hud.clone()
+    }
+
+    /// Whether a function is hooked to the instruction at `address`
+    pub fn hooks_instruction(&self, address: u16) -> bool {
+        self.context
+            .borrow()
+            .instruction_hooks
+            .contains_key(&address)
+    }
+
+    pub fn on_frame(&mut self, machine: &mut Machine, frame: u64) -> Result<(), String> {
+        if !self.on_frame {
+            return Ok(());
+        }
+        self.call(machine, "on_frame", (frame as INT,))
+    }
+
+    pub fn on_instruction(&mut self, machine: &mut Machine, address: u16) -> Result<(), String> {
+        let function = self
+            .context
+            .borrow()
+            .instruction_hooks
+            .get(&address)
+            .cloned();
+        match function {
+            Some(function) => self.call(machine, &function, ()),
+            None => Ok(()),
+        }
+    }
+
+    /// Hand the bytes written since the last call to `on_memory_write`
+    pub fn on_memory_writes(&mut self, machine: &mut Machine) -> Result<(), String> {
+        let Some(writes) = machine.cpu.memory_writes.as_mut() else {
+            return Ok(());
+        };
+        let writes = mem::take(writes);
+        if !self.on_memory_write {
+            return Ok(());
+        }
+        for (address, value) in writes {
+            self.call(machine, "on_memory_write", (address as INT, value as INT))?;
+        }
+        Ok(())
+    }
+
+    /// Call a script function with the current machine state and apply the commands it
+    /// issued
+    fn call(
+        &mut self,
+        machine: &mut Machine,
+        function: &str,
+        arguments: impl FuncArgs,
+    ) -> Result<(), String> {
+        self.take_snapshot(machine);
+        // The top level statements only run once, when the script is loaded
+        let options = CallFnOptions::new().eval_ast(false);
+        let result = self.engine.call_fn_with_options::<Dynamic>(
+            options,
+            &mut self.scope,
+            &self.ast,
+            function,
+            arguments,
+        );
+        self.apply_commands(machine);
+        result
+            .map(|_| ())
+            .map_err(|error| format!("{}: {}", function, error))
+    }
+
+    /// Copy the registers for the script and lend it the memory, until `apply_commands`
+    fn take_snapshot(&self, machine: &mut Machine) {
+        let mut context = self.context.borrow_mut();
+        let cpu = &mut machine.cpu;
+        context.pc = cpu.pc();
+        context.i_register = cpu.i_register();
+        context.registers = *cpu.registers();
+        cpu.swap_memory(&mut context.memory);
+        context.keys = machine.keypad.state();
+        context.frame = machine.frame();
+    }
+
+    /// Give the memory back to the machine and apply the other changes of the script
+    fn apply_commands(&self, machine: &mut Machine) {
+        let commands = {
+            let mut context = self.context.borrow_mut();
+            machine.cpu.swap_memory(&mut context.memory);
+            mem::take(&mut context.commands)
+        };
+        for command in commands {
+            match command {
+                Command::SetRegister(x, value) => machine.cpu.set_register(x, value),
+                Command::SetIRegister(value) => machine.cpu.set_i_register(value),
+                Command::KeyDown(key) => machine.keypad.key_down(key),
+                Command::KeyUp(key) => machine.keypad.key_up(key),
+                Command::Screenshot(path) => {
+                    let (width, height) = machine.cpu.display_size();
+                    match screenshot::save_png(
+                        &path,
+                        machine.cpu.frame_buffer(),
+                        width,
+                        height,
+                        self.screenshot_scale,
+                        &self.palette,
+                    ) {
+                        Ok(()) => machine.log(&format!("Saved screenshot to {}", path)),
+                        Err(error) => {
+                            machine.log(&format!("Couldn't save screenshot to {}: {}", path, error))
+                        }
+                    }
+                }
+                Command::SaveState(path) => match machine.save_state().save(&path) {
+                    Ok(()) => machine.log(&format!("Saved state to {}", path)),
+                    Err(error) => {
+                        machine.log(&format!("Couldn't save state to {}: {}", path, error))
+                    }
+                },
+                Command::LoadState(path) => {
+                    let result = SaveState::load(&path)
+                        .map_err(|error| error.to_string())
+                        .and_then(|state| machine.load_state(&state));
+                    match result {
+                        Ok(()) => machine.log(&format!("Loaded state from {}", path)),
+                        Err(error) => {
+                            machine.log(&format!("Couldn't load state from {}: {}", path, error))
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use alloc::{string::String, vec::Vec};
+#[cfg(feature = "std")]
+use std::{
+    fs,
+    io::{self, ErrorKind},
+};
+
+/// Identifies save state files, followed by the format version
+#[cfg(feature = "std")]
+const MAGIC: &[u8; 8] = b"CH8STATE";
+#[cfg(feature = "std")]
+const VERSION: u8 = 3;
+
+/// Everything of the CPU that has to be restored to continue from a saved point
+pub struct CpuState {
+    pub pc: u16,
+    pub i_register: u16,
+    pub registers: [u8; 16],
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub memory: Vec<u8>,
+    pub frame_buffer: Vec<bool>,
+    pub stack: Vec<u16>,
+    /// Keys the CPU saw pressed in the last cycle, to detect releases while waiting
+    pub keys: u16,
+    pub display_width: usize,
+    pub display_height: usize,
+    /// Register the key is stored in while waiting for a key press (FX0A)
+    pub waiting_register: Option<u8>,
+    pub panic_message: Option<String>,
+    /// Nanoseconds passed since the timers were last counted down
+    pub time_since_last_decrease: u64,
+    /// Seed of the random numbers of CXNN and the position in their stream, in 32 bit
+    /// words
+    pub rng_seed: u64,
+    pub rng_position: u64,
+}
+
+/// A snapshot of the whole machine that can be saved to a file and loaded again later
+#[cfg(feature = "std")]
+pub struct SaveState {
+    pub cpu: CpuState,
+    pub keys: u16,
+    pub cycle: u64,
+}
+
+#[cfg(feature = "std")]
+impl SaveState {
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        fs::write(path, self.to_bytes())
+    }
+
+    pub fn load(path: &str) -> io::Result<Self> {
+        let data = fs::read(path)?;
+        SaveState::from_bytes(&data).map_err(|error| match error.kind() {
+            ErrorKind::InvalidData => io::Error::new(
+                ErrorKind::InvalidData,
+                format!("{} is no save state of this version", path),
+            ),
+            _ => error,
+        })
+    }
+
+    /// Encode the state in the binary format of save state files. All numbers are big
+    /// endian, lists are prefixed with their length.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let cpu = &self.cpu;
+        let mut data = Vec::with_capacity(cpu.memory.len() + cpu.frame_buffer.len() + 128);
+        data.extend_from_slice(MAGIC);
+        data.push(VERSION);
+        data.extend_from_slice(&self.cycle.to_be_bytes());
+        data.extend_from_slice(&self.keys.to_be_bytes());
+        data.extend_from_slice(&cpu.pc.to_be_bytes());
+        data.extend_from_slice(&cpu.i_register.to_be_bytes());
+        data.extend_from_slice(&cpu.registers);
+        data.extend_from_slice(&[cpu.delay_timer, cpu.sound_timer]);
+        data.extend_from_slice(&cpu.keys.to_be_bytes());
+        data.extend_from_slice(&(cpu.display_width as u16).to_be_bytes());
+        data.extend_from_slice(&(cpu.display_height as u16).to_be_bytes());
+        data.push(cpu.waiting_register.unwrap_or(0xFF));
+        data.extend_from_slice(&cpu.time_since_last_decrease.to_be_bytes());
+        data.extend_from_slice(&cpu.rng_seed.to_be_bytes());
+        data.extend_from_slice(&cpu.rng_position.to_be_bytes());
+        data.extend_from_slice(&(cpu.stack.len() as u32).to_be_bytes());
+        for address in &cpu.stack {
+            data.extend_from_slice(&address.to_be_bytes());
+        }
+        data.extend_from_slice(&(cpu.memory.len() as u32).to_be_bytes());
+        data.extend_from_slice(&cpu.memory);
+        data.extend_from_slice(&(cpu.frame_buffer.len() as u32).to_be_bytes());
+        data.extend(cpu.frame_buffer.iter().map(|&pixel| pixel as u8));
+        let message = cpu.panic_message.as_deref().unwrap_or("");
+        data.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        data.extend_from_slice(message.as_bytes());
+        data
+    }
+
+    pub fn from_bytes(data: &[u8]) -> io::Result<Self> {
+        let mut reader = Reader { data };
+        if reader.bytes(MAGIC.len())? != MAGIC || reader.u8()? != VERSION {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "no save state of this version",
+            ));
+        }
+        let cycle = reader.u64()?;
+        let keys = reader.u16()?;
+        let pc = reader.u16()?;
+        let i_register = reader.u16()?;
+        let mut registers = [0; 16];
+        registers.copy_from_slice(reader.bytes(16)?);
+        let delay_timer = reader.u8()?;
+        let sound_timer = reader.u8()?;
+        let cpu_keys = reader.u16()?;
+        let display_width = reader.u16()? as usize;
+        let display_height = reader.u16()? as usize;
+        let waiting_register = Some(reader.u8()?).filter(|&register| register < 16);
+        let time_since_last_decrease = reader.u64()?;
+        let rng_seed = reader.u64()?;
+        let rng_position = reader.u64()?;
+        let stack_size = reader.u32()? as usize;
+        let stack = (0..stack_size)
+            .map(|_| reader.u16())
+            .collect::<io::Result<_>>()?;
+        let memory_size = reader.u32()? as usize;
+        let memory = reader.bytes(memory_size)?.to_vec();
+        let frame_buffer_size = reader.u32()? as usize;
+        let frame_buffer = reader
+            .bytes(frame_buffer_size)?
+            .iter()
+            .map(|&pixel| pixel != 0)
+            .collect();
+        let message_length = reader.u32()? as usize;
+        let message = String::from_utf8_lossy(reader.bytes(message_length)?).into_owned();
+
+        Ok(Self {
+            cpu: CpuState {
+                pc,
+                i_register,
+                registers,
+                delay_timer,
+                sound_timer,
+                memory,
+                frame_buffer,
+                stack,
+                keys: cpu_keys,
+                display_width,
+                display_height,
+                waiting_register,
+                panic_message: Some(message).filter(|message| !message.is_empty()),
+                time_since_last_decrease,
+                rng_seed,
+                rng_position,
+            },
+            keys,
+            cycle,
+        })
+    }
+}
+
+/// Reads big endian numbers from the front of a byte slice
+#[cfg(feature = "std")]
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+#[cfg(feature = "std")]
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, length: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() < length {
+            return Err(io::Error::from(ErrorKind::UnexpectedEof));
+        }
+        let (bytes, rest) = self.data.split_at(length);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.bytes(4)?);
+        Ok(u32::from_be_bytes(bytes))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_be_bytes(bytes))
+    }
+}
@@ -0,0 +1,140 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, io,
+};
+
+/// Label names of a ROM, shown instead of raw addresses in disassembly, traces and the
+/// debugger.
+///
+/// Symbol files have one symbol per line, as `<address> <name>`, `<name> = <address>`
+/// or Octo's `:const <name> <value>`. Addresses are hex, with or without `0x`, `:const`
+/// values follow Octo: decimal unless they start with `0x` or `0b`.
+/// Lines starting with `#` are comments.
+#[derive(Clone, Default)]
+pub struct Symbols {
+    names: BTreeMap<u16, String>,
+    addresses: HashMap<String, u16>,
+}
+
+impl Symbols {
+    pub fn load(path: &str) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        let mut symbols = Symbols::default();
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match Symbols::parse_line(line) {
+                Some((address, name)) => symbols.insert(address, name),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{}:{}: expected `<address> <name>`", path, number + 1),
+                    ))
+                }
+            }
+        }
+        Ok(symbols)
+    }
+
+    /// The form of the line decides which token is the name, since names like `add` or
+    /// `beef` are valid hex numbers as well
+    fn parse_line(line: &str) -> Option<(u16, &str)> {
+        if let Some(rest) = line.strip_prefix(":const ") {
+            let [name, value] = Symbols::tokens(rest)?;
+            return Some((Symbols::parse_octo_number(value)?, name));
+        }
+        if let Some((name, address)) = line.split_once('=') {
+            let [name] = Symbols::tokens(name)?;
+            let [address] = Symbols::tokens(address)?;
+            return Some((Symbols::parse_address(address)?, name));
+        }
+        let [address, name] = Symbols::tokens(line)?;
+        Some((Symbols::parse_address(address)?, name))
+    }
+
+    /// Exactly `N` whitespace separated tokens
+    fn tokens<const N: usize>(text: &str) -> Option<[&str; N]> {
+        let tokens: Vec<&str> = text.split_whitespace().collect();
+        tokens.try_into().ok()
+    }
+
+    /// Hex address with or without `0x`
+    pub fn parse_address(value: &str) -> Option<u16> {
+        let digits = value.strip_prefix("0x").unwrap_or(value);
+        u16::from_str_radix(digits, 16).ok()
+    }
+
+    /// Number literal of Octo: decimal, or hex with `0x` and binary with `0b`
+    fn parse_octo_number(value: &str) -> Option<u16> {
+        if let Some(digits) = value.strip_prefix("0x") {
+            u16::from_str_radix(digits, 16).ok()
+        } else if let Some(digits) = value.strip_prefix("0b") {
+            u16::from_str_radix(digits, 2).ok()
+        } else {
+            value.parse().ok()
+        }
+    }
+
+    pub fn insert(&mut self, address: u16, name: &str) {
+        self.names.insert(address, String::from(name));
+        self.addresses.insert(String::from(name), address);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Name of the symbol at exactly `address`
+    pub fn name(&self, address: u16) -> Option<&str> {
+        self.names.get(&address).map(String::as_str)
+    }
+
+    /// Address of a symbol, or a hex address if there is no symbol of that name
+    pub fn resolve(&self, name: &str) -> Option<u16> {
+        self.addresses
+            .get(name)
+            .copied()
+            .or_else(|| Symbols::parse_address(name))
+    }
+
+    /// `address` relative to the closest symbol before it, like `draw_paddle+6`,
+    /// or as hex address if there is no symbol before it
+    pub fn describe(&self, address: u16) -> String {
+        match self.names.range(..=address).next_back() {
+            Some((&start, name)) if start == address => name.clone(),
+            Some((&start, name)) => format!("{}+{:X}", name, address - start),
+            None => format!("{:04X}", address),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_that_are_hex_numbers_are_names() {
+        assert_eq!(Symbols::parse_line("2A0 add"), Some((0x2A0, "add")));
+        assert_eq!(Symbols::parse_line("beef = 0x2A0"), Some((0x2A0, "beef")));
+        assert_eq!(Symbols::parse_line("face=2A0"), Some((0x2A0, "face")));
+        assert_eq!(Symbols::parse_line(":const a 0x2A0"), Some((0x2A0, "a")));
+        assert_eq!(Symbols::parse_line("draw_paddle 2A0"), None);
+        assert_eq!(Symbols::parse_line("a = b = 2A0"), None);
+    }
+
+    #[test]
+    fn octo_constants_are_decimal_without_prefix() {
+        assert_eq!(Symbols::parse_line(":const SPEED 10"), Some((10, "SPEED")));
+        assert_eq!(
+            Symbols::parse_line(":const MASK 0b1010"),
+            Some((10, "MASK"))
+        );
+        assert_eq!(
+            Symbols::parse_line(":const BALL 0x2A0"),
+            Some((0x2A0, "BALL"))
+        );
+        assert_eq!(Symbols::parse_line(":const BALL 2A0"), None);
+    }
+}
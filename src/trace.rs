@@ -0,0 +1,221 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufWriter, Write},
+};
+
+use crate::{cpu::CPU, disassembler, symbols::Symbols};
+
+/// Selects the instructions that are written to the trace. Unset parts match everything.
+#[derive(Clone, Copy, Default)]
+pub struct TraceFilter {
+    /// Inclusive range of instruction addresses
+    pub addresses: Option<(u16, u16)>,
+    /// Opcode families as bit mask, bit N set traces the instructions starting with nibble N
+    pub families: Option<u16>,
+    /// Frames from the first one up to, but not including, the second one
+    pub frames: Option<(u64, u64)>,
+}
+
+impl TraceFilter {
+    /// Parse an address range like `200-2FF` or a single address like `23A`, in hex
+    pub fn parse_addresses(value: &str) -> Option<(u16, u16)> {
+        let (start, end) = value.split_once('-').unwrap_or((value, value));
+        let start = u16::from_str_radix(start.trim().trim_start_matches("0x"), 16).ok()?;
+        let end = u16::from_str_radix(end.trim().trim_start_matches("0x"), 16).ok()?;
+        (start <= end).then_some((start, end))
+    }
+
+    /// Parse a comma separated list of opcode families given by their first hex digit,
+    /// e.g. `D,F` for drawing and the 0xF... instructions
+    pub fn parse_families(value: &str) -> Option<u16> {
+        let mut families = 0;
+        for family in value.split(',') {
+            let family = family.trim().trim_start_matches("0x");
+            if family.len() != 1 {
+                return None;
+            }
+            families |= 1 << u16::from_str_radix(family, 16).ok()?;
+        }
+        Some(families)
+    }
+
+    /// Parse a frame window like `120-180`
+    pub fn parse_frames(value: &str) -> Option<(u64, u64)> {
+        let (start, end) = value.split_once('-')?;
+        let start = start.trim().parse().ok()?;
+        let end = end.trim().parse().ok()?;
+        (start < end).then_some((start, end))
+    }
+
+    fn matches(&self, frame: u64, pc: u16, opcode: u16) -> bool {
+        self.addresses
+            .is_none_or(|(start, end)| (start..=end).contains(&pc))
+            && self
+                .families
+                .is_none_or(|families| families & (1 << (opcode >> 12)) != 0)
+            && self
+                .frames
+                .is_none_or(|(start, end)| (start..end).contains(&frame))
+    }
+}
+
+/// The state an instruction can change, besides the PC and memory
+#[derive(Clone, Copy, PartialEq)]
+pub struct Snapshot {
+    registers: [u8; 16],
+    i_register: u16,
+    delay_timer: u8,
+    sound_timer: u8,
+    stack_pointer: usize,
+}
+
+impl Snapshot {
+    pub fn of(cpu: &CPU) -> Self {
+        Self {
+            registers: *cpu.registers(),
+            i_register: cpu.i_register(),
+            delay_timer: cpu.delay_timer(),
+            sound_timer: cpu.sound_timer(),
+            stack_pointer: cpu.stack().len(),
+        }
+    }
+}
+
+/// One executed instruction. The mnemonic and the changes are only worked out when the
+/// entry is written, so keeping entries in the ring buffer stays cheap.
+struct Entry {
+    cycle: u64,
+    frame: u64,
+    pc: u16,
+    opcode: u16,
+    before: Snapshot,
+    after: Snapshot,
+}
+
+impl Entry {
+    /// Write the entry as a line like
+    /// `4711 7 022A D015 DRW V0, V1, 5      VF=00>01`,
+    /// preceded by a `# name:` line if the instruction has a symbol
+    fn write(&self, out: &mut impl Write, symbols: &Symbols) -> io::Result<()> {
+        if let Some(name) = symbols.name(self.pc) {
+            writeln!(out, "# {}:", name)?;
+        }
+        let (before, after) = (&self.before, &self.after);
+        let mut changes = String::new();
+        for (index, (old, new)) in before.registers.iter().zip(&after.registers).enumerate() {
+            if old != new {
+                changes += &format!(" V{:X}={:02X}>{:02X}", index, old, new);
+            }
+        }
+        if before.i_register != after.i_register {
+            changes += &format!(" I={:03X}>{:03X}", before.i_register, after.i_register);
+        }
+        if before.delay_timer != after.delay_timer {
+            changes += &format!(" DT={}>{}", before.delay_timer, after.delay_timer);
+        }
+        if before.sound_timer != after.sound_timer {
+            changes += &format!(" ST={}>{}", before.sound_timer, after.sound_timer);
+        }
+        if before.stack_pointer != after.stack_pointer {
+            changes += &format!(" SP={}>{}", before.stack_pointer, after.stack_pointer);
+        }
+        let line = format!(
+            "{} {} {:04X} {:04X} {:<16}{}",
+            self.cycle,
+            self.frame,
+            self.pc,
+            self.opcode,
+            disassembler::disassemble_with_symbols(self.opcode, symbols),
+            changes
+        );
+        writeln!(out, "{}", line.trim_end())
+    }
+}
+
+/// Writes executed instructions to a text file, one per line with the cycle, frame, PC,
+/// opcode, mnemonic and the registers it changed.
+///
+/// With a ring buffer only the most recent entries are kept in memory, and they are written
+/// when the CPU panics, showing how the program got there without the cost of a full trace.
+pub struct Tracer {
+    writer: BufWriter<File>,
+    filter: TraceFilter,
+    ring: Option<(VecDeque<Entry>, usize)>,
+    symbols: Symbols,
+}
+
+impl Tracer {
+    /// Create the trace file. `ring_size` is the number of entries of the ring buffer,
+    /// without one every instruction is written right away.
+    pub fn create(
+        path: &str,
+        filter: TraceFilter,
+        ring_size: Option<usize>,
+        symbols: Symbols,
+    ) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "# cycle frame pc opcode instruction changes")?;
+        Ok(Self {
+            writer,
+            filter,
+            ring: ring_size.map(|size| (VecDeque::with_capacity(size), size.max(1))),
+            symbols,
+        })
+    }
+
+    /// Name addresses with other symbols from now on, e.g. after a reload of the ROM
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    /// Note an executed instruction, if it passes the filter
+    pub fn record(
+        &mut self,
+        cycle: u64,
+        frame: u64,
+        pc: u16,
+        opcode: u16,
+        before: Snapshot,
+        after: Snapshot,
+    ) -> io::Result<()> {
+        if !self.filter.matches(frame, pc, opcode) {
+            return Ok(());
+        }
+        let entry = Entry {
+            cycle,
+            frame,
+            pc,
+            opcode,
+            before,
+            after,
+        };
+        match &mut self.ring {
+            Some((entries, size)) => {
+                if entries.len() == *size {
+                    entries.pop_front();
+                }
+                entries.push_back(entry);
+                Ok(())
+            }
+            None => entry.write(&mut self.writer, &self.symbols),
+        }
+    }
+
+    /// Write the ring buffer, if there is one, followed by the panic message
+    pub fn record_panic(&mut self, message: &str) -> io::Result<()> {
+        if let Some((entries, _)) = &mut self.ring {
+            for entry in entries.drain(..) {
+                entry.write(&mut self.writer, &self.symbols)?;
+            }
+        }
+        writeln!(self.writer, "# PANIC: {}", message)?;
+        self.writer.flush()
+    }
+
+    /// Write buffered lines to the file. The window may be closed without giving the
+    /// tracer a chance to finish, so this is done once per frame.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
@@ -0,0 +1,410 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Stdout, Write},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crossterm::{
+    cursor,
+    event::{
+        self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+        PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+    },
+    execute, queue,
+    style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor},
+    terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+};
+
+use crate::{
+    config::Config,
+    coverage,
+    cpu::{self, Console},
+    frontend::{self, AudioSink, DisplaySink, InputSource, RealTime},
+    keypad::key_for_char,
+    machine::Machine,
+    palette::Palette,
+    profiler,
+};
+
+/// Most terminals only report key presses, so a key counts as held for this long after
+/// its last press or auto repeat
+const KEY_HOLD: Duration = Duration::from_millis(150);
+/// Columns between the display and the register panel
+const PANEL_MARGIN: u16 = 2;
+/// Latest messages of the machine shown at the bottom of the panel
+const MESSAGE_LINES: usize = 5;
+
+/// Characters used to draw the display
+#[derive(Clone, Copy, PartialEq)]
+pub enum Charset {
+    /// `▀` with foreground and background color, one cell shows 1x2 pixels
+    HalfBlock,
+    /// Braille patterns, one cell shows 2x4 pixels
+    Braille,
+}
+
+impl Charset {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "half" => Some(Charset::HalfBlock),
+            "braille" => Some(Charset::Braille),
+            _ => None,
+        }
+    }
+
+    /// Pixels covered by a single character as (columns, rows)
+    fn cell_size(&self) -> (usize, usize) {
+        match self {
+            Charset::HalfBlock => (1, 2),
+            Charset::Braille => (2, 4),
+        }
+    }
+}
+
+/// Draws the display with Unicode characters next to a register panel
+struct Terminal {
+    palette: Palette,
+    charset: Charset,
+    /// Whether the terminal reports key releases
+    release_events: bool,
+    /// Panel lines drawn last time, only the lines that changed are drawn again
+    panel: Vec<String>,
+    /// Size of the terminal when it was last drawn, it is cleared after resizes
+    size: (u16, u16),
+    messages: PanelConsole,
+    out: Stdout,
+}
+
+/// Collects the messages of the CPU and the machine for the panel, printing them would
+/// scroll the terminal
+#[derive(Clone, Default)]
+struct PanelConsole {
+    lines: Arc<Mutex<VecDeque<String>>>,
+}
+
+impl Console for PanelConsole {
+    fn write_line(&mut self, line: &str) {
+        let mut lines = self.lines.lock().expect("Console lock poisoned");
+        lines.extend(line.lines().map(String::from));
+        while lines.len() > MESSAGE_LINES {
+            lines.pop_front();
+        }
+    }
+}
+
+/// Reads the keys from the terminal
+struct TerminalInput {
+    /// When keys that were pressed without a release event get released
+    key_release: [Option<Instant>; 16],
+    release_events: bool,
+}
+
+/// Rings the terminal bell when the beeper starts
+struct TerminalBell {
+    beeping: bool,
+    out: Stdout,
+}
+
+/// Run the machine in the terminal until Escape or Ctrl+C is pressed
+pub fn run(config: &Config, mut machine: Machine) -> io::Result<()> {
+    let charset = Charset::from_name(&config.tui_charset).unwrap_or(Charset::HalfBlock);
+    let mut terminal = Terminal {
+        palette: config.palette,
+        charset,
+        release_events: false,
+        panel: Vec::new(),
+        size: (0, 0),
+        messages: PanelConsole::default(),
+        out: io::stdout(),
+    };
+    machine.cpu.set_console(Box::new(terminal.messages.clone()));
+    terminal.setup()?;
+    let mut input = TerminalInput {
+        key_release: [None; 16],
+        release_events: terminal.release_events,
+    };
+    let mut bell = TerminalBell {
+        beeping: false,
+        out: io::stdout(),
+    };
+    let result = frontend::run(
+        &mut machine,
+        &mut terminal,
+        &mut input,
+        &mut bell,
+        &mut RealTime::default(),
+    );
+    // Always give the terminal back in a usable state, even if the loop failed
+    terminal.restore()?;
+    machine.cpu.set_console(Box::new(cpu::Stdout));
+    for line in terminal
+        .messages
+        .lines
+        .lock()
+        .expect("Console lock poisoned")
+        .iter()
+    {
+        println!("{}", line);
+    }
+    if let Some(path) = &config.profile {
+        profiler::save_report(&machine.cpu, &machine.symbols, path);
+    }
+    if let Some(path) = &config.coverage {
+        coverage::save_report(
+            &machine.cpu,
+            &machine.symbols,
+            machine.program().len(),
+            path,
+        );
+    }
+    result
+}
+
+impl InputSource for TerminalInput {
+    fn poll(&mut self, machine: &mut Machine, paused: &mut bool) -> io::Result<bool> {
+        while event::poll(Duration::ZERO)? {
+            let quit = match event::read()? {
+                Event::Key(key) => self.handle_key(key, machine, paused),
+                _ => false,
+            };
+            if quit {
+                return Ok(true);
+            }
+        }
+        self.release_held_keys(machine);
+        Ok(false)
+    }
+}
+
+impl TerminalInput {
+    /// Handle a key event, returns true if the frontend should quit
+    fn handle_key(&mut self, key: KeyEvent, machine: &mut Machine, paused: &mut bool) -> bool {
+        let pressed = key.kind != KeyEventKind::Release;
+        match key.code {
+            KeyCode::Esc => return true,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return true,
+            KeyCode::Char(' ') if pressed => *paused = !*paused,
+            KeyCode::F(5) if pressed => machine.soft_reset(),
+            KeyCode::F(6) if pressed => machine.hard_reset(),
+            KeyCode::Char(c) => {
+                if let Some(id) = key_for_char(c) {
+                    if pressed {
+                        machine.keypad.key_down(id);
+                        if !self.release_events {
+                            self.key_release[id] = Some(Instant::now() + KEY_HOLD);
+                        }
+                    } else {
+                        machine.keypad.key_up(id);
+                    }
+                }
+            }
+            _ => (),
+        }
+        false
+    }
+
+    fn release_held_keys(&mut self, machine: &mut Machine) {
+        let now = Instant::now();
+        for (id, release) in self.key_release.iter_mut().enumerate() {
+            if release.is_some_and(|time| time <= now) {
+                *release = None;
+                machine.keypad.key_up(id);
+            }
+        }
+    }
+}
+
+impl AudioSink for TerminalBell {
+    fn beep(&mut self, on: bool) {
+        if on && !self.beeping {
+            // The bell is only a hint, a terminal without one doesn't stop the frontend
+            let _ = self.out.write_all(b"\x07").and_then(|()| self.out.flush());
+        }
+        self.beeping = on;
+    }
+}
+
+impl DisplaySink for Terminal {
+    fn show(&mut self, machine: &Machine, changed: bool, paused: bool) -> io::Result<()> {
+        self.draw(machine, changed, paused)
+    }
+}
+
+impl Terminal {
+    fn setup(&mut self) -> io::Result<()> {
+        terminal::enable_raw_mode()?;
+        execute!(
+            self.out,
+            EnterAlternateScreen,
+            cursor::Hide,
+            Clear(ClearType::All)
+        )?;
+        self.release_events = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if self.release_events {
+            execute!(
+                self.out,
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+        }
+        Ok(())
+    }
+
+    fn restore(&mut self) -> io::Result<()> {
+        if self.release_events {
+            execute!(self.out, PopKeyboardEnhancementFlags)?;
+        }
+        execute!(self.out, ResetColor, cursor::Show, LeaveAlternateScreen)?;
+        terminal::disable_raw_mode()
+    }
+
+    /// Draw what changed since the last call, every cell of the display costs color
+    /// escapes, which adds up over slow connections like SSH
+    fn draw(&mut self, machine: &Machine, changed: bool, paused: bool) -> io::Result<()> {
+        let size = terminal::size()?;
+        let resized = size != self.size;
+        if resized {
+            queue!(self.out, Clear(ClearType::All))?;
+            self.size = size;
+            self.panel.clear();
+        }
+        let (width, height) = machine.cpu.display_size();
+        let (cell_width, cell_height) = self.charset.cell_size();
+        let columns = width.div_ceil(cell_width);
+        let rows = if changed || resized {
+            height.div_ceil(cell_height)
+        } else {
+            0
+        };
+        let background = Terminal::color(self.palette.background());
+        let foreground = Terminal::color(self.palette.foreground());
+
+        for row in 0..rows {
+            queue!(self.out, cursor::MoveTo(0, row as u16))?;
+            match self.charset {
+                Charset::HalfBlock => {
+                    for x in 0..columns {
+                        let top = Terminal::pixel(machine, x, row * 2);
+                        let bottom = Terminal::pixel(machine, x, row * 2 + 1);
+                        queue!(
+                            self.out,
+                            SetForegroundColor(if top { foreground } else { background }),
+                            SetBackgroundColor(if bottom { foreground } else { background }),
+                            Print('▀')
+                        )?;
+                    }
+                }
+                Charset::Braille => {
+                    queue!(
+                        self.out,
+                        SetForegroundColor(foreground),
+                        SetBackgroundColor(background)
+                    )?;
+                    for column in 0..columns {
+                        let c = Terminal::braille(machine, column * 2, row * 4);
+                        queue!(self.out, Print(c))?;
+                    }
+                }
+            }
+            queue!(self.out, ResetColor)?;
+        }
+
+        let panel_column = columns as u16 + PANEL_MARGIN;
+        // Long lines like memory dumps would wrap into the display. Terminals that don't
+        // report their size get the whole lines.
+        let panel_width = match self.size.0 {
+            0 => usize::MAX,
+            width => width.saturating_sub(panel_column) as usize,
+        };
+        let lines: Vec<String> = self
+            .panel_lines(machine, paused)
+            .iter()
+            .map(|line| line.chars().take(panel_width).collect())
+            .collect();
+        for row in 0..lines.len().max(self.panel.len()) {
+            let line = lines.get(row).map(String::as_str).unwrap_or("");
+            if self.panel.get(row).map(String::as_str) == Some(line) {
+                continue;
+            }
+            queue!(
+                self.out,
+                cursor::MoveTo(panel_column, row as u16),
+                Print(line),
+                Clear(ClearType::UntilNewLine)
+            )?;
+        }
+        self.panel = lines;
+        self.out.flush()
+    }
+
+    fn pixel(machine: &Machine, x: usize, y: usize) -> bool {
+        let (width, height) = machine.cpu.display_size();
+        x < width && y < height && machine.cpu.frame_buffer()[y * width + x]
+    }
+
+    /// Braille character for the 2x4 pixels with the top left corner at (x, y)
+    fn braille(machine: &Machine, x: usize, y: usize) -> char {
+        // Bit of every dot, indexed by [row][column]
+        const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+        let mut pattern = 0;
+        for (row, bits) in DOTS.iter().enumerate() {
+            for (column, bit) in bits.iter().enumerate() {
+                if Terminal::pixel(machine, x + column, y + row) {
+                    pattern |= bit;
+                }
+            }
+        }
+        char::from_u32(0x2800 + pattern).unwrap_or(' ')
+    }
+
+    fn panel_lines(&self, machine: &Machine, paused: bool) -> Vec<String> {
+        let cpu = &machine.cpu;
+        let mut lines = vec![
+            format!("PC {:04X}  I {:04X}", cpu.pc(), cpu.i_register()),
+            format!("DT {:>4}  ST {:>3}", cpu.delay_timer(), cpu.sound_timer()),
+            format!("SP {:>4}", cpu.stack().len()),
+            String::new(),
+        ];
+        for (index, values) in cpu.registers().chunks(2).enumerate() {
+            lines.push(format!(
+                "V{:X} {:02X}    V{:X} {:02X}",
+                index * 2,
+                values[0],
+                index * 2 + 1,
+                values[1]
+            ));
+        }
+        lines.push(String::new());
+        lines.push(format!("Frame {}", machine.frame()));
+        if paused {
+            lines.push(String::from("PAUSED"));
+        } else if cpu.is_waiting_for_key() {
+            lines.push(String::from("Waiting for key"));
+        }
+        if let Some(message) = cpu.panic_message() {
+            lines.push(format!("PANIC: {}", message));
+        }
+        if let Some(watcher) = &machine.watcher {
+            lines.extend(watcher.error_lines());
+        }
+        if let Some(script) = &machine.script {
+            lines.extend(script.hud());
+        }
+        let messages = self.messages.lines.lock().expect("Console lock poisoned");
+        if !messages.is_empty() {
+            lines.push(String::new());
+            lines.extend(messages.iter().cloned());
+        }
+        lines.push(String::new());
+        lines.push(String::from("Space pause  F5/F6 reset  Esc quit"));
+        lines
+    }
+
+    fn color(color: u32) -> Color {
+        Color::Rgb {
+            r: (color >> 16) as u8,
+            g: (color >> 8) as u8,
+            b: color as u8,
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use std::cell::RefCell;
+
+use crate::{
+    clock::FrameClock, cpu::CPU, keypad::Keypad, DEFAULT_FRAME_BUFFER_SIZE,
+    DEFAULT_INSTRUCTIONS_PER_SECOND, DEFAULT_MAX_STACK_SIZE, DEFAULT_MEMORY_SIZE, FONT,
+};
+
+/// The emulator behind the exported functions. JavaScript drives it with `run_frame`
+/// from its animation loop, so it needs no clock, and passes a seed for the random
+/// numbers.
+struct WebEmulator {
+    cpu: CPU,
+    keypad: Keypad,
+    program: Vec<u8>,
+    clock: FrameClock,
+    /// JavaScript writes the ROM here before calling `load_rom`
+    rom_buffer: Vec<u8>,
+    /// The display as one byte per pixel, 1 for set pixels, updated after every frame.
+    /// It moves when the resolution changes.
+    pixels: Vec<u8>,
+}
+
+thread_local! {
+    // WebAssembly modules run on a single thread
+    static EMULATOR: RefCell<WebEmulator> = RefCell::new(WebEmulator::new());
+}
+
+impl WebEmulator {
+    fn new() -> Self {
+        Self {
+            cpu: CPU::new(
+                &FONT,
+                DEFAULT_MEMORY_SIZE,
+                DEFAULT_FRAME_BUFFER_SIZE,
+                DEFAULT_MAX_STACK_SIZE,
+            ),
+            keypad: Keypad::new(),
+            program: Vec::new(),
+            clock: FrameClock::new(DEFAULT_INSTRUCTIONS_PER_SECOND),
+            rom_buffer: vec![0; DEFAULT_MEMORY_SIZE - 0x200],
+            pixels: Vec::new(),
+        }
+    }
+
+    fn update_pixels(&mut self) {
+        self.pixels.clear();
+        let pixels = self.cpu.frame_buffer().iter().map(|&on| on as u8);
+        self.pixels.extend(pixels);
+    }
+}
+
+fn with_emulator<T>(f: impl FnOnce(&mut WebEmulator) -> T) -> T {
+    EMULATOR.with(|emulator| f(&mut emulator.borrow_mut()))
+}
+
+/// Address of the buffer the ROM is written to before `load_rom`
+#[no_mangle]
+pub extern "C" fn rom_buffer() -> *mut u8 {
+    with_emulator(|emulator| emulator.rom_buffer.as_mut_ptr())
+}
+
+#[no_mangle]
+pub extern "C" fn rom_buffer_size() -> usize {
+    with_emulator(|emulator| emulator.rom_buffer.len())
+}
+
+/// Start the first `length` bytes of the ROM buffer as program with cleared memory
+#[no_mangle]
+pub extern "C" fn load_rom(length: usize) -> bool {
+    with_emulator(|emulator| {
+        let Some(program) = emulator.rom_buffer.get(..length) else {
+            return false;
+        };
+        emulator.program = program.to_vec();
+        emulator.cpu.hard_reset();
+        emulator.cpu.set_program(&emulator.program);
+        emulator.keypad = Keypad::new();
+        emulator.clock.reset();
+        emulator.update_pixels();
+        true
+    })
+}
+
+/// Start the program again, memory outside of it is kept
+#[no_mangle]
+pub extern "C" fn reset() {
+    with_emulator(|emulator| {
+        emulator.cpu.reset();
+        emulator.cpu.set_program(&emulator.program);
+        emulator.clock.reset();
+        emulator.update_pixels();
+    })
+}
+
+/// Seed the random numbers of CXNN, e.g. with the current time
+#[no_mangle]
+pub extern "C" fn seed(seed: u32) {
+    with_emulator(|emulator| emulator.cpu.seed_rng(seed as u64))
+}
+
+#[no_mangle]
+pub extern "C" fn key_down(key: usize) {
+    if key < 16 {
+        with_emulator(|emulator| emulator.keypad.key_down(key))
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn key_up(key: usize) {
+    if key < 16 {
+        with_emulator(|emulator| emulator.keypad.key_up(key))
+    }
+}
+
+/// Execute one 60 Hz frame
+#[no_mangle]
+pub extern "C" fn run_frame() {
+    with_emulator(|emulator| {
+        emulator
+            .clock
+            .run_frame(&mut emulator.cpu, &emulator.keypad);
+        emulator.update_pixels();
+    })
+}
+
+/// Address of the display pixels, row by row, see `display_width` and `display_height`.
+/// It is only valid until the next call.
+#[no_mangle]
+pub extern "C" fn framebuffer() -> *const u8 {
+    with_emulator(|emulator| emulator.pixels.as_ptr())
+}
+
+#[no_mangle]
+pub extern "C" fn display_width() -> usize {
+    with_emulator(|emulator| emulator.cpu.display_size().0)
+}
+
+#[no_mangle]
+pub extern "C" fn display_height() -> usize {
+    with_emulator(|emulator| emulator.cpu.display_size().1)
+}
+
+/// Whether the beeper sounds
+#[no_mangle]
+pub extern "C" fn sound_active() -> bool {
+    with_emulator(|emulator| emulator.cpu.sound_timer() > 0)
+}
+
+/// Whether the CPU stopped on an invalid instruction
+#[no_mangle]
+pub extern "C" fn has_panicked() -> bool {
+    with_emulator(|emulator| emulator.cpu.panic_message().is_some())
+}
@@ -0,0 +1,152 @@
+// Browser and Node frontend of the WebAssembly build, see index.html and test.mjs
+
+// Computer keys of the CHIP-8 keypad, the same layout as the emulator:
+// 1 2 3 4 / Q W E R / A S D F / ` Z X C
+const KEYS = {
+  Digit1: 0x1, Digit2: 0x2, Digit3: 0x3, Digit4: 0xc,
+  KeyQ: 0x4, KeyW: 0x5, KeyE: 0x6, KeyR: 0xd,
+  KeyA: 0x7, KeyS: 0x8, KeyD: 0x9, KeyF: 0xe,
+  Backquote: 0xa, KeyZ: 0x0, KeyX: 0xb, KeyC: 0xf,
+};
+
+const FRAME_DURATION = 1000 / 60;
+// Frames emulated at most per animation frame, after the tab was in the background
+const MAX_FRAMES_PER_UPDATE = 15;
+
+// Instantiate the core from the URL of chip8.wasm or its bytes
+export async function loadCore(wasm) {
+  const source = wasm instanceof ArrayBuffer || ArrayBuffer.isView(wasm)
+    ? wasm
+    : await (await fetch(wasm)).arrayBuffer();
+  const { instance } = await WebAssembly.instantiate(source, {});
+  return new Chip8(instance.exports);
+}
+
+// Thin wrapper of the functions exported by src/wasm.rs
+export class Chip8 {
+  constructor(exports) {
+    this.exports = exports;
+  }
+
+  // Start a ROM, `seed` makes the random numbers reproducible
+  loadRom(bytes, seed = Date.now()) {
+    const { exports } = this;
+    if (bytes.length > exports.rom_buffer_size()) {
+      throw new Error(`ROM is too large: ${bytes.length} bytes`);
+    }
+    new Uint8Array(exports.memory.buffer, exports.rom_buffer(), bytes.length).set(bytes);
+    exports.seed(seed >>> 0);
+    exports.load_rom(bytes.length);
+  }
+
+  reset() {
+    this.exports.reset();
+  }
+
+  runFrame() {
+    this.exports.run_frame();
+  }
+
+  keyDown(key) {
+    this.exports.key_down(key);
+  }
+
+  keyUp(key) {
+    this.exports.key_up(key);
+  }
+
+  // The display with one byte per pixel, 1 for set pixels. `data` is a view of the
+  // wasm memory, it changes with the next frame.
+  display() {
+    const { exports } = this;
+    const width = exports.display_width();
+    const height = exports.display_height();
+    const data = new Uint8Array(exports.memory.buffer, exports.framebuffer(), width * height);
+    return { width, height, data };
+  }
+
+  get soundActive() {
+    return Boolean(this.exports.sound_active());
+  }
+
+  get hasPanicked() {
+    return Boolean(this.exports.has_panicked());
+  }
+}
+
+// Play a ROM in a canvas with keyboard input and a beeper. The canvas shows the
+// display in its native resolution, scale it with CSS.
+export async function play(canvas, romUrl, wasmUrl = new URL('chip8.wasm', import.meta.url)) {
+  const chip8 = await loadCore(wasmUrl);
+  chip8.loadRom(new Uint8Array(await (await fetch(romUrl)).arrayBuffer()));
+
+  const context = canvas.getContext('2d');
+  const beeper = new Beeper();
+  canvas.tabIndex = 0;
+  canvas.addEventListener('keydown', (event) => {
+    if (event.code in KEYS) {
+      chip8.keyDown(KEYS[event.code]);
+      beeper.resume();
+      event.preventDefault();
+    }
+  });
+  canvas.addEventListener('keyup', (event) => {
+    if (event.code in KEYS) {
+      chip8.keyUp(KEYS[event.code]);
+      event.preventDefault();
+    }
+  });
+
+  let lastTime = performance.now();
+  let due = 0;
+  const update = (time) => {
+    due = Math.min(due + (time - lastTime) / FRAME_DURATION, MAX_FRAMES_PER_UPDATE);
+    lastTime = time;
+    for (; due >= 1; due--) {
+      chip8.runFrame();
+    }
+    draw(context, canvas, chip8.display());
+    beeper.set(chip8.soundActive);
+    requestAnimationFrame(update);
+  };
+  requestAnimationFrame(update);
+  return chip8;
+}
+
+function draw(context, canvas, { width, height, data }) {
+  if (canvas.width !== width || canvas.height !== height) {
+    canvas.width = width;
+    canvas.height = height;
+  }
+  const image = context.createImageData(width, height);
+  for (let i = 0; i < data.length; i++) {
+    const value = data[i] ? 0xff : 0x00;
+    image.data.set([value, value, value, 0xff], i * 4);
+  }
+  context.putImageData(image, 0, 0);
+}
+
+// Square wave while the sound timer runs. Browsers only allow audio after an input
+// event, so it starts with the first key press.
+class Beeper {
+  resume() {
+    if (!this.audio) {
+      this.audio = new AudioContext();
+      this.gain = this.audio.createGain();
+      this.gain.gain.value = 0;
+      this.gain.connect(this.audio.destination);
+      const oscillator = this.audio.createOscillator();
+      oscillator.type = 'square';
+      oscillator.frequency.value = 440;
+      oscillator.connect(this.gain);
+      oscillator.start();
+    }
+    this.audio.resume();
+  }
+
+  set(active) {
+    if (this.gain) {
+      this.gain.gain.value = active ? 0.1 : 0;
+    }
+  }
+}
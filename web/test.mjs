@@ -0,0 +1,28 @@
+// Runs ROMs in the WebAssembly build without a browser:
+// node web/test.mjs [target/wasm32-unknown-unknown/release/chip8.wasm]
+import assert from 'node:assert/strict';
+import { readFile } from 'node:fs/promises';
+
+import { loadCore } from './chip8.js';
+
+const wasmPath = process.argv[2] ?? 'target/wasm32-unknown-unknown/release/chip8.wasm';
+const chip8 = await loadCore(await readFile(wasmPath));
+
+// Set pixels after 60 frames, the same as in the native build
+const expected = {
+  'test-programs/IBM Logo.ch8': 208,
+  'test-programs/3-corax+.ch8': 501,
+  'test-programs/4-flags.ch8': 493,
+};
+
+for (const [path, pixels] of Object.entries(expected)) {
+  chip8.loadRom(await readFile(path), 1);
+  for (let frame = 0; frame < 60; frame++) {
+    chip8.runFrame();
+  }
+  const { width, height, data } = chip8.display();
+  assert.equal(chip8.hasPanicked, false, `${path} panicked`);
+  assert.deepEqual([width, height], [64, 32]);
+  assert.equal(data.reduce((sum, pixel) => sum + pixel, 0), pixels, `pixels of ${path}`);
+  console.log(`ok ${path}`);
+}